@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::cli::MetaArgs;
+use crate::cmd::{self, NewOpts};
+use crate::config::Config;
+use crate::gitx;
+use crate::lock;
+use crate::registry;
+use crate::state::{Allocation, State};
+use crate::ui::{Event, Logger};
+use crate::worktree;
+
+/// Why an operation failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The path is not inside a git repository (or git is not available).
+    NotARepo(String),
+    UnknownWorktree(String),
+    AlreadyExists(String),
+    /// The name belongs to an archived worktree (`wrt restore` brings it back).
+    Archived(String),
+    /// The operation rejected its arguments (a malformed label, an unknown sparse profile, ...).
+    Invalid(String),
+    /// No `profiles` entry of the config has this name.
+    UnknownProfile(String),
+    /// Creating would make the repo track more worktrees than allowed.
+    LimitReached {
+        existing: usize,
+        max: usize,
+    },
+    /// Another wrt process held the state lock for too long; nothing was changed.
+    Locked(String),
+    /// A git command failed (`git worktree add`, `git branch -D`, ...).
+    Git(String),
+    /// Removing would lose uncommitted changes, unpushed commits or stashes; nothing was removed.
+    WouldLoseWork {
+        name: String,
+        at_risk: Vec<String>,
+    },
+    /// The operation failed part way; `events` is everything it reported.
+    Failed {
+        message: String,
+        events: Vec<Event>,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotARepo(e) => write!(f, "not a git repo (or git not available): {e}"),
+            Error::UnknownWorktree(name) => write!(f, "unknown worktree: \"{name}\""),
+            Error::AlreadyExists(name) => write!(f, "worktree \"{name}\" already exists"),
+            Error::Archived(name) => write!(f, "worktree \"{name}\" is archived"),
+            Error::UnknownProfile(name) => write!(f, "unknown profile: \"{name}\""),
+            Error::LimitReached { existing, max } => write!(
+                f,
+                "limit reached: {existing} worktrees exist and at most {max} are allowed; remove one first"
+            ),
+            Error::Invalid(msg)
+            | Error::Locked(msg)
+            | Error::Git(msg)
+            | Error::Failed { message: msg, .. } => write!(f, "{msg}"),
+            Error::WouldLoseWork { name, at_risk } => {
+                write!(f, "removing \"{name}\" would lose: {}", at_risk.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    // exit_code is what the `wrt` command exits with for this error: 2 for usage errors.
+    pub(crate) fn exit_code(&self) -> i32 {
+        match self {
+            Error::UnknownWorktree(_)
+            | Error::AlreadyExists(_)
+            | Error::Archived(_)
+            | Error::Invalid(_)
+            | Error::UnknownProfile(_)
+            | Error::LimitReached { .. } => 2,
+            _ => 1,
+        }
+    }
+
+    pub(crate) fn failed(message: impl Into<String>) -> Error {
+        Error::Failed {
+            message: message.into(),
+            events: Vec::new(),
+        }
+    }
+
+    // with_events attaches what the operation logged to a failure.
+    fn with_events(self, log: &Logger) -> Error {
+        match self {
+            Error::Failed { message, .. } => Error::Failed {
+                message,
+                events: log.captured(),
+            },
+            e => e,
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Error {
+        if e.downcast_ref::<lock::TimedOut>().is_some() {
+            return Error::Locked(e.to_string());
+        }
+        Error::failed(format!("{e:#}"))
+    }
+}
+
+/// Whether a setup step runs: `Auto` decides from what the worktree contains (a lockfile, a
+/// supabase config, ...), as `wrt new` does by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl Mode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Mode::Auto => "auto",
+            Mode::Always => "true",
+            Mode::Never => "false",
+        }
+    }
+}
+
+/// What `Wrt::create` makes; the fields mirror `wrt new`'s flags.
+#[derive(Clone, Debug, Default)]
+pub struct CreateOpts {
+    /// Worktree name; slugged for the directory (`.worktrees/<name>`). May be empty with `pr`.
+    pub name: String,
+    /// A `profiles` entry of the config (default: its `default_profile`); the other fields win
+    /// over the profile's settings.
+    pub profile: Option<String>,
+    /// Ref to branch from (default: the profile's, else HEAD).
+    pub from_ref: Option<String>,
+    /// Branch name (default: the name, or `pr/<n>`).
+    pub branch: Option<String>,
+    /// Whether to install dependencies (default: the profile's, else `Auto`).
+    pub install: Option<Mode>,
+    pub supabase: Option<Mode>,
+    /// DB reset/seed after setup; `Auto` never prompts here, so it only runs with `Always`.
+    pub db: Option<Mode>,
+    /// Cone-mode sparse-checkout paths (empty: full checkout).
+    pub sparse: Vec<String>,
+    /// A `sparse_profiles` entry of the config, combined with `sparse`.
+    pub sparse_profile: Option<String>,
+    /// Check out this pull/merge request.
+    pub pr: Option<u64>,
+    pub remote: Option<String>,
+    pub no_fetch: bool,
+    pub labels: BTreeMap<String, String>,
+    pub note: Option<String>,
+    pub owner: Option<String>,
+    pub agent: Option<String>,
+    pub task_url: Option<String>,
+    /// Let `wrt gc` remove the worktree after this long (e.g. "2d").
+    pub ttl: Option<String>,
+    /// Keep the worktree (marked `failed`) when a setup step fails, instead of rolling it back.
+    pub keep_on_failure: bool,
+}
+
+/// How `Wrt::remove` treats work that would be lost.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RemoveOpts {
+    /// Remove even if uncommitted changes, unpushed commits or stashes would be lost.
+    pub force: bool,
+    /// Also delete the branch.
+    pub delete_branch: bool,
+    /// Save the branch (and uncommitted changes) under refs/wrt-archive/ first.
+    pub backup: bool,
+    /// With `backup`, also push those refs to the remote (if there is one).
+    pub push: bool,
+}
+
+/// A repo's worktrees, with the same state, config and locking as the `wrt` CLI; both can be
+/// used on the same repo at once.
+pub struct Wrt {
+    repo: gitx::Repo,
+    cfg: Config,
+    st: State,
+    log: Logger,
+}
+
+impl Wrt {
+    /// Opens the repo containing `path` (any of its worktrees will do).
+    pub fn open(path: impl AsRef<Path>) -> Result<Wrt, Error> {
+        let repo =
+            gitx::detect_repo(path.as_ref()).map_err(|e| Error::NotARepo(format!("{e:#}")))?;
+        let _ = gitx::ensure_info_exclude(&repo.common_dir, gitx::WRT_EXCLUDES);
+        let cfg = Config::load(&repo.common_dir)?;
+        let mut st = State::load(&repo.common_dir)?;
+        if cfg.registry == Some(true) {
+            st.link_registry(registry::Link::for_repo(&repo)?)?;
+        }
+        Ok(Wrt {
+            repo,
+            cfg,
+            st,
+            log: Logger::to_sink(Arc::new(|_| {})),
+        })
+    }
+
+    /// Sends the progress of every operation to `sink` (by default it is dropped).
+    pub fn with_sink(mut self, sink: impl Fn(&Event) + Send + Sync + 'static) -> Wrt {
+        self.log = Logger::to_sink(Arc::new(sink));
+        self
+    }
+
+    /// The main workdir of the repo.
+    pub fn root(&self) -> &Path {
+        &self.repo.root
+    }
+
+    /// Creates a worktree: branch, port block, `.wrt.env`, and the setup steps of `opts`.
+    /// Commands it runs (git, the package manager, supabase) inherit stdout and stderr.
+    pub fn create(&mut self, opts: &CreateOpts) -> Result<Allocation, Error> {
+        let name = match (opts.name.trim(), opts.pr) {
+            ("", Some(pr)) => format!("pr-{pr}"),
+            ("", None) => return Err(Error::Invalid("missing name".into())),
+            (n, _) => n.to_string(),
+        };
+        let meta = MetaArgs {
+            label: opts
+                .labels
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect(),
+            note: opts.note.clone(),
+            owner: opts.owner.clone(),
+            agent: opts.agent.clone(),
+            task_url: opts.task_url.clone(),
+        };
+        let new = NewOpts {
+            name: &name,
+            profile: opts.profile.as_deref(),
+            from_ref: opts.from_ref.as_deref(),
+            branch: opts.branch.as_deref(),
+            install_mode: opts.install.map(Mode::as_str),
+            sb_mode: opts.supabase.map(Mode::as_str),
+            db_mode: opts.db.map(Mode::as_str),
+            sparse: &opts.sparse,
+            sparse_profile: opts.sparse_profile.as_deref(),
+            pr: opts.pr,
+            remote: opts.remote.as_deref(),
+            no_fetch: opts.no_fetch,
+            meta: &meta,
+            ttl: opts.ttl.as_deref(),
+            count: None,
+            keep_on_failure: opts.keep_on_failure,
+            jobs: 1,
+            json: false,
+            emit_cd: false,
+            interactive: false,
+            max_worktrees: None,
+        };
+        let log = self.log.capturing();
+        cmd::new_worktree(&log, &self.repo, &self.cfg, &mut self.st, &new)
+            .map_err(|e| e.with_events(&log))
+    }
+
+    /// Removes a worktree, its checkout and its services. Without `force` or `backup` it fails
+    /// with `Error::WouldLoseWork` rather than lose anything.
+    pub fn remove(&mut self, name: &str, opts: &RemoveOpts) -> Result<(), Error> {
+        self.st.reload(&self.repo.common_dir)?;
+        let log = self.log.capturing();
+        cmd::remove_worktree(&log, &self.repo, &mut self.st, name, opts)
+            .map_err(|e| e.with_events(&log))
+    }
+
+    /// The tracked worktrees, by name.
+    pub fn list(&mut self) -> Result<Vec<Allocation>, Error> {
+        self.st.reload(&self.repo.common_dir)?;
+        Ok(self.st.sorted_allocations())
+    }
+
+    /// One worktree, by name (slugged like `wrt new` does).
+    pub fn get(&mut self, name: &str) -> Result<Allocation, Error> {
+        self.st.reload(&self.repo.common_dir)?;
+        let key = worktree::slug(name);
+        self.st
+            .allocations
+            .get(&key)
+            .cloned()
+            .ok_or(Error::UnknownWorktree(key))
+    }
+
+    /// The WRT_* variables of a worktree, as written to its `.wrt.env`.
+    pub fn env_for(&mut self, name: &str) -> Result<Vec<(String, String)>, Error> {
+        Ok(worktree::env_file_vars(&self.get(name)?))
+    }
+}
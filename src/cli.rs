@@ -1,23 +1,53 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 
 pub const USAGE_TEXT: &str = r#"wrt: git worktree helper geared for parallel (agentic) workflows
 
 Usage:
   wrt init [--force] [--print] [--model <codex-model>]
-  wrt new <name> [--from <ref>] [--branch <branch>] [--install auto|true|false] [--supabase auto|true|false] [--db auto|true|false] [--cd]
+  wrt new <name> [--profile <name>] [--from <ref> | --pr <number>] [--branch <branch>] [--install auto|true|false] [--supabase auto|true|false] [--db auto|true|false] [--sparse <path>...] [--sparse-profile <name>] [--remote <name>] [--no-fetch] [--label <k=v>]... [--note <text>] [--owner <who>] [--agent <who>] [--task-url <url>] [--ttl <duration>] [--keep-on-failure] [--count <n> [--jobs <n>] [--json]] [--cd]
+  wrt adopt [<path> [--name <name>] | --all] [--supabase auto|true|false]
+  wrt setup <name> [--steps env,files,supabase,install,db] [--from-step <step>] [--install auto|true|false] [--supabase auto|true|false] [--db auto|true|false]
+  wrt sparse <name> list|add|set|disable [<path>...]
   wrt db [<name>] reset|seed|migrate [--print]
-  wrt ls
+  wrt ls [--filter <expr>]... [--all-repos]
+  wrt status [--filter <expr>]... [--json]
+  wrt ui
+  wrt proxy [--port <n>] [--routes]
+  wrt mcp
+  wrt tag <name> [--label <k=v>]... [--unlabel <k>]... [--note <text>] [--owner <who>] [--agent <who>] [--task-url <url>]
   wrt path <name>
+  wrt cd [<name>]
   wrt env [<name>]
-  wrt rm <name> [--force] [--delete-branch]
+  wrt rm <name> [--force] [--delete-branch] [--backup]
+  wrt mv <old> <new> [--branch [<branch>]]
+  wrt archive <name>
+  wrt restore <name>
+  wrt compare <group> [--check <command> | --no-check] [--json]
+  wrt pick <name> [--into <branch>] [--mode merge|squash|cherry-pick] [--others keep|archive|rm]
+  wrt du [--sort total|name|checkout|node_modules|build|volumes] [--json]
   wrt prune
+  wrt gc [--older-than <duration>] [--merged] [--dry-run]
+  wrt doctor [--fix]
   wrt run <name> -- <command> [args...]
-  wrt completions zsh
+  wrt foreach [--filter <expr>]... -- <command> [args...]
+  wrt completions bash|zsh|fish|nushell|powershell
+  wrt shell-init bash|zsh|fish
+  wrt prompt [--format <fmt>]
 
 Conventions:
   - Worktrees live under: <repo>/.worktrees/<name>
   - Each worktree gets a reserved "port block" (offset = block*100); block 0 is kept for the main workdir.
   - If a Supabase config exists (supabase/config.toml), wrt can patch it to avoid port/container collisions.
+  - `wrt new --count <n>` (alias `wrt spawn`) creates <name>-1..<n> from one base, recorded as group=<name>.
+  - Config `profiles` bundle `wrt new` settings (`--profile <name>`, or `default_profile`); flags win over the profile.
+  - Provisioning steps (env, files, supabase, install, db) that completed are recorded per worktree; `wrt setup` re-runs them.
+  - If a step of `wrt new` fails, what it created (worktree, new branch, state entry) is rolled back; --keep-on-failure keeps it, marked failed.
+  - With "registry": true in config, port blocks are unique across all repos on the machine (~/.local/state/wrt/registry.json).
+  - With "isolation": "loopback" in config, each worktree gets its own address 127.x.y.1 (WRT_HOST) and keeps the default ports.
+  - `wrt proxy` routes http://<name>.<service>.localhost:8080 to the services in .wrt.json (exported as WRT_URL_<SERVICE>).
+  - Filters: label=<k>, label=<k>=<v>, name|branch|group|owner|agent|note|task_url=<v> (a trailing * matches a prefix).
+  - `eval "$(wrt shell-init zsh)"` adds completions and a `wrt` function: `wrt cd <name>` (fuzzy), cd after `wrt new`, WRT_* env loaded inside worktrees.
+  - Completions are computed by `wrt __complete` from these definitions plus the repo's worktrees, groups and profiles.
   - If DB reset/seed commands are discovered (via .wrt.json), wrt can optionally run them after setup.
 "#;
 
@@ -31,6 +61,7 @@ pub struct Cli {
 }
 
 #[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum Cmd {
     /// Print usage
     Help,
@@ -46,77 +77,378 @@ pub enum Cmd {
     },
 
     /// Create a new worktree (+branch), optionally install deps and start supabase
+    #[command(alias = "spawn")]
     New {
-        name: String,
-        #[arg(long, default_value = "HEAD")]
-        from: String,
+        /// Worktree name (defaults to `pr-<number>` with --pr)
+        #[arg(required_unless_present = "pr")]
+        name: Option<String>,
+        /// Use a named set of settings from config (`profiles`; default: `default_profile`)
+        #[arg(long, value_name = "PROFILE")]
+        profile: Option<String>,
+        /// Ref to branch from (default: the profile's, else HEAD)
+        #[arg(long)]
+        from: Option<String>,
         #[arg(long)]
         branch: Option<String>,
-        #[arg(long, default_value = "auto")]
-        install: String,
-        #[arg(long, default_value = "auto")]
-        supabase: String,
-        #[arg(long, default_value = "auto")]
-        db: String,
-        /// Print a `cd <path>` snippet to stdout after creation (use with `eval "$(wrt new ... --cd)"`)
+        /// Check out a GitHub pull request / GitLab merge request head (branch defaults to `pr/<number>`)
+        #[arg(long, value_name = "NUMBER", conflicts_with = "from")]
+        pr: Option<u64>,
+        /// Remote to fetch from and track (default: config `remote`, else origin)
+        #[arg(long, value_name = "NAME")]
+        remote: Option<String>,
+        /// Don't fetch; use the refs already present locally
+        #[arg(long = "no-fetch")]
+        no_fetch: bool,
+        /// auto|true|false (default: the profile's, else auto)
+        #[arg(long, value_name = "MODE")]
+        install: Option<String>,
+        #[arg(long, value_name = "MODE")]
+        supabase: Option<String>,
+        #[arg(long, value_name = "MODE")]
+        db: Option<String>,
+        /// Only materialize these directories (cone-mode sparse-checkout)
+        #[arg(long, value_name = "PATH", num_args = 1..)]
+        sparse: Vec<String>,
+        /// Use a named set of sparse paths from config (`sparse_profiles`)
+        #[arg(long = "sparse-profile", value_name = "SPARSE_PROFILE")]
+        sparse_profile: Option<String>,
+        #[command(flatten)]
+        meta: MetaArgs,
+        /// Let `wrt gc` remove the worktree after this long (e.g. 12h, 2d, 1w)
+        #[arg(long, value_name = "DURATION")]
+        ttl: Option<String>,
+        /// Create N worktrees `<name>-1..N` from the same base, grouped under `<name>`
+        #[arg(long, value_name = "N")]
+        count: Option<usize>,
+        /// Keep a worktree whose setup failed (marked `failed` in `wrt ls`) instead of rolling it back
         #[arg(long)]
+        keep_on_failure: bool,
+        /// With --count: how many worktrees run install/DB setup at once
+        #[arg(long, value_name = "N", default_value_t = 4, requires = "count")]
+        jobs: usize,
+        /// With --count: print the summary as JSON
+        #[arg(long, requires = "count")]
+        json: bool,
+        /// Print a `cd <path>` snippet to stdout after creation (use with `eval "$(wrt new ... --cd)"`)
+        #[arg(long, conflicts_with = "count")]
         cd: bool,
     },
 
+    /// Start tracking worktrees created outside wrt (e.g. plain `git worktree add`)
+    Adopt {
+        /// Worktree path (defaults to the current directory)
+        #[arg(conflicts_with = "all")]
+        path: Option<String>,
+        /// Adopt every linked worktree that wrt doesn't track yet
+        #[arg(long)]
+        all: bool,
+        /// Name to track the worktree under (defaults to the directory name)
+        #[arg(long, conflicts_with = "all")]
+        name: Option<String>,
+        #[arg(long, value_name = "MODE", default_value = "auto")]
+        supabase: String,
+    },
+
+    /// Re-run provisioning steps (env, files, supabase, install, db) on an existing worktree
+    Setup {
+        #[arg(value_name = "WORKTREE")]
+        name: String,
+        /// Only these steps (comma-separated; default: all, in order)
+        #[arg(long, value_name = "STEPS", value_delimiter = ',', value_parser = ["env", "files", "supabase", "install", "db"])]
+        steps: Vec<String>,
+        /// Skip the steps before this one (e.g. `--from-step install` after a failed install)
+        #[arg(long = "from-step", value_name = "STEP", value_parser = ["env", "files", "supabase", "install", "db"])]
+        from_step: Option<String>,
+        /// auto|true|false (default: the worktree's profile's, else auto)
+        #[arg(long, value_name = "MODE")]
+        install: Option<String>,
+        #[arg(long, value_name = "MODE")]
+        supabase: Option<String>,
+        #[arg(long, value_name = "MODE")]
+        db: Option<String>,
+    },
+
+    /// Show or adjust the sparse-checkout paths of a worktree
+    Sparse {
+        #[arg(value_name = "WORKTREE")]
+        name: String,
+        #[command(subcommand)]
+        action: SparseAction,
+    },
+
     /// Run database utilities for a worktree (reset/seed/migrate)
     Db {
         /// Worktree name (optional if run from inside a worktree directory)
-        #[arg(value_name = "NAME")]
+        #[arg(value_name = "WORKTREE")]
         name: Option<String>,
         /// Explicit worktree name (useful if the name conflicts with a subcommand like "reset")
-        #[arg(long, value_name = "NAME")]
+        #[arg(long, value_name = "WORKTREE")]
         worktree: Option<String>,
         #[command(subcommand)]
         action: DbAction,
     },
 
     /// List tracked worktrees
-    Ls,
+    Ls {
+        /// Only show worktrees matching all filters (e.g. label=agent=gpt)
+        #[arg(long, value_name = "EXPR")]
+        filter: Vec<String>,
+        /// List the worktrees of every repo in the machine-wide registry
+        #[arg(long = "all-repos")]
+        all_repos: bool,
+    },
     /// Alias for ls
-    List,
+    List {
+        #[arg(long, value_name = "EXPR")]
+        filter: Vec<String>,
+        #[arg(long = "all-repos")]
+        all_repos: bool,
+    },
+
+    /// Per-worktree dashboard: ahead/behind, last commit, merge conflicts, services
+    Status {
+        #[arg(long, value_name = "EXPR")]
+        filter: Vec<String>,
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Full-screen terminal UI: live status, shell/editor/diff, services, archive/remove
+    Ui,
+
+    /// Serve http://<name>.<service>.localhost:<port>, routed to each worktree's service port
+    Proxy {
+        /// Port to listen on (default: config `proxy_port`, else 8080)
+        #[arg(long)]
+        port: Option<u16>,
+        /// Print the routing table and exit
+        #[arg(long)]
+        routes: bool,
+    },
+
+    /// Serve create/list/env/run/db_reset/remove/status as MCP tools over stdio (for coding agents)
+    Mcp,
+
+    /// Show or change a worktree's labels, note, owner, agent and task URL
+    Tag {
+        #[arg(value_name = "WORKTREE")]
+        name: String,
+        #[command(flatten)]
+        meta: MetaArgs,
+        /// Remove a label
+        #[arg(long, value_name = "KEY")]
+        unlabel: Vec<String>,
+    },
 
     /// Print worktree path
-    Path { name: String },
+    Path {
+        #[arg(value_name = "WORKTREE")]
+        name: String,
+    },
+
+    /// Print the path of a worktree, matched fuzzily (the main workdir without a name); with
+    /// `wrt shell-init`, change into it
+    Cd {
+        #[arg(value_name = "WORKTREE")]
+        name: Option<String>,
+    },
 
     /// Print exports for the current worktree (or pass a name)
-    Env { name: Option<String> },
+    Env {
+        #[arg(value_name = "WORKTREE")]
+        name: Option<String>,
+    },
 
     /// Remove a worktree
     Rm {
+        #[arg(value_name = "WORKTREE")]
         name: String,
+        /// Remove even if uncommitted changes or unpushed commits would be lost
         #[arg(long)]
         force: bool,
         #[arg(long = "delete-branch")]
         delete_branch: bool,
+        /// Save the branch (and uncommitted changes) under refs/wrt-archive/ first, pushed to the remote if any
+        #[arg(long)]
+        backup: bool,
     },
     /// Alias for rm
     Remove {
+        #[arg(value_name = "WORKTREE")]
         name: String,
+        /// Remove even if uncommitted changes or unpushed commits would be lost
         #[arg(long)]
         force: bool,
         #[arg(long = "delete-branch")]
         delete_branch: bool,
+        /// Save the branch (and uncommitted changes) under refs/wrt-archive/ first, pushed to the remote if any
+        #[arg(long)]
+        backup: bool,
+    },
+
+    /// Rename a worktree (directory, state, .wrt.env, supabase project_id)
+    Mv {
+        #[arg(value_name = "WORKTREE")]
+        old: String,
+        new: String,
+        /// Also rename the branch (to <new>, or to the given name)
+        #[arg(long, value_name = "BRANCH", num_args = 0..=1, default_missing_value = "")]
+        branch: Option<String>,
+    },
+
+    /// Park a worktree: save uncommitted changes to a ref, stop services, remove the checkout
+    Archive {
+        #[arg(value_name = "WORKTREE")]
+        name: String,
+    },
+    /// Recreate an archived worktree with its uncommitted changes
+    Restore {
+        #[arg(value_name = "ARCHIVED")]
+        name: String,
+    },
+
+    /// Compare the worktrees of a group: diff stats against the base, check results, similarity
+    Compare {
+        #[arg(value_name = "GROUP")]
+        group: String,
+        /// Command to run in each worktree (default: config `check`)
+        #[arg(long, value_name = "COMMAND")]
+        check: Option<String>,
+        /// Don't run the check command
+        #[arg(long = "no-check", conflicts_with = "check")]
+        no_check: bool,
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Bring a worktree's branch into the target branch; optionally archive or remove the rest of its group
+    Pick {
+        #[arg(value_name = "WORKTREE")]
+        name: String,
+        /// Branch to pick into (default: the ref the worktree was created from)
+        #[arg(long, value_name = "BRANCH")]
+        into: Option<String>,
+        #[arg(long, default_value = "merge", value_parser = ["merge", "squash", "cherry-pick"])]
+        mode: String,
+        /// What to do with the other worktrees of the group
+        #[arg(long, default_value = "keep", value_parser = ["keep", "archive", "rm"])]
+        others: String,
+    },
+
+    /// Show disk usage per worktree (checkout, node_modules, build output, docker volumes)
+    Du {
+        /// Sort by this column (sizes sort largest first)
+        #[arg(long, default_value = "total", value_parser = ["total", "name", "checkout", "node_modules", "build", "volumes"])]
+        sort: String,
+        #[arg(long)]
+        json: bool,
     },
 
     /// Prune git worktrees and state
     Prune,
+    /// Remove idle, expired or merged worktrees (never ones with uncommitted changes)
+    Gc {
+        /// Remove worktrees with no commits or file changes for this long (e.g. 7d, 12h)
+        #[arg(long = "older-than", value_name = "DURATION")]
+        older_than: Option<String>,
+        /// Remove worktrees whose branch is merged into the default branch (and the branch)
+        #[arg(long)]
+        merged: bool,
+        /// Only print what would be removed
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+    },
+    /// Cross-check state.json against git, .wrt.env and supabase config
+    Doctor {
+        /// Apply safe repairs (state, .wrt.env, supabase patch, excludes, git worktree prune)
+        #[arg(long)]
+        fix: bool,
+    },
     /// Run a command inside a worktree with WRT_* env vars set
     ///
     /// Must be invoked as: wrt run <name> -- <command> [args...]
     #[command(trailing_var_arg = true)]
     Run {
+        #[arg(value_name = "WORKTREE")]
         name: String,
         #[arg(required = true, value_name = "COMMAND", num_args = 1.., allow_hyphen_values = true)]
         command: Vec<String>,
     },
 
-    /// Generate shell completions (currently zsh only)
-    Completions { shell: String },
+    /// Run a command in every (matching) worktree with WRT_* env vars set
+    ///
+    /// Must be invoked as: wrt foreach [--filter <expr>]... -- <command> [args...]
+    Foreach {
+        #[arg(long, value_name = "EXPR")]
+        filter: Vec<String>,
+        #[arg(required = true, value_name = "COMMAND", last = true)]
+        command: Vec<String>,
+    },
+
+    /// Generate shell completions (bash, zsh, fish, nushell, powershell)
+    Completions {
+        #[arg(value_parser = crate::completions::SHELLS, ignore_case = true)]
+        shell: String,
+    },
+
+    /// Print shell integration: completions, `wrt cd`, auto-cd after `wrt new`, worktree env on entry
+    #[command(name = "shell-init")]
+    ShellInit {
+        #[arg(value_parser = crate::shell::SHELLS, ignore_case = true)]
+        shell: String,
+    },
+
+    /// Print a prompt segment (name and port offset) for the worktree of the current directory
+    Prompt {
+        /// Placeholders: {name} {branch} {offset} {block} {host} (default: {name}+{offset}, or
+        /// {name}@{host} with loopback isolation)
+        #[arg(long, value_name = "FORMAT")]
+        format: Option<String>,
+    },
+
+    /// Print completion candidates for the words after `wrt` (used by the completion scripts)
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        /// Index of the word under the cursor (default: the last one)
+        #[arg(long)]
+        index: Option<usize>,
+        #[arg(last = true)]
+        words: Vec<String>,
+    },
+}
+
+/// Metadata shared by `wrt new` and `wrt tag`. An empty value clears a field.
+#[derive(Args, Debug, Clone, Default)]
+pub struct MetaArgs {
+    /// Attach a label (repeatable)
+    #[arg(long, value_name = "K=V")]
+    pub label: Vec<String>,
+    #[arg(long, value_name = "TEXT")]
+    pub note: Option<String>,
+    #[arg(long, value_name = "WHO")]
+    pub owner: Option<String>,
+    #[arg(long, value_name = "WHO")]
+    pub agent: Option<String>,
+    #[arg(long = "task-url", value_name = "URL")]
+    pub task_url: Option<String>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum SparseAction {
+    /// Print the sparse-checkout paths
+    List,
+    /// Add paths to the sparse-checkout (enables it if needed)
+    Add {
+        #[arg(required = true, value_name = "PATH")]
+        paths: Vec<String>,
+    },
+    /// Replace the sparse-checkout paths
+    Set {
+        #[arg(required = true, value_name = "PATH")]
+        paths: Vec<String>,
+    },
+    /// Disable sparse-checkout and materialize the full tree
+    Disable,
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -0,0 +1,228 @@
+use anyhow::Result;
+use chrono::SecondsFormat;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::config::Isolation;
+use crate::gitx;
+use crate::proxy;
+use crate::state::{Allocation, State};
+use crate::supabase;
+use crate::ui;
+use crate::util::run_cmd;
+use crate::worktree::{self, GitWorktree};
+
+pub struct AdoptOpts<'a> {
+    pub path: Option<&'a str>,
+    pub all: bool,
+    pub name: Option<&'a str>,
+    pub sb_mode: &'a str,
+    pub isolation: Isolation,
+    /// Port of `wrt proxy`, for the WRT_URL_<SERVICE> variables.
+    pub proxy_port: u16,
+}
+
+pub fn cmd_adopt(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    st: &mut State,
+    opts: AdoptOpts<'_>,
+) -> Result<i32> {
+    // Held until the last worktree is saved, like `wrt new`: recovered and allocated blocks must
+    // stay free meanwhile.
+    let _lock = match State::lock(&repo.common_dir) {
+        Ok(l) => l,
+        Err(e) => {
+            log.errorf(&format!("{e}"));
+            return Ok(1);
+        }
+    };
+    if let Err(e) = st.reload(&repo.common_dir) {
+        log.errorf(&format!("state load failed: {e}"));
+        return Ok(1);
+    }
+
+    let wts = match worktree::list(&repo.root) {
+        Ok(w) => w,
+        Err(e) => {
+            log.errorf(&format!("{e}"));
+            return Ok(1);
+        }
+    };
+    // The first entry is the main worktree (block 0); it is never adopted.
+    let linked: Vec<&GitWorktree> = wts.iter().skip(1).filter(|w| !w.bare).collect();
+
+    let candidates: Vec<&GitWorktree> = if opts.all {
+        linked
+            .into_iter()
+            .filter(|w| tracked_name(st, &w.path).is_none() && !w.prunable)
+            .collect()
+    } else {
+        let target = match opts.path {
+            Some(p) => PathBuf::from(p),
+            None => env::current_dir()?,
+        };
+        let Some(wt) = linked
+            .into_iter()
+            .find(|w| worktree::same_path(&w.path, &target))
+        else {
+            log.errorf(&format!(
+                "{} is not a linked git worktree of this repo (see `git worktree list`)",
+                target.display()
+            ));
+            return Ok(2);
+        };
+        if let Some(name) = tracked_name(st, &wt.path) {
+            log.infof(&format!("already tracked as \"{name}\""));
+            return Ok(0);
+        }
+        vec![wt]
+    };
+
+    if candidates.is_empty() {
+        log.infof("no untracked worktrees found");
+        return Ok(0);
+    }
+
+    let mut failed = 0;
+    for wt in candidates {
+        match adopt_one(log, repo, st, wt, &opts) {
+            Ok(true) => {}
+            Ok(false) => failed += 1,
+            Err(e) => {
+                log.errorf(&format!("adopt {}: {e}", wt.path.display()));
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Ok(1);
+    }
+    Ok(0)
+}
+
+fn tracked_name(st: &State, path: &Path) -> Option<String> {
+    st.allocations
+        .values()
+        .find(|a| worktree::same_path(Path::new(&a.path), path))
+        .map(|a| a.name.clone())
+}
+
+fn adopt_one(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    st: &mut State,
+    wt: &GitWorktree,
+    opts: &AdoptOpts<'_>,
+) -> Result<bool> {
+    let (sb, isolation) = (opts.sb_mode.trim().to_lowercase(), opts.isolation);
+    let Some(branch) = wt.branch.clone() else {
+        log.errorf(&format!(
+            "{}: detached HEAD; check out a branch first",
+            wt.path.display()
+        ));
+        return Ok(false);
+    };
+
+    let base = opts.name.map(str::to_string).unwrap_or_else(|| {
+        wt.path
+            .file_name()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default()
+    });
+    let wt_name = worktree::slug(&base);
+    if st.allocations.contains_key(&wt_name) {
+        log.errorf(&format!(
+            "{}: name \"{wt_name}\" is already tracked; pass `--name`",
+            wt.path.display()
+        ));
+        return Ok(false);
+    }
+
+    let use_sb = sb == "true" || (sb == "auto" && supabase::has_config(&wt.path));
+
+    // A config already patched for this name tells us which block the worktree had (port offsets
+    // only; loopback patches don't shift ports).
+    let recovered = if use_sb && supabase::has_config(&wt.path) {
+        supabase::patched_offset(&wt.path, &wt_name).unwrap_or(None)
+    } else {
+        None
+    };
+    let recovered_block = recovered
+        .filter(|off| isolation == Isolation::Ports && *off > 0 && off % 100 == 0)
+        .map(|off| off / 100)
+        .filter(|b| !st.block_in_use(*b));
+    // Another repo could take it before this one publishes; reserve it like a new block.
+    let recovered_block = match (recovered_block, &st.registry) {
+        (Some(b), Some(link)) => link.claim(b)?.then_some(b),
+        (b, _) => b,
+    };
+
+    let block = match recovered_block {
+        Some(b) => {
+            log.infof(&format!(
+                "{wt_name}: recovered port block {b} from supabase config"
+            ));
+            b
+        }
+        None => st.allocate_block()?,
+    };
+
+    let mut alloc = Allocation {
+        name: wt_name.clone(),
+        branch,
+        path: wt.path.to_string_lossy().to_string(),
+        created_at: chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        ..Default::default()
+    };
+    alloc.assign_block(block, isolation);
+    alloc.urls = proxy::urls(&wt_name, &proxy::services(&repo.root), opts.proxy_port);
+    let (offset, host) = (alloc.offset, alloc.host.as_deref());
+
+    worktree::write_env_file(&wt.path, &alloc)?;
+
+    if use_sb {
+        match recovered {
+            Some(off) if off == offset && host.is_none() => {}
+            // Re-patching starts from HEAD's config; don't throw away edits of the user's.
+            Some(_) if supabase::has_local_edits(&wt.path).unwrap_or(true) => {
+                log.warnf(&format!(
+                    "{wt_name}: supabase/config.toml has local edits; keeping it (re-patch it by hand for block {block})"
+                ));
+            }
+            Some(_) => {
+                // Patched for a block we can't reuse; start over from the committed config.
+                log.infof(&format!(
+                    "{wt_name}: re-patching supabase config for block {block}"
+                ));
+                supabase::restore_config(&wt.path)?;
+                supabase::patch_config(&wt.path, &wt_name, offset, host)?;
+            }
+            None => {
+                log.infof(&format!(
+                    "{wt_name}: patching supabase config for isolation (project_id + {})",
+                    if host.is_some() { "host" } else { "ports" }
+                ));
+                supabase::patch_config(&wt.path, &wt_name, offset, host)?;
+            }
+        }
+        let _ = run_cmd(
+            &wt.path,
+            "git",
+            &["update-index", "--skip-worktree", "supabase/config.toml"],
+        );
+    }
+
+    let isolated = match host {
+        Some(h) => format!("host={h}"),
+        None => format!("offset={offset}"),
+    };
+    log.infof(&format!(
+        "adopted {} as \"{wt_name}\" (block={block} {isolated})",
+        wt.path.display()
+    ));
+    st.allocations.insert(wt_name, alloc);
+    st.save(&repo.common_dir)?;
+    Ok(true)
+}
@@ -0,0 +1,187 @@
+use anyhow::Result;
+use chrono::SecondsFormat;
+use std::path::Path;
+
+use crate::gitx;
+use crate::services;
+use crate::state::{ArchivedAllocation, State};
+use crate::supabase;
+use crate::ui;
+use crate::util::run_cmd;
+use crate::worktree;
+
+pub fn cmd_archive(log: &ui::Logger, repo: &gitx::Repo, st: &mut State, name: &str) -> Result<i32> {
+    let key = worktree::slug(name);
+    let Some(a) = st.allocations.get(&key).cloned() else {
+        log.errorf(&format!("unknown worktree: \"{key}\""));
+        return Ok(2);
+    };
+    let wt_path = Path::new(&a.path);
+
+    let mut wip_ref: Option<String> = None;
+    let mut supabase_patched = false;
+    if wt_path.exists() {
+        let msg = format!("wrt: uncommitted changes of {}", a.name);
+        match worktree::snapshot(wt_path, &msg) {
+            Ok(Some(commit)) => {
+                let r = format!("{}/{}", worktree::WIP_REF_PREFIX, a.name);
+                if let Err(e) = worktree::update_ref(&repo.root, &r, &commit) {
+                    log.errorf(&format!("save uncommitted changes: {e}"));
+                    return Ok(1);
+                }
+                log.infof(&format!("saved uncommitted changes to {r}"));
+                wip_ref = Some(r);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                log.errorf(&format!("save uncommitted changes: {e}"));
+                return Ok(1);
+            }
+        }
+
+        supabase_patched = supabase::has_config(wt_path)
+            && matches!(supabase::patched_offset(wt_path, &a.name), Ok(Some(_)));
+
+        if let Err(e) = services::stop(log, wt_path) {
+            log.errorf(&format!("supabase stop failed: {e}"));
+            return Ok(1);
+        }
+
+        // Everything uncommitted is in the snapshot, so a forced remove loses nothing.
+        if let Err(e) = worktree::remove(&repo.root, wt_path, true) {
+            log.errorf(&format!("git worktree remove failed: {e}"));
+            return Ok(1);
+        }
+    }
+
+    log.infof(&format!(
+        "archived {} (branch {}, block {} released)",
+        a.name, a.branch, a.block
+    ));
+    let ar = ArchivedAllocation {
+        alloc: a,
+        archived_at: chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        wip_ref,
+        supabase_patched,
+    };
+    let saved = st.update(&repo.common_dir, |s| {
+        s.allocations.remove(&key);
+        s.archived.insert(key, ar);
+    });
+    if let Err(e) = saved {
+        log.errorf(&format!("state save failed: {e}"));
+        return Ok(1);
+    }
+    Ok(0)
+}
+
+pub fn cmd_restore(log: &ui::Logger, repo: &gitx::Repo, st: &mut State, name: &str) -> Result<i32> {
+    // Held until the state is saved, like `wrt new`: the block picked below must stay free.
+    let _lock = match State::lock(&repo.common_dir) {
+        Ok(l) => l,
+        Err(e) => {
+            log.errorf(&format!("{e}"));
+            return Ok(1);
+        }
+    };
+    if let Err(e) = st.reload(&repo.common_dir) {
+        log.errorf(&format!("state load failed: {e}"));
+        return Ok(1);
+    }
+
+    let key = worktree::slug(name);
+    let Some(ar) = st.archived.get(&key).cloned() else {
+        log.errorf(&format!("no archived worktree named \"{key}\""));
+        return Ok(2);
+    };
+    if st.allocations.contains_key(&key) {
+        log.errorf(&format!(
+            "worktree \"{key}\" already exists in state; use `wrt ls`"
+        ));
+        return Ok(2);
+    }
+
+    let mut a = ar.alloc.clone();
+    if st.block_in_use(a.block) {
+        let block = match st.allocate_block() {
+            Ok(b) => b,
+            Err(e) => {
+                log.errorf(&format!("allocate port block: {e}"));
+                return Ok(1);
+            }
+        };
+        log.infof(&format!(
+            "block {} is taken now; using block {block}",
+            a.block
+        ));
+        let isolation = a.isolation();
+        a.assign_block(block, isolation);
+    }
+
+    let wt_path = Path::new(&a.path).to_path_buf();
+    log.infof(&format!(
+        "restoring worktree: {} ({}) at {}",
+        a.name,
+        a.branch,
+        wt_path.display()
+    ));
+    if let Some(parent) = wt_path.parent() {
+        worktree::ensure_dir(parent)?;
+    }
+
+    let branch_ref = format!("refs/heads/{}", a.branch);
+    if !worktree::ref_exists(&repo.root, &branch_ref) {
+        log.errorf(&format!("branch {} no longer exists", a.branch));
+        return Ok(1);
+    }
+    let add_opts = worktree::AddOpts {
+        branch: &a.branch,
+        from_ref: "HEAD",
+        remote: None,
+        sparse: &a.sparse,
+    };
+    if let Err(e) = worktree::add(&repo.root, &wt_path, &add_opts) {
+        log.errorf(&format!("git worktree add failed: {e}"));
+        return Ok(1);
+    }
+
+    if let Some(r) = &ar.wip_ref {
+        if let Err(e) = worktree::apply_snapshot(&wt_path, r) {
+            log.errorf(&format!("restore uncommitted changes from {r}: {e}"));
+            return Ok(1);
+        }
+        log.infof(&format!("restored uncommitted changes from {r}"));
+    }
+
+    if let Err(e) = worktree::write_env_file(&wt_path, &a) {
+        log.errorf(&format!("write env file: {e}"));
+        return Ok(1);
+    }
+    match worktree::copy_repo_env(&repo.root, &wt_path) {
+        Ok(true) => log.infof("copied .env from repo root"),
+        Ok(false) => {}
+        Err(e) => log.infof(&format!("copy .env failed: {e}")),
+    }
+    if ar.supabase_patched && supabase::has_config(&wt_path) {
+        if let Err(e) = supabase::patch_config(&wt_path, &a.name, a.offset, a.host.as_deref()) {
+            log.errorf(&format!("supabase patch failed: {e}"));
+            return Ok(1);
+        }
+        let _ = run_cmd(
+            &wt_path,
+            "git",
+            &["update-index", "--skip-worktree", "supabase/config.toml"],
+        );
+    }
+
+    st.archived.remove(&key);
+    st.allocations.insert(key, a);
+    if let Err(e) = st.save(&repo.common_dir) {
+        log.errorf(&format!("state save failed: {e}"));
+        return Ok(1);
+    }
+    if let Some(r) = &ar.wip_ref {
+        let _ = worktree::delete_ref(&repo.root, r);
+    }
+    Ok(0)
+}
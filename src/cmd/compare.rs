@@ -0,0 +1,379 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::api::RemoveOpts;
+use crate::config::Config;
+use crate::gitx;
+use crate::state::{Allocation, State};
+use crate::ui;
+use crate::util::{child_stdout_to_stderr, run_argv_with_wrt_env};
+use crate::worktree::{self, DiffStat, Integrate};
+
+use super::{cmd_archive, cmd_rm};
+
+// Compared is one worktree's row in `wrt compare`.
+#[derive(Serialize)]
+struct Compared {
+    name: String,
+    branch: String,
+    base: Option<String>,
+    commits: Option<u32>,
+    diff: Option<DiffStat>,
+    /// Uncommitted files; they are not part of the diff.
+    dirty: Option<usize>,
+    check: Option<Check>,
+    #[serde(skip)]
+    lines: Option<BTreeSet<String>>,
+}
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    secs: u64,
+}
+
+#[derive(Serialize)]
+struct Similarity {
+    a: String,
+    b: String,
+    score: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    group: &'a str,
+    check: Option<&'a str>,
+    worktrees: &'a [Compared],
+    similarity: &'a [Similarity],
+}
+
+pub struct CompareOpts<'a> {
+    pub group: &'a str,
+    /// Overrides the configured `check` command.
+    pub check: Option<&'a str>,
+    pub no_check: bool,
+    pub json: bool,
+}
+
+// cmd_compare lines up the worktrees of a group (`wrt new --count`): what each changed since the
+// shared base, whether the configured check passes, and how alike the changes are.
+pub fn cmd_compare(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    cfg: &Config,
+    st: &State,
+    opts: CompareOpts<'_>,
+) -> Result<i32> {
+    let group = opts.group;
+    let members = group_members(st, group);
+    if members.is_empty() {
+        log.errorf(&format!("no worktrees in group \"{group}\""));
+        return Ok(2);
+    }
+    let check = if opts.no_check {
+        None
+    } else {
+        opts.check.or(cfg.check.as_deref())
+    };
+
+    let remote = worktree::resolve_remote(&repo.root, cfg.remote.as_deref()).unwrap_or(None);
+    let main = worktree::default_branch(&repo.root, remote.as_deref());
+
+    // Check output goes to stderr; stdout is the report.
+    child_stdout_to_stderr(true);
+
+    let mut rows: Vec<Compared> = Vec::new();
+    for a in &members {
+        let wt_path = Path::new(&a.path);
+        let base = a.from_ref.clone().or_else(|| main.clone());
+        let mut row = Compared {
+            name: a.name.clone(),
+            branch: a.branch.clone(),
+            base: base.clone(),
+            commits: None,
+            diff: None,
+            dirty: None,
+            check: None,
+            lines: None,
+        };
+        if !wt_path.exists() {
+            log.warnf(&format!("{}: checkout missing", a.name));
+            rows.push(row);
+            continue;
+        }
+        row.dirty = worktree::dirty_files(wt_path).ok().map(|f| f.len());
+        if let Some(base) = &base {
+            row.commits = worktree::ahead_behind(wt_path, "HEAD", base)
+                .ok()
+                .map(|(ahead, _)| ahead);
+            row.diff = worktree::diff_stat(wt_path, base).ok();
+            row.lines = worktree::changed_lines(wt_path, base).ok();
+        }
+        if let Some(check) = check {
+            log.infof(&format!("==> {}: {check}", a.name));
+            let argv = vec!["sh".to_string(), "-c".to_string(), check.to_string()];
+            let started = Instant::now();
+            let ok = run_argv_with_wrt_env(wt_path, a, &argv).is_ok();
+            row.check = Some(Check {
+                ok,
+                secs: started.elapsed().as_secs(),
+            });
+        }
+        rows.push(row);
+    }
+
+    let score = |i: usize, j: usize| match (&rows[i].lines, &rows[j].lines) {
+        (Some(l), Some(r)) => jaccard(l, r),
+        _ => None,
+    };
+    let mut sims: Vec<Similarity> = Vec::new();
+    for i in 0..rows.len() {
+        for j in i + 1..rows.len() {
+            sims.push(Similarity {
+                a: rows[i].name.clone(),
+                b: rows[j].name.clone(),
+                score: score(i, j),
+            });
+        }
+    }
+
+    if opts.json {
+        let report = Report {
+            group,
+            check,
+            worktrees: &rows,
+            similarity: &sims,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(0);
+    }
+
+    println!(
+        "{:<3}  {:<28}  {:>7}  {:>5}  {:>6}  {:>6}  {:>5}  CHECK",
+        "#", "NAME", "COMMITS", "FILES", "+", "-", "DIRTY"
+    );
+    let num = |v: Option<u32>| v.map(|n| n.to_string()).unwrap_or_else(|| "-".into());
+    for (i, r) in rows.iter().enumerate() {
+        let check = match &r.check {
+            None => "-".to_string(),
+            Some(c) if c.ok => format!("pass ({}s)", c.secs),
+            Some(c) => format!("FAIL ({}s)", c.secs),
+        };
+        println!(
+            "{:<3}  {:<28}  {:>7}  {:>5}  {:>6}  {:>6}  {:>5}  {check}",
+            i + 1,
+            r.name,
+            num(r.commits),
+            num(r.diff.map(|d| d.files)),
+            num(r.diff.map(|d| d.insertions)),
+            num(r.diff.map(|d| d.deletions)),
+            r.dirty.map(|n| n.to_string()).unwrap_or_else(|| "-".into()),
+        );
+    }
+
+    if rows.len() > 1 {
+        println!();
+        println!("similarity of changed lines (1.00 = identical changes):");
+        let header: String = (1..=rows.len()).map(|i| format!("  {i:>4}")).collect();
+        println!("{:<3}{header}", "");
+        for i in 0..rows.len() {
+            let cells: String = (0..rows.len())
+                .map(|j| match score(i, j) {
+                    _ if i == j => format!("  {:>4}", "-"),
+                    Some(s) => format!("  {s:>4.2}"),
+                    None => format!("  {:>4}", "?"),
+                })
+                .collect();
+            println!("{:<3}{cells}", i + 1);
+        }
+    }
+    Ok(0)
+}
+
+pub struct PickOpts<'a> {
+    pub name: &'a str,
+    pub into: Option<&'a str>,
+    pub how: Integrate,
+    /// What to do with the other worktrees of the group: keep, archive or rm.
+    pub others: &'a str,
+}
+
+// cmd_pick brings the winning worktree's branch into the target branch (by default the ref the
+// group was created from) and optionally archives or removes the rest of its group.
+pub fn cmd_pick(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    cfg: &Config,
+    st: &mut State,
+    opts: PickOpts<'_>,
+) -> Result<i32> {
+    let key = worktree::slug(opts.name);
+    let Some(a) = st.allocations.get(&key).cloned() else {
+        log.errorf(&format!("unknown worktree: \"{key}\""));
+        return Ok(2);
+    };
+    if opts.others != "keep" && a.group.is_none() {
+        log.errorf(&format!(
+            "{} is not part of a group; nothing to {}",
+            a.name, opts.others
+        ));
+        return Ok(2);
+    }
+
+    let wt_path = Path::new(&a.path);
+    match worktree::dirty_files(wt_path) {
+        Ok(files) if files.is_empty() => {}
+        Ok(_) => {
+            log.errorf(&format!(
+                "{} has uncommitted changes; commit them first",
+                a.name
+            ));
+            return Ok(2);
+        }
+        Err(e) => {
+            log.errorf(&format!("{}: {e}", a.name));
+            return Ok(1);
+        }
+    }
+
+    let remote = worktree::resolve_remote(&repo.root, cfg.remote.as_deref()).unwrap_or(None);
+    let target = match opts.into.map(str::to_string).or_else(|| a.from_ref.clone()) {
+        Some(t) => t,
+        None => match worktree::default_branch(&repo.root, remote.as_deref()) {
+            Some(t) => t,
+            None => {
+                log.errorf("cannot tell which branch to pick into; pass --into <branch>");
+                return Ok(2);
+            }
+        },
+    };
+    if target == a.branch {
+        log.errorf(&format!("{} is already on {target}", a.name));
+        return Ok(2);
+    }
+    if !worktree::branch_exists(&repo.root, &target) {
+        log.errorf(&format!(
+            "target \"{target}\" is not a local branch; pass --into <branch>"
+        ));
+        return Ok(2);
+    }
+
+    // Work where the target is checked out; otherwise in a throwaway checkout of it.
+    let checkout = match worktree::list(&repo.root) {
+        Ok(list) => list
+            .into_iter()
+            .find(|w| w.branch.as_deref() == Some(target.as_str()))
+            .map(|w| w.path),
+        Err(e) => {
+            log.errorf(&format!("{e}"));
+            return Ok(1);
+        }
+    };
+    let temp = match &checkout {
+        Some(dir) => {
+            if worktree::is_dirty(dir).unwrap_or(true) {
+                log.errorf(&format!(
+                    "{target} is checked out at {} with uncommitted changes",
+                    dir.display()
+                ));
+                return Ok(2);
+            }
+            None
+        }
+        None => {
+            let dir = repo
+                .root
+                .join(".worktrees")
+                .join(format!(".pick-{}", a.name));
+            let add = worktree::AddOpts {
+                branch: &target,
+                from_ref: &target,
+                remote: None,
+                sparse: &[],
+            };
+            if let Err(e) = worktree::add(&repo.root, &dir, &add) {
+                log.errorf(&format!("check out {target}: {e}"));
+                return Ok(1);
+            }
+            Some(dir)
+        }
+    };
+    let dir = checkout.as_deref().or(temp.as_deref()).unwrap();
+
+    log.infof(&format!("picking {} ({}) into {target}", a.name, a.branch));
+    let res = worktree::integrate(dir, &a.branch, opts.how);
+    if let Some(temp) = &temp {
+        let _ = worktree::remove(&repo.root, temp, true);
+    }
+    if let Err(e) = res {
+        log.errorf(&format!(
+            "could not apply {} onto {target} ({e}); nothing was changed",
+            a.branch
+        ));
+        return Ok(1);
+    }
+
+    let Some(group) = a.group.as_deref().filter(|_| opts.others != "keep") else {
+        return Ok(0);
+    };
+    let mut failed = false;
+    for other in group_members(st, group) {
+        if other.name == a.name {
+            continue;
+        }
+        let code = if opts.others == "archive" {
+            cmd_archive(log, repo, st, &other.name)?
+        } else {
+            // The branch is saved under refs/wrt-archive/ first, so forcing loses nothing. The
+            // refs stay local: picking a winner shouldn't publish the losers.
+            let rm = RemoveOpts {
+                force: true,
+                delete_branch: true,
+                backup: true,
+                push: false,
+            };
+            cmd_rm(log, repo, st, &other.name, &rm)?
+        };
+        failed |= code != 0;
+    }
+    if failed {
+        return Ok(1);
+    }
+    Ok(0)
+}
+
+fn group_members(st: &State, group: &str) -> Vec<Allocation> {
+    st.sorted_allocations()
+        .into_iter()
+        .filter(|a| a.group.as_deref() == Some(group))
+        .collect()
+}
+
+// jaccard is |a ∩ b| / |a ∪ b|; None when neither side changed anything.
+fn jaccard(a: &BTreeSet<String>, b: &BTreeSet<String>) -> Option<f64> {
+    let union = a.union(b).count();
+    if union == 0 {
+        return None;
+    }
+    Some(a.intersection(b).count() as f64 / union as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jaccard_of_changed_lines() {
+        let set = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<BTreeSet<_>>();
+        assert_eq!(jaccard(&set(&[]), &set(&[])), None);
+        assert_eq!(jaccard(&set(&["a", "b"]), &set(&["a", "b"])), Some(1.0));
+        assert_eq!(
+            jaccard(&set(&["a", "b"]), &set(&["b", "c"])),
+            Some(1.0 / 3.0)
+        );
+        assert_eq!(jaccard(&set(&["a"]), &set(&[])), Some(0.0));
+    }
+}
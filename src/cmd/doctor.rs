@@ -0,0 +1,437 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::gitx;
+use crate::state::State;
+use crate::supabase;
+use crate::ui;
+use crate::util::run_cmd;
+use crate::worktree;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Severity {
+    Info,
+    Warn,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Severity::Info => "info",
+            Severity::Warn => "warn",
+            Severity::Error => "error",
+        };
+        f.write_str(s)
+    }
+}
+
+// Fix is a repair `--fix` may apply. Each one only touches wrt-owned data (state.json, .wrt.env,
+// the patched supabase config, info/exclude) or runs `git worktree prune`; nothing deletes work.
+#[derive(Clone, Debug)]
+enum Fix {
+    GitPrune,
+    DropEntry(String),
+    SetBranch(String, String),
+    Reblock(String),
+    WriteEnv(String),
+    Repatch(String),
+    SkipWorktree(String),
+    Exclude,
+}
+
+struct Finding {
+    severity: Severity,
+    subject: String,
+    msg: String,
+    fix: Option<Fix>,
+}
+
+pub fn cmd_doctor(log: &ui::Logger, repo: &gitx::Repo, st: &mut State, fix: bool) -> Result<i32> {
+    // With --fix, check and repair one consistent state: hold the lock until it is saved.
+    let _lock = if fix {
+        let lock = match State::lock(&repo.common_dir) {
+            Ok(l) => l,
+            Err(e) => {
+                log.errorf(&format!("{e}"));
+                return Ok(1);
+            }
+        };
+        if let Err(e) = st.reload(&repo.common_dir) {
+            log.errorf(&format!("state load failed: {e}"));
+            return Ok(1);
+        }
+        Some(lock)
+    } else {
+        None
+    };
+
+    let findings = check(repo, st)?;
+
+    if findings.is_empty() {
+        println!("ok: no problems found");
+        return Ok(0);
+    }
+
+    for f in &findings {
+        let hint = match (&f.fix, fix) {
+            (Some(_), false) => "  [fixable: --fix]",
+            _ => "",
+        };
+        println!("{:<5}  {:<20}  {}{hint}", f.severity, f.subject, f.msg);
+    }
+
+    if !fix {
+        let worst = findings.iter().map(|f| f.severity).max();
+        return Ok(if worst >= Some(Severity::Warn) { 1 } else { 0 });
+    }
+
+    let mut failed = 0;
+    let mut applied = 0;
+    for f in &findings {
+        let Some(x) = &f.fix else { continue };
+        match apply(repo, st, x) {
+            Ok(()) => applied += 1,
+            Err(e) => {
+                log.errorf(&format!("fix {}: {e}", f.subject));
+                failed += 1;
+            }
+        }
+    }
+    if let Err(e) = st.save(&repo.common_dir) {
+        log.errorf(&format!("state save failed: {e}"));
+        return Ok(1);
+    }
+    log.infof(&format!("applied {applied} fix(es)"));
+
+    // Re-check so the exit code reflects what is left.
+    let left = check(repo, st)?;
+    let unresolved = left.iter().filter(|f| f.severity >= Severity::Warn).count();
+    if failed > 0 || unresolved > 0 {
+        log.infof(&format!("{unresolved} problem(s) need manual attention"));
+        return Ok(1);
+    }
+    Ok(0)
+}
+
+fn check(repo: &gitx::Repo, st: &State) -> Result<Vec<Finding>> {
+    let mut out: Vec<Finding> = Vec::new();
+    let mut push = |severity, subject: &str, msg: String, fix| {
+        out.push(Finding {
+            severity,
+            subject: subject.to_string(),
+            msg,
+            fix,
+        })
+    };
+
+    let wts = worktree::list(&repo.root)?;
+    let linked: Vec<&worktree::GitWorktree> = wts.iter().skip(1).collect();
+
+    // git's own bookkeeping.
+    for wt in linked.iter().filter(|w| w.prunable) {
+        push(
+            Severity::Warn,
+            "git",
+            format!("stale worktree entry: {}", wt.path.display()),
+            Some(Fix::GitPrune),
+        );
+    }
+
+    // Worktrees git knows about but wrt doesn't.
+    for wt in linked.iter().filter(|w| !w.prunable && !w.bare) {
+        let tracked = st
+            .allocations
+            .values()
+            .any(|a| worktree::same_path(Path::new(&a.path), &wt.path));
+        if !tracked {
+            push(
+                Severity::Info,
+                "git",
+                format!(
+                    "untracked worktree: {} (run `wrt adopt {}`)",
+                    wt.path.display(),
+                    wt.path.display()
+                ),
+                None,
+            );
+        }
+    }
+
+    // Blocks shared by several allocations: the oldest keeps it. Entries whose path is gone are
+    // dropped instead (below), so they don't take part.
+    let mut by_block: BTreeMap<i32, Vec<&crate::state::Allocation>> = BTreeMap::new();
+    for a in st
+        .allocations
+        .values()
+        .filter(|a| Path::new(&a.path).exists())
+    {
+        by_block.entry(a.block).or_default().push(a);
+    }
+    let mut reblocked: Vec<String> = Vec::new();
+    for (block, mut allocs) in by_block {
+        if block <= 0 {
+            for a in allocs {
+                push(
+                    Severity::Error,
+                    &a.name,
+                    format!("invalid port block {block} (block 0 is the main workdir)"),
+                    Some(Fix::Reblock(a.name.clone())),
+                );
+                reblocked.push(a.name.clone());
+            }
+            continue;
+        }
+        if allocs.len() < 2 {
+            continue;
+        }
+        allocs.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        let keeper = allocs[0].name.clone();
+        for a in &allocs[1..] {
+            push(
+                Severity::Error,
+                &a.name,
+                format!("port block {block} is also used by \"{keeper}\""),
+                Some(Fix::Reblock(a.name.clone())),
+            );
+            reblocked.push(a.name.clone());
+        }
+    }
+    if let Some(link) = &st.registry {
+        for a in st.allocations.values() {
+            if reblocked.contains(&a.name) {
+                continue;
+            }
+            if let Some(holder) = link.holder(a.block) {
+                push(
+                    Severity::Error,
+                    &a.name,
+                    format!("port block {} is also used by {holder}", a.block),
+                    Some(Fix::Reblock(a.name.clone())),
+                );
+                reblocked.push(a.name.clone());
+            }
+        }
+    }
+
+    for a in st.allocations.values() {
+        let wt_path = Path::new(&a.path);
+        if !wt_path.exists() {
+            push(
+                Severity::Error,
+                &a.name,
+                format!("path missing: {}", a.path),
+                Some(Fix::DropEntry(a.name.clone())),
+            );
+            continue;
+        }
+
+        let git_wt = linked
+            .iter()
+            .find(|w| worktree::same_path(&w.path, wt_path));
+        match git_wt {
+            None => push(
+                Severity::Error,
+                &a.name,
+                format!("{} is not a git worktree of this repo", a.path),
+                None,
+            ),
+            Some(w) => match &w.branch {
+                Some(b) if *b != a.branch => push(
+                    Severity::Warn,
+                    &a.name,
+                    format!("branch is \"{b}\" but state says \"{}\"", a.branch),
+                    Some(Fix::SetBranch(a.name.clone(), b.clone())),
+                ),
+                None if w.detached => push(
+                    Severity::Warn,
+                    &a.name,
+                    format!("detached HEAD (state says branch \"{}\")", a.branch),
+                    None,
+                ),
+                _ => {}
+            },
+        }
+
+        // The fixes below are covered by Reblock.
+        if reblocked.contains(&a.name) {
+            continue;
+        }
+
+        if !a.is_consistent() {
+            let what = match &a.host {
+                Some(h) => format!("host {h}"),
+                None => format!("offset {}", a.offset),
+            };
+            push(
+                Severity::Error,
+                &a.name,
+                format!("{what} does not match block {}", a.block),
+                Some(Fix::Reblock(a.name.clone())),
+            );
+            continue;
+        }
+
+        match worktree::read_env_file(wt_path) {
+            Err(_) => push(
+                Severity::Warn,
+                &a.name,
+                ".wrt.env missing".to_string(),
+                Some(Fix::WriteEnv(a.name.clone())),
+            ),
+            Ok(vars) => {
+                let stale: Vec<String> = worktree::env_file_vars(a)
+                    .into_iter()
+                    .filter(|(k, v)| !vars.iter().any(|(k2, v2)| k2 == k && v2 == v))
+                    .map(|(k, _)| k)
+                    .collect();
+                if !stale.is_empty() {
+                    push(
+                        Severity::Warn,
+                        &a.name,
+                        format!(".wrt.env is stale ({})", stale.join(", ")),
+                        Some(Fix::WriteEnv(a.name.clone())),
+                    );
+                }
+            }
+        }
+
+        if supabase::has_config(wt_path) {
+            let skip = worktree::is_skip_worktree(wt_path, "supabase/config.toml").unwrap_or(false);
+            // Re-patching starts from HEAD's config; edits of the user's would be lost.
+            let (repatch_fix, edited) = match supabase::has_local_edits(wt_path) {
+                Ok(false) => (Some(Fix::Repatch(a.name.clone())), ""),
+                Ok(true) => (None, "; it has other local edits, so fix it by hand"),
+                Err(_) => (None, ""),
+            };
+            match supabase::patched_offset(wt_path, &a.name) {
+                Ok(Some(off)) if off != a.offset => push(
+                    Severity::Error,
+                    &a.name,
+                    format!(
+                        "supabase config is patched for offset {off}, expected {}{edited}",
+                        a.offset
+                    ),
+                    repatch_fix,
+                ),
+                Ok(Some(_)) if !skip => push(
+                    Severity::Warn,
+                    &a.name,
+                    "supabase/config.toml is patched but not marked skip-worktree".to_string(),
+                    Some(Fix::SkipWorktree(a.name.clone())),
+                ),
+                Ok(Some(_)) => {}
+                Ok(None) if skip => push(
+                    Severity::Warn,
+                    &a.name,
+                    format!(
+                        "supabase config lost its patch (project_id has no worktree suffix){edited}"
+                    ),
+                    repatch_fix,
+                ),
+                Ok(None) => push(
+                    Severity::Info,
+                    &a.name,
+                    "supabase config is not patched (created with --supabase false?)".to_string(),
+                    None,
+                ),
+                Err(e) => push(
+                    Severity::Warn,
+                    &a.name,
+                    format!("supabase config: {e}"),
+                    None,
+                ),
+            }
+        }
+    }
+
+    let exclude =
+        fs::read_to_string(repo.common_dir.join("info").join("exclude")).unwrap_or_default();
+    let missing: Vec<&str> = gitx::WRT_EXCLUDES
+        .iter()
+        .copied()
+        .filter(|p| !exclude.lines().any(|l| l.trim() == *p))
+        .collect();
+    if !missing.is_empty() {
+        push(
+            Severity::Warn,
+            "info/exclude",
+            format!("missing patterns: {}", missing.join(" ")),
+            Some(Fix::Exclude),
+        );
+    }
+
+    Ok(out)
+}
+
+fn apply(repo: &gitx::Repo, st: &mut State, fix: &Fix) -> Result<()> {
+    match fix {
+        Fix::GitPrune => run_cmd(&repo.root, "git", &["worktree", "prune"]),
+        Fix::DropEntry(key) => {
+            st.allocations.remove(key);
+            Ok(())
+        }
+        Fix::SetBranch(key, branch) => {
+            let Some(a) = st.allocations.get_mut(key) else {
+                return Ok(());
+            };
+            a.branch = branch.clone();
+            worktree::write_env_file(Path::new(&a.path), a)
+        }
+        Fix::Reblock(key) => {
+            // Free the entry's own block first so it is not counted against itself.
+            let Some(mut a) = st.allocations.remove(key) else {
+                return Ok(());
+            };
+            if a.block <= 0 || st.block_in_use(a.block) {
+                a.block = st.allocate_block()?;
+            }
+            let isolation = a.isolation();
+            a.assign_block(a.block, isolation);
+            let wt_path = Path::new(&a.path).to_path_buf();
+            st.allocations.insert(key.clone(), a.clone());
+            worktree::write_env_file(&wt_path, &a)?;
+            if supabase::has_config(&wt_path)
+                && supabase::patched_offset(&wt_path, &a.name)?.is_some()
+            {
+                repatch(&wt_path, &a.name, a.offset, a.host.as_deref())?;
+            }
+            Ok(())
+        }
+        Fix::WriteEnv(key) => match st.allocations.get(key) {
+            Some(a) => worktree::write_env_file(Path::new(&a.path), a),
+            None => Ok(()),
+        },
+        Fix::Repatch(key) => match st.allocations.get(key) {
+            Some(a) => repatch(Path::new(&a.path), &a.name, a.offset, a.host.as_deref()),
+            None => Ok(()),
+        },
+        Fix::SkipWorktree(key) => match st.allocations.get(key) {
+            Some(a) => run_cmd(
+                Path::new(&a.path),
+                "git",
+                &["update-index", "--skip-worktree", "supabase/config.toml"],
+            ),
+            None => Ok(()),
+        },
+        Fix::Exclude => gitx::ensure_info_exclude(&repo.common_dir, gitx::WRT_EXCLUDES),
+    }
+}
+
+fn repatch(wt_path: &Path, name: &str, offset: i32, host: Option<&str>) -> Result<()> {
+    if supabase::has_local_edits(wt_path)? {
+        return Err(anyhow!(
+            "supabase/config.toml has local edits besides wrt's patch; re-patch it by hand"
+        ));
+    }
+    supabase::restore_config(wt_path)?;
+    supabase::patch_config(wt_path, name, offset, host)?;
+    run_cmd(
+        wt_path,
+        "git",
+        &["update-index", "--skip-worktree", "supabase/config.toml"],
+    )
+}
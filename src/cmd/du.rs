@@ -0,0 +1,192 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::state::State;
+use crate::supabase;
+use crate::ui;
+use crate::util::which;
+
+// Directory names counted as build output (anywhere in the tree, outside node_modules).
+const BUILD_DIRS: &[&str] = &[
+    "target",
+    "dist",
+    "build",
+    ".next",
+    ".nuxt",
+    ".svelte-kit",
+    ".turbo",
+    ".output",
+    ".parcel-cache",
+];
+
+#[derive(Debug, Default, Serialize)]
+struct Usage {
+    name: String,
+    path: String,
+    checkout: u64,
+    node_modules: u64,
+    build: u64,
+    /// Docker volumes of the worktree's supabase project (None: no config or docker unavailable).
+    volumes: Option<u64>,
+    /// Bytes in files with more than one hard link. They are charged to the first worktree they
+    /// are found in, so totals don't double count.
+    shared: u64,
+    total: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Checkout,
+    NodeModules,
+    Build,
+}
+
+pub fn cmd_du(log: &ui::Logger, st: &State, sort: &str, json: bool) -> Result<i32> {
+    let allocs = st.sorted_allocations();
+
+    let wants_volumes = allocs
+        .iter()
+        .any(|a| supabase::has_config(Path::new(&a.path)));
+    let vols = if wants_volumes && which("docker").is_some() {
+        match supabase::volume_sizes() {
+            Ok(v) => Some(v),
+            Err(e) => {
+                log.warnf(&format!("docker volumes not included: {e}"));
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut seen: HashSet<(u64, u64)> = HashSet::new();
+    let mut rows: Vec<Usage> = Vec::new();
+    for a in &allocs {
+        let wt_path = Path::new(&a.path);
+        let mut u = Usage {
+            name: a.name.clone(),
+            path: a.path.clone(),
+            ..Default::default()
+        };
+        if wt_path.exists() {
+            walk(wt_path, Kind::Checkout, &mut seen, &mut u);
+        }
+        if let (Some(vols), true) = (&vols, supabase::has_config(wt_path)) {
+            if let Ok(Some(id)) = supabase::project_id(wt_path) {
+                u.volumes = Some(supabase::volumes_size(vols, &id));
+            }
+        }
+        u.total = u.checkout + u.node_modules + u.build + u.volumes.unwrap_or(0);
+        rows.push(u);
+    }
+
+    match sort {
+        "name" => {}
+        "checkout" => rows.sort_by_key(|u| std::cmp::Reverse(u.checkout)),
+        "node_modules" => rows.sort_by_key(|u| std::cmp::Reverse(u.node_modules)),
+        "build" => rows.sort_by_key(|u| std::cmp::Reverse(u.build)),
+        "volumes" => rows.sort_by_key(|u| std::cmp::Reverse(u.volumes.unwrap_or(0))),
+        _ => rows.sort_by_key(|u| std::cmp::Reverse(u.total)),
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(0);
+    }
+
+    if rows.is_empty() {
+        println!("(no worktrees tracked by wrt)");
+        return Ok(0);
+    }
+
+    println!(
+        "{:<28}  {:>9}  {:>12}  {:>9}  {:>9}  {:>9}  {:>9}",
+        "NAME", "CHECKOUT", "NODE_MODULES", "BUILD", "VOLUMES", "SHARED", "TOTAL"
+    );
+    for u in &rows {
+        println!(
+            "{:<28}  {:>9}  {:>12}  {:>9}  {:>9}  {:>9}  {:>9}",
+            u.name,
+            fmt_bytes(u.checkout),
+            fmt_bytes(u.node_modules),
+            fmt_bytes(u.build),
+            u.volumes.map(fmt_bytes).unwrap_or_else(|| "-".into()),
+            fmt_bytes(u.shared),
+            fmt_bytes(u.total)
+        );
+    }
+    let total: u64 = rows.iter().map(|u| u.total).sum();
+    println!("{:<28}  {:>67}", "(total)", fmt_bytes(total));
+    Ok(0)
+}
+
+// walk adds up allocated file sizes below dir without following symlinks. Unreadable entries are
+// skipped rather than failing the whole report.
+fn walk(dir: &Path, kind: Kind, seen: &mut HashSet<(u64, u64)>, u: &mut Usage) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for e in entries.flatten() {
+        let Ok(md) = e.path().symlink_metadata() else {
+            continue;
+        };
+        let name = e.file_name();
+        let name = name.to_string_lossy();
+        if md.is_dir() {
+            if name == ".git" {
+                continue;
+            }
+            let sub = match kind {
+                Kind::NodeModules => Kind::NodeModules,
+                _ if name == "node_modules" => Kind::NodeModules,
+                Kind::Checkout if BUILD_DIRS.contains(&name.as_ref()) => Kind::Build,
+                k => k,
+            };
+            walk(&e.path(), sub, seen, u);
+            continue;
+        }
+
+        let (size, links, id) = file_info(&md);
+        if links > 1 {
+            u.shared += size;
+            if !seen.insert(id) {
+                continue;
+            }
+        }
+        match kind {
+            Kind::Checkout => u.checkout += size,
+            Kind::NodeModules => u.node_modules += size,
+            Kind::Build => u.build += size,
+        }
+    }
+}
+
+// file_info returns (allocated bytes, hard link count, (dev, inode)).
+#[cfg(unix)]
+fn file_info(md: &fs::Metadata) -> (u64, u64, (u64, u64)) {
+    use std::os::unix::fs::MetadataExt;
+    (md.blocks() * 512, md.nlink(), (md.dev(), md.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_info(md: &fs::Metadata) -> (u64, u64, (u64, u64)) {
+    (md.len(), 1, (0, 0))
+}
+
+fn fmt_bytes(n: u64) -> String {
+    const UNITS: &[&str] = &["B", "K", "M", "G", "T"];
+    let mut v = n as f64;
+    let mut i = 0;
+    while v >= 1024.0 && i < UNITS.len() - 1 {
+        v /= 1024.0;
+        i += 1;
+    }
+    if i == 0 {
+        format!("{n}B")
+    } else {
+        format!("{v:.1}{}", UNITS[i])
+    }
+}
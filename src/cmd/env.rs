@@ -37,5 +37,13 @@ pub fn cmd_env(log: &ui::Logger, st: &State, name: Option<&str>) -> Result<i32>
     println!("export WRT_BRANCH={}", sh_quote(&a.branch));
     println!("export WRT_PORT_BLOCK={}", a.block);
     println!("export WRT_PORT_OFFSET={}", a.offset);
+    if let Some(host) = &a.host {
+        println!("export WRT_HOST={host}");
+    }
+    for (k, v) in worktree::env_file_vars(a) {
+        if k.starts_with("WRT_URL_") {
+            println!("export {k}={}", sh_quote(&v));
+        }
+    }
     Ok(0)
 }
@@ -0,0 +1,53 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::filter;
+use crate::state::State;
+use crate::ui;
+use crate::util::run_argv_with_wrt_env;
+
+// cmd_foreach runs the command in each matching worktree in turn. A failure doesn't stop the
+// loop; the exit code is 1 if any run failed.
+pub fn cmd_foreach(
+    log: &ui::Logger,
+    st: &State,
+    filters: &[String],
+    command: &[String],
+) -> Result<i32> {
+    if command.is_empty() {
+        log.errorf("usage: wrt foreach [--filter <expr>]... -- <command> [args...]");
+        return Ok(2);
+    }
+    let filters = match filter::parse_all(filters) {
+        Ok(f) => f,
+        Err(e) => {
+            log.errorf(&format!("{e}"));
+            return Ok(2);
+        }
+    };
+
+    let targets: Vec<_> = st
+        .sorted_allocations()
+        .into_iter()
+        .filter(|a| filter::matches_all(&filters, a))
+        .collect();
+    if targets.is_empty() {
+        log.infof("no matching worktrees");
+        return Ok(0);
+    }
+
+    let mut failed: Vec<String> = Vec::new();
+    for a in &targets {
+        log.infof(&format!("==> {} ({})", a.name, a.path));
+        if let Err(e) = run_argv_with_wrt_env(Path::new(&a.path), a, command) {
+            log.errorf(&format!("{}: {e}", a.name));
+            failed.push(a.name.clone());
+        }
+    }
+
+    if !failed.is_empty() {
+        log.errorf(&format!("failed in: {}", failed.join(", ")));
+        return Ok(1);
+    }
+    Ok(0)
+}
@@ -0,0 +1,173 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::gitx;
+use crate::services;
+use crate::state::{Allocation, State};
+use crate::ui;
+use crate::util::{fmt_age, parse_duration, run_cmd};
+use crate::worktree;
+
+pub struct GcOpts<'a> {
+    pub older_than: Option<&'a str>,
+    pub merged: bool,
+    pub dry_run: bool,
+}
+
+struct Candidate {
+    alloc: Allocation,
+    reasons: Vec<String>,
+    merged: bool,
+}
+
+// cmd_gc removes worktrees whose TTL has expired, that have been idle longer than --older-than,
+// or (with --merged) whose branch is merged into the default branch. Worktrees with uncommitted
+// changes are always skipped; branches are only deleted when merged.
+pub fn cmd_gc(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    cfg: &Config,
+    st: &mut State,
+    opts: GcOpts<'_>,
+) -> Result<i32> {
+    let older_than = match opts.older_than.map(parse_duration).transpose() {
+        Ok(d) => d,
+        Err(e) => {
+            log.errorf(&format!("--older-than: {e}"));
+            return Ok(2);
+        }
+    };
+
+    let base = if opts.merged {
+        let remote = worktree::resolve_remote(&repo.root, cfg.remote.as_deref()).unwrap_or(None);
+        let Some(base) = worktree::default_branch(&repo.root, remote.as_deref()) else {
+            log.errorf("--merged: could not determine the default branch");
+            return Ok(1);
+        };
+        Some(base)
+    } else {
+        None
+    };
+
+    let now = Utc::now();
+    let mut candidates: Vec<Candidate> = Vec::new();
+    for a in st.sorted_allocations() {
+        let wt_path = Path::new(&a.path);
+        if !wt_path.exists() {
+            // `wrt prune` handles entries whose checkout is gone.
+            continue;
+        }
+
+        let mut reasons: Vec<String> = Vec::new();
+        if let Some(exp) = a.expires_at.as_deref().and_then(parse_time) {
+            if exp <= now {
+                reasons.push("ttl expired".to_string());
+            }
+        }
+        if let Some(d) = older_than {
+            let last = last_activity(&a);
+            if now - last >= d {
+                reasons.push(format!("idle {}", fmt_age((now - last).num_seconds())));
+            }
+        }
+        let mut merged = false;
+        if let Some(base) = &base {
+            if a.branch != *base
+                && worktree::is_merged(&repo.root, &a.branch, base).unwrap_or(false)
+            {
+                merged = true;
+                reasons.push(format!("merged into {base}"));
+            }
+        }
+
+        if !reasons.is_empty() {
+            candidates.push(Candidate {
+                alloc: a,
+                reasons,
+                merged,
+            });
+        }
+    }
+
+    if candidates.is_empty() {
+        log.infof("nothing to collect");
+        return Ok(0);
+    }
+
+    let mut failed = 0;
+    for c in &candidates {
+        let a = &c.alloc;
+        let why = c.reasons.join(", ");
+        let wt_path = Path::new(&a.path);
+
+        if worktree::is_dirty(wt_path).unwrap_or(true) {
+            println!("{:<28}  skipped: uncommitted changes ({why})", a.name);
+            continue;
+        }
+        if opts.dry_run {
+            let branch = if c.merged { " and branch" } else { "" };
+            println!("{:<28}  would remove{branch} ({why})", a.name);
+            continue;
+        }
+
+        match collect(log, repo, st, c) {
+            Ok(()) => println!("{:<28}  removed ({why})", a.name),
+            Err(e) => {
+                log.errorf(&format!("{}: {e}", a.name));
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Ok(1);
+    }
+    Ok(0)
+}
+
+fn collect(log: &ui::Logger, repo: &gitx::Repo, st: &mut State, c: &Candidate) -> Result<()> {
+    let a = &c.alloc;
+    let wt_path = Path::new(&a.path);
+
+    services::stop(log, wt_path)?;
+    worktree::remove(&repo.root, wt_path, false)?;
+    if c.merged {
+        run_cmd(&repo.root, "git", &["branch", "-D", &a.branch])?;
+    }
+
+    st.update(&repo.common_dir, |s| {
+        s.allocations.remove(&a.name);
+    })
+}
+
+// last_activity is the latest of: creation, the HEAD commit, and modification of any file git
+// reports as changed or untracked (ignored files such as node_modules don't count).
+fn last_activity(a: &Allocation) -> DateTime<Utc> {
+    let wt_path = Path::new(&a.path);
+    let mut last = parse_time(&a.created_at).unwrap_or(DateTime::<Utc>::MIN_UTC);
+
+    if let Ok(ts) = worktree::last_commit_time(wt_path) {
+        if let Some(t) = DateTime::<Utc>::from_timestamp(ts, 0) {
+            last = last.max(t);
+        }
+    }
+
+    for line in worktree::dirty_files(wt_path).unwrap_or_default() {
+        // Porcelain lines are "XY path" (renames: "XY old -> new").
+        let rel = line.get(3..).unwrap_or("");
+        let rel = rel.rsplit(" -> ").next().unwrap_or(rel).trim_matches('"');
+        if let Ok(modified) = fs::metadata(wt_path.join(rel)).and_then(|m| m.modified()) {
+            last = last.max(DateTime::<Utc>::from(modified));
+        }
+    }
+    last
+}
+
+fn parse_time(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
@@ -1,26 +1,153 @@
 use anyhow::Result;
 use std::path::Path;
 
-use crate::state::State;
+use crate::filter;
+use crate::registry::{self, Registry};
+use crate::state::{Allocation, State};
+use crate::ui;
 use crate::worktree;
 
-pub fn cmd_ls(st: &State) -> Result<i32> {
-    if st.allocations.is_empty() {
+pub fn cmd_ls(log: &ui::Logger, st: &State, filters: &[String]) -> Result<i32> {
+    let filters = match filter::parse_all(filters) {
+        Ok(f) => f,
+        Err(e) => {
+            log.errorf(&format!("{e}"));
+            return Ok(2);
+        }
+    };
+
+    if st.allocations.is_empty() && st.archived.is_empty() {
         println!("(no worktrees tracked by wrt)");
         return Ok(0);
     }
 
     for a in st.sorted_allocations() {
-        let dirty = match worktree::is_dirty(Path::new(&a.path)) {
-            Ok(true) => "dirty",
-            Ok(false) => "clean",
-            Err(_) => "?",
-        };
+        if !filter::matches_all(&filters, &a) {
+            continue;
+        }
+        println!("{}", line(&a));
+    }
+
+    for ar in st.archived.values() {
+        if !filter::matches_all(&filters, &ar.alloc) {
+            continue;
+        }
+        println!(
+            "{:<28}  block=-    {:<11}  {:<6}  {}  (archived {}){}",
+            ar.alloc.name,
+            "offset=-",
+            "arch",
+            ar.alloc.branch,
+            ar.archived_at,
+            meta_suffix(&ar.alloc)
+        );
+    }
+
+    Ok(0)
+}
+
+// cmd_ls_all lists the worktrees of every repo in the machine-wide registry, by repo.
+pub fn cmd_ls_all(log: &ui::Logger, filters: &[String]) -> Result<i32> {
+    let filters = match filter::parse_all(filters) {
+        Ok(f) => f,
+        Err(e) => {
+            log.errorf(&format!("{e}"));
+            return Ok(2);
+        }
+    };
+    let Some(p) = registry::default_path() else {
+        log.errorf("cannot locate ~/.local/state (HOME not set)");
+        return Ok(2);
+    };
+    if !p.exists() {
         println!(
-            "{:<28}  block={:<3}  offset={:<4}  {:<5}  {}  ({})",
-            a.name, a.block, a.offset, dirty, a.branch, a.path
+            "(no machine-wide registry at {}; set \"registry\": true in ~/.config/wrt/config.json)",
+            p.display()
         );
+        return Ok(0);
     }
+    let reg = match Registry::load(&p) {
+        Ok(r) => r,
+        Err(e) => {
+            log.errorf(&format!("{e}"));
+            return Ok(1);
+        }
+    };
 
+    let mut shown = 0;
+    for (key, r) in &reg.repos {
+        let allocs: Vec<&Allocation> = r
+            .worktrees
+            .values()
+            .filter(|a| filter::matches_all(&filters, a))
+            .collect();
+        if allocs.is_empty() {
+            continue;
+        }
+        let gone = if Path::new(key).exists() {
+            ""
+        } else {
+            "  (repo missing; `wrt prune` drops it)"
+        };
+        println!("{}{gone}", r.root);
+        for a in allocs {
+            println!("  {}", line(a));
+            shown += 1;
+        }
+    }
+    if shown == 0 {
+        println!("(no worktrees tracked by wrt)");
+    }
     Ok(0)
 }
+
+fn line(a: &Allocation) -> String {
+    // A worktree kept by `wrt new --keep-on-failure` shows that rather than clean/dirty.
+    let dirty = if a.failed.is_some() {
+        "failed"
+    } else {
+        match worktree::is_dirty(Path::new(&a.path)) {
+            Ok(true) => "dirty",
+            Ok(false) => "clean",
+            Err(_) => "?",
+        }
+    };
+    let isolated = match &a.host {
+        Some(h) => format!("host={h}"),
+        None => format!("offset={:<4}", a.offset),
+    };
+    format!(
+        "{:<28}  block={:<3}  {:<11}  {:<6}  {}  ({}){}",
+        a.name,
+        a.block,
+        isolated,
+        dirty,
+        a.branch,
+        a.path,
+        meta_suffix(a)
+    )
+}
+
+// meta_suffix shows group, profile, owner, agent and labels; notes and task URLs are left to `wrt tag <name>`.
+fn meta_suffix(a: &Allocation) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    if let Some(g) = &a.group {
+        parts.push(format!("group={g}"));
+    }
+    if let Some(p) = &a.profile {
+        parts.push(format!("profile={p}"));
+    }
+    if let Some(o) = &a.owner {
+        parts.push(format!("owner={o}"));
+    }
+    if let Some(ag) = &a.agent {
+        parts.push(format!("agent={ag}"));
+    }
+    for (k, v) in &a.labels {
+        parts.push(format!("{k}={v}"));
+    }
+    if parts.is_empty() {
+        return String::new();
+    }
+    format!("  [{}]", parts.join(" "))
+}
@@ -0,0 +1,388 @@
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::api::{Error, RemoveOpts};
+use crate::cli::{DbAction, MetaArgs};
+use crate::config::Config;
+use crate::filter;
+use crate::gitx;
+use crate::mcp::{self, Outcome, Tool};
+use crate::state::{Allocation, State};
+use crate::ui;
+use crate::util::{child_stdin_null, child_stdout_to_stderr, output_argv_with_wrt_env};
+use crate::worktree;
+
+use super::{cmd_db, cmd_new, remove_worktree, status, NewOpts};
+
+const DEFAULT_RUN_TIMEOUT_SECS: u64 = 600;
+// Output past this many bytes (per stream) is cut from the front; the end is what matters.
+const MAX_OUTPUT: usize = 64 * 1024;
+
+// cmd_mcp serves wrt's commands as Model Context Protocol tools over stdio, so agents can create,
+// inspect and remove their own worktrees. The tools run the same code as the CLI.
+pub fn cmd_mcp(log: &ui::Logger, repo: &gitx::Repo, cfg: &Config, st: &mut State) -> Result<i32> {
+    // stdin and stdout carry the protocol; nothing wrt runs may touch them.
+    child_stdout_to_stderr(true);
+    child_stdin_null(true);
+
+    log.infof(&format!("mcp: serving {} on stdio", repo.root.display()));
+    let tools = tools();
+    mcp::serve(
+        io::stdin().lock(),
+        io::stdout().lock(),
+        &tools,
+        |name, args, client| {
+            let log = log.capturing();
+            if let Err(e) = st.reload(&repo.common_dir) {
+                return Outcome::error(&format!("state load failed: {e}"));
+            }
+            let out = match name {
+                "create" => create(&log, repo, cfg, st, args, client),
+                "list" => list(st, args),
+                "env" => env(st, args),
+                "run" => run(st, args),
+                "db_reset" => db_reset(&log, repo, st, args),
+                "remove" => remove(&log, repo, st, args),
+                "status" => status(repo, cfg, st, args),
+                _ => Err(format!("unknown tool: \"{name}\"")),
+            };
+            match out {
+                Ok(o) => o,
+                Err(e) => {
+                    let mut o = Outcome::error(&e);
+                    o.value["log"] = json!(log.captured());
+                    o
+                }
+            }
+        },
+    )?;
+    Ok(0)
+}
+
+fn tools() -> Vec<Tool> {
+    let name = json!({ "type": "string", "description": "Worktree name" });
+    let filters = json!({
+        "type": "array",
+        "items": { "type": "string" },
+        "description": "Filters as in `wrt ls --filter`, e.g. label=agent=gpt or group=task; all must match",
+    });
+    vec![
+        Tool {
+            name: "create",
+            description: "Create a worktree (branch, port block, .wrt.env, dependency install) and return it with its WRT_* environment.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "name": name,
+                    "profile": { "type": "string", "description": "Named settings from the wrt config (`profiles`); the other arguments win" },
+                    "from": { "type": "string", "description": "Ref to branch from (default HEAD)" },
+                    "branch": { "type": "string", "description": "Branch name (default: the name)" },
+                    "install": { "type": "string", "enum": ["auto", "true", "false"] },
+                    "supabase": { "type": "string", "enum": ["auto", "true", "false"] },
+                    "db": { "type": "string", "enum": ["auto", "true", "false"] },
+                    "sparse": { "type": "array", "items": { "type": "string" }, "description": "Only check out these directories" },
+                    "labels": { "type": "object", "additionalProperties": { "type": "string" } },
+                    "note": { "type": "string" },
+                    "agent": { "type": "string", "description": "Who works in it (default: the MCP client's name)" },
+                    "task_url": { "type": "string" },
+                    "ttl": { "type": "string", "description": "Let `wrt gc` remove it after this long, e.g. 2d" },
+                },
+                "required": ["name"],
+            }),
+        },
+        Tool {
+            name: "list",
+            description: "List tracked worktrees: name, branch, path, port block and metadata.",
+            input_schema: json!({ "type": "object", "properties": { "filter": filters } }),
+        },
+        Tool {
+            name: "env",
+            description: "Return a worktree's WRT_* environment variables (port offset, host, service URLs).",
+            input_schema: json!({ "type": "object", "properties": { "name": name }, "required": ["name"] }),
+        },
+        Tool {
+            name: "run",
+            description: "Run a command in a worktree with its WRT_* variables set; returns exit code, stdout and stderr.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "name": name,
+                    "command": { "type": "array", "items": { "type": "string" }, "minItems": 1, "description": "argv, e.g. [\"npm\", \"test\"]" },
+                    "timeout_secs": { "type": "integer", "minimum": 1, "description": "Kill the command after this long (default 600)" },
+                },
+                "required": ["name", "command"],
+            }),
+        },
+        Tool {
+            name: "db_reset",
+            description: "Reset the worktree's local database (destructive; its own isolated database only).",
+            input_schema: json!({ "type": "object", "properties": { "name": name }, "required": ["name"] }),
+        },
+        Tool {
+            name: "remove",
+            description: "Remove a worktree. Refuses if uncommitted changes, unpushed commits or stashes would be lost, unless backup is set.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "name": name,
+                    "delete_branch": { "type": "boolean", "description": "Also delete the branch" },
+                    "backup": { "type": "boolean", "description": "Save the branch and changes under refs/wrt-archive/ first, and push those refs to the git remote (if any)" },
+                },
+                "required": ["name"],
+            }),
+        },
+        Tool {
+            name: "status",
+            description: "Per-worktree status: dirty files, ahead/behind upstream and base, conflicts with the default branch, services.",
+            input_schema: json!({ "type": "object", "properties": { "filter": filters } }),
+        },
+    ]
+}
+
+// Tool arguments are checked by hand; errors go back to the agent as text.
+type Res<T> = std::result::Result<T, String>;
+
+fn create(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    cfg: &Config,
+    st: &mut State,
+    args: &Map<String, Value>,
+    client: Option<&str>,
+) -> Res<Outcome> {
+    let name = req_str(args, "name")?;
+    let labels = match args.get("labels") {
+        None => Vec::new(),
+        Some(Value::Object(m)) => m
+            .iter()
+            .map(|(k, v)| match v.as_str() {
+                Some(v) => Ok(format!("{k}={v}")),
+                None => Err(format!("labels.{k} must be a string")),
+            })
+            .collect::<Res<_>>()?,
+        Some(_) => return Err("labels must be an object".into()),
+    };
+    let meta = MetaArgs {
+        label: labels,
+        note: opt_str(args, "note")?.map(str::to_string),
+        owner: None,
+        agent: opt_str(args, "agent")?.or(client).map(str::to_string),
+        task_url: opt_str(args, "task_url")?.map(str::to_string),
+    };
+    let sparse = str_list(args, "sparse")?;
+    let opts = NewOpts {
+        name,
+        profile: opt_str(args, "profile")?,
+        from_ref: opt_str(args, "from")?,
+        branch: opt_str(args, "branch")?,
+        install_mode: opt_str(args, "install")?,
+        sb_mode: opt_str(args, "supabase")?,
+        db_mode: opt_str(args, "db")?,
+        sparse: &sparse,
+        sparse_profile: None,
+        pr: None,
+        remote: None,
+        no_fetch: false,
+        meta: &meta,
+        ttl: opt_str(args, "ttl")?,
+        count: None,
+        keep_on_failure: false,
+        jobs: 1,
+        json: false,
+        emit_cd: false,
+        interactive: false,
+        max_worktrees: cfg.mcp_max_worktrees,
+    };
+    let code = cmd_new(log, repo, cfg, st, opts).map_err(|e| e.to_string())?;
+
+    let key = worktree::slug(name);
+    let _ = st.reload(&repo.common_dir);
+    let mut value = json!({ "exit_code": code, "log": log.captured() });
+    if let Some(a) = st.allocations.get(&key) {
+        value["worktree"] = json!(a);
+        value["env"] = json!(env_map(a));
+    }
+    Ok(outcome(code, value))
+}
+
+fn list(st: &State, args: &Map<String, Value>) -> Res<Outcome> {
+    let allocs = filtered(st, args)?;
+    Ok(Outcome::ok(json!({ "worktrees": allocs })))
+}
+
+fn env(st: &State, args: &Map<String, Value>) -> Res<Outcome> {
+    let a = lookup(st, args)?;
+    Ok(Outcome::ok(json!({
+        "name": a.name,
+        "path": a.path,
+        "env": env_map(a),
+    })))
+}
+
+fn run(st: &State, args: &Map<String, Value>) -> Res<Outcome> {
+    let a = lookup(st, args)?;
+    let argv = str_list(args, "command")?;
+    if argv.is_empty() {
+        return Err("command must be a non-empty array of strings".into());
+    }
+    let secs = match args.get("timeout_secs") {
+        None => DEFAULT_RUN_TIMEOUT_SECS,
+        Some(v) => v
+            .as_u64()
+            .filter(|s| *s > 0)
+            .ok_or("timeout_secs must be a positive integer")?,
+    };
+    let out = output_argv_with_wrt_env(Path::new(&a.path), a, &argv, Duration::from_secs(secs))
+        .map_err(|e| format!("{e:#}"))?;
+
+    let value = json!({
+        "exit_code": out.code,
+        "timed_out": out.timed_out,
+        "stdout": tail(&out.stdout),
+        "stderr": tail(&out.stderr),
+    });
+    Ok(Outcome {
+        value,
+        is_error: out.code != Some(0),
+    })
+}
+
+fn db_reset(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    st: &State,
+    args: &Map<String, Value>,
+) -> Res<Outcome> {
+    let a = lookup(st, args)?;
+    let action = DbAction::Reset {
+        yes: true,
+        print: false,
+    };
+    let code = cmd_db(log, repo, st, Some(&a.name), None, action).map_err(|e| e.to_string())?;
+    Ok(outcome(
+        code,
+        json!({ "exit_code": code, "log": log.captured() }),
+    ))
+}
+
+fn remove(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    st: &mut State,
+    args: &Map<String, Value>,
+) -> Res<Outcome> {
+    let name = lookup(st, args)?.name.clone();
+    let delete_branch = opt_bool(args, "delete_branch")?;
+    let backup = opt_bool(args, "backup")?;
+    // Never forced: removing must not lose work an agent forgot to commit or push.
+    let opts = RemoveOpts {
+        force: false,
+        delete_branch,
+        backup,
+        push: backup,
+    };
+    // The locked remove path, as `create` goes through `cmd_new`'s: agents remove and create
+    // worktrees concurrently.
+    let (code, at_risk) = match remove_worktree(log, repo, st, &name, &opts) {
+        Ok(()) => (0, Vec::new()),
+        Err(Error::WouldLoseWork { name, at_risk }) => {
+            log.errorf(&format!(
+                "refusing to remove \"{name}\"; pass backup to save its work first"
+            ));
+            (1, at_risk)
+        }
+        Err(e) => {
+            log.errorf(&format!("{e}"));
+            (e.exit_code(), Vec::new())
+        }
+    };
+    let mut value = json!({ "exit_code": code, "log": log.captured() });
+    if !at_risk.is_empty() {
+        value["at_risk"] = json!(at_risk);
+    }
+    Ok(outcome(code, value))
+}
+
+fn status(repo: &gitx::Repo, cfg: &Config, st: &State, args: &Map<String, Value>) -> Res<Outcome> {
+    let allocs = filtered(st, args)?;
+    let rows = status::collect(repo, cfg, &allocs);
+    Ok(Outcome::ok(json!({ "worktrees": rows })))
+}
+
+fn outcome(code: i32, value: Value) -> Outcome {
+    Outcome {
+        value,
+        is_error: code != 0,
+    }
+}
+
+fn env_map(a: &Allocation) -> BTreeMap<String, String> {
+    worktree::env_file_vars(a).into_iter().collect()
+}
+
+fn lookup<'s>(st: &'s State, args: &Map<String, Value>) -> Res<&'s Allocation> {
+    let key = worktree::slug(req_str(args, "name")?);
+    st.allocations
+        .get(&key)
+        .ok_or_else(|| format!("unknown worktree: \"{key}\""))
+}
+
+fn filtered(st: &State, args: &Map<String, Value>) -> Res<Vec<Allocation>> {
+    let filters = filter::parse_all(&str_list(args, "filter")?).map_err(|e| e.to_string())?;
+    Ok(st
+        .sorted_allocations()
+        .into_iter()
+        .filter(|a| filter::matches_all(&filters, a))
+        .collect())
+}
+
+fn req_str<'a>(args: &'a Map<String, Value>, key: &str) -> Res<&'a str> {
+    opt_str(args, key)?
+        .filter(|s| !s.trim().is_empty())
+        .ok_or_else(|| format!("missing \"{key}\""))
+}
+
+fn opt_str<'a>(args: &'a Map<String, Value>, key: &str) -> Res<Option<&'a str>> {
+    match args.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s)),
+        Some(_) => Err(format!("\"{key}\" must be a string")),
+    }
+}
+
+fn opt_bool(args: &Map<String, Value>, key: &str) -> Res<bool> {
+    match args.get(key) {
+        None | Some(Value::Null) => Ok(false),
+        Some(Value::Bool(b)) => Ok(*b),
+        Some(_) => Err(format!("\"{key}\" must be a boolean")),
+    }
+}
+
+fn str_list(args: &Map<String, Value>, key: &str) -> Res<Vec<String>> {
+    let bad = || format!("\"{key}\" must be an array of strings");
+    match args.get(key) {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|v| v.as_str().map(str::to_string).ok_or_else(bad))
+            .collect(),
+        Some(_) => Err(bad()),
+    }
+}
+
+// tail decodes command output, keeping the last MAX_OUTPUT bytes.
+fn tail(b: &[u8]) -> String {
+    let s = String::from_utf8_lossy(b);
+    if s.len() <= MAX_OUTPUT {
+        return s.to_string();
+    }
+    let mut start = s.len() - MAX_OUTPUT;
+    while !s.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("[... {start} bytes cut ...]\n{}", &s[start..])
+}
@@ -1,17 +1,49 @@
+mod adopt;
+mod archive;
+mod compare;
 mod db;
+mod doctor;
+mod du;
 mod env;
+mod foreach;
+mod gc;
 mod init;
 mod ls;
+mod mcp;
+mod mv;
 mod new;
+mod proxy;
 mod prune;
 mod rm;
 mod run;
+mod setup;
+mod shell;
+mod sparse;
+mod status;
+mod tag;
+mod tui;
 
+pub use adopt::{cmd_adopt, AdoptOpts};
+pub use archive::{cmd_archive, cmd_restore};
+pub use compare::{cmd_compare, cmd_pick, CompareOpts, PickOpts};
 pub use db::cmd_db;
+pub use doctor::cmd_doctor;
+pub use du::cmd_du;
 pub use env::{cmd_env, cmd_path};
+pub use foreach::cmd_foreach;
+pub use gc::{cmd_gc, GcOpts};
 pub use init::cmd_init;
-pub use ls::cmd_ls;
-pub use new::{cmd_new, NewOpts};
+pub use ls::{cmd_ls, cmd_ls_all};
+pub use mcp::cmd_mcp;
+pub use mv::cmd_mv;
+pub use new::{cmd_new, new_worktree, NewOpts};
+pub use proxy::cmd_proxy;
 pub use prune::cmd_prune;
-pub use rm::cmd_rm;
+pub use rm::{cmd_rm, remove_worktree};
 pub use run::{cmd_run, raw_run_has_sep};
+pub use setup::{cmd_setup, SetupOpts};
+pub use shell::{cmd_cd, cmd_prompt, cmd_shell_init};
+pub use sparse::cmd_sparse;
+pub use status::cmd_status;
+pub use tag::{apply_meta, cmd_tag};
+pub use tui::cmd_ui;
@@ -0,0 +1,200 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use crate::gitx;
+use crate::services;
+use crate::state::{Allocation, State};
+use crate::supabase;
+use crate::ui;
+use crate::util::run_cmd;
+use crate::worktree;
+
+// cmd_mv renames a tracked worktree. The name is the state key, the directory slug, part of the
+// Supabase project_id suffix and WRT_NAME, so all of them move together.
+pub fn cmd_mv(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    st: &mut State,
+    old: &str,
+    new: &str,
+    branch: Option<&str>,
+) -> Result<i32> {
+    let old_key = worktree::slug(old);
+    let new_key = worktree::slug(new);
+    let Some(a) = st.allocations.get(&old_key).cloned() else {
+        log.errorf(&format!("unknown worktree: \"{old_key}\""));
+        return Ok(2);
+    };
+    if new_key == old_key && branch.is_none() {
+        log.errorf(&format!("\"{old_key}\" already has that name"));
+        return Ok(2);
+    }
+    if new_key != old_key
+        && (st.allocations.contains_key(&new_key) || st.archived.contains_key(&new_key))
+    {
+        log.errorf(&format!(
+            "worktree \"{new_key}\" already exists in state; use `wrt ls`"
+        ));
+        return Ok(2);
+    }
+
+    // Keep the worktree next to where it is (adopted worktrees may live outside .worktrees/).
+    let old_path = PathBuf::from(&a.path);
+    let new_path = match old_path.parent() {
+        Some(parent) => parent.join(&new_key),
+        None => repo.root.join(".worktrees").join(&new_key),
+    };
+    if new_path != old_path && new_path.exists() {
+        log.errorf(&format!("{} already exists", new_path.display()));
+        return Ok(2);
+    }
+
+    // `--branch` without a value renames the branch after the new worktree name.
+    let new_branch = branch.map(|b| match b.trim() {
+        "" => worktree::normalize_branch(new),
+        b => worktree::normalize_branch(b),
+    });
+    if let Some(nb) = &new_branch {
+        if *nb != a.branch && worktree::ref_exists(&repo.root, &format!("refs/heads/{nb}")) {
+            log.errorf(&format!("branch {nb} already exists"));
+            return Ok(2);
+        }
+    }
+
+    // The Supabase project_id embeds the name: containers must be stopped under the old id and the
+    // config re-patched for the new one.
+    let mut repatch = new_key != old_key
+        && supabase::has_config(&old_path)
+        && matches!(supabase::patched_offset(&old_path, &a.name), Ok(Some(_)));
+    // Re-patching starts from HEAD's config; don't throw away edits of the user's.
+    if repatch && supabase::has_local_edits(&old_path).unwrap_or(true) {
+        log.warnf(
+            "supabase/config.toml has local edits; keeping its project_id (re-patch it by hand)",
+        );
+        repatch = false;
+    }
+    // Only a stack that is up now is started again under the new id.
+    let restart = repatch
+        && supabase::running_containers()
+            .ok()
+            .and_then(|c| services::running(&old_path, &c))
+            .unwrap_or(false);
+    if repatch {
+        if let Err(e) = services::stop(log, &old_path) {
+            log.errorf(&format!("supabase stop failed: {e}"));
+            return Ok(1);
+        }
+    }
+
+    if new_path != old_path {
+        log.infof(&format!(
+            "moving worktree: {} -> {}",
+            old_path.display(),
+            new_path.display()
+        ));
+        if let Err(e) = worktree::move_to(&repo.root, &old_path, &new_path) {
+            log.errorf(&format!("git worktree move failed: {e}"));
+            if restart {
+                let _ = services::start(log, &old_path);
+            }
+            return Ok(1);
+        }
+    }
+
+    let mut a = a;
+    if let Some(nb) = new_branch.filter(|nb| *nb != a.branch) {
+        log.infof(&format!("renaming branch: {} -> {nb}", a.branch));
+        if let Err(e) = worktree::rename_branch(&repo.root, &a.branch, &nb) {
+            log.errorf(&format!("branch rename failed: {e}"));
+            undo_move(log, repo, st, &old_key, &a, &new_path);
+            if restart {
+                let _ = services::start(log, Path::new(&st.allocations[&old_key].path));
+            }
+            return Ok(1);
+        }
+        a.branch = nb;
+    }
+    // Proxy hostnames lead with the worktree name.
+    for url in a.urls.values_mut() {
+        *url = url.replacen(&format!("//{}.", a.name), &format!("//{new_key}."), 1);
+    }
+    a.name = new_key.clone();
+    a.path = new_path.to_string_lossy().to_string();
+
+    let saved = st.update(&repo.common_dir, |s| {
+        s.allocations.remove(&old_key);
+        s.allocations.insert(new_key.clone(), a.clone());
+    });
+    if let Err(e) = saved {
+        log.errorf(&format!("state save failed: {e}"));
+        return Ok(1);
+    }
+
+    if let Err(e) = worktree::write_env_file(&new_path, &a) {
+        log.errorf(&format!("write env file: {e}"));
+        return Ok(1);
+    }
+
+    if repatch {
+        log.infof("supabase: re-suffixing project_id for the new name");
+        if let Err(e) = resuffix(&new_path, &a.name, a.offset, a.host.as_deref()) {
+            log.errorf(&format!("supabase patch failed: {e}"));
+            return Ok(1);
+        }
+        log.infof("note: data volumes of the old project_id are not carried over");
+        if restart {
+            if let Err(e) = services::start(log, &new_path) {
+                log.errorf(&format!("supabase start failed: {e}"));
+                return Ok(1);
+            }
+        }
+    }
+
+    Ok(0)
+}
+
+// undo_move puts the checkout back where state says it is after a later step failed. If git
+// can't move it back, state follows the checkout instead, so it never points at a missing path.
+fn undo_move(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    st: &mut State,
+    key: &str,
+    a: &Allocation,
+    moved_to: &Path,
+) {
+    let old_path = Path::new(&a.path);
+    if moved_to == old_path {
+        return;
+    }
+    match worktree::move_to(&repo.root, moved_to, old_path) {
+        Ok(()) => log.infof(&format!(
+            "moved the worktree back to {}",
+            old_path.display()
+        )),
+        Err(e) => {
+            log.warnf(&format!(
+                "could not move the worktree back ({e}); it stays at {}",
+                moved_to.display()
+            ));
+            let mut a = a.clone();
+            a.path = moved_to.to_string_lossy().to_string();
+            let saved = st.update(&repo.common_dir, |s| {
+                s.allocations.insert(key.to_string(), a);
+            });
+            if let Err(e) = saved {
+                log.errorf(&format!("state save failed: {e}"));
+            }
+        }
+    }
+}
+
+fn resuffix(wt_path: &Path, name: &str, offset: i32, host: Option<&str>) -> Result<()> {
+    supabase::restore_config(wt_path)?;
+    supabase::patch_config(wt_path, name, offset, host)?;
+    run_cmd(
+        wt_path,
+        "git",
+        &["update-index", "--skip-worktree", "supabase/config.toml"],
+    )
+}
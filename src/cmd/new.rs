@@ -1,236 +1,740 @@
 use anyhow::Result;
 use chrono::SecondsFormat;
-use std::fs;
-use std::io::IsTerminal;
-use std::path::Path;
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
 
-use crate::codex;
-use crate::db;
+use crate::api::Error;
+use crate::cli::MetaArgs;
+use crate::config::{Config, Isolation, Profile};
 use crate::gitx;
-use crate::pm;
+use crate::lock::FileLock;
+use crate::proxy;
+use crate::services;
 use crate::state::{Allocation, State};
-use crate::supabase;
 use crate::ui;
-use crate::util::{confirm, run_argv_with_wrt_env, run_cmd, sh_quote, which};
+use crate::util::{child_stdout_to_stderr, parse_duration, run_cmd, sh_quote};
 use crate::worktree;
 
+use super::apply_meta;
+use super::setup::{mode, provision, Provisioned, Step, StepOpts};
+
 pub struct NewOpts<'a> {
     pub name: &'a str,
-    pub from_ref: &'a str,
+    /// Named settings from config `profiles` (default: config `default_profile`); the other
+    /// options win over the profile's.
+    pub profile: Option<&'a str>,
+    /// Ref to branch from (default: the profile's, else HEAD).
+    pub from_ref: Option<&'a str>,
     pub branch: Option<&'a str>,
-    pub install_mode: &'a str,
-    pub sb_mode: &'a str,
-    pub db_mode: &'a str,
+    /// auto|true|false (default: the profile's, else auto); likewise sb_mode and db_mode.
+    pub install_mode: Option<&'a str>,
+    pub sb_mode: Option<&'a str>,
+    pub db_mode: Option<&'a str>,
+    pub sparse: &'a [String],
+    pub sparse_profile: Option<&'a str>,
+    pub pr: Option<u64>,
+    pub remote: Option<&'a str>,
+    pub no_fetch: bool,
+    pub meta: &'a MetaArgs,
+    pub ttl: Option<&'a str>,
+    /// On failure, keep what was created and mark it failed instead of rolling it back.
+    pub keep_on_failure: bool,
+    /// Spawn this many numbered copies (`<name>-1..N`) in one group.
+    pub count: Option<usize>,
+    /// Parallel install/DB setup jobs for --count.
+    pub jobs: usize,
+    pub json: bool,
     pub emit_cd: bool,
+    /// Whether DB setup may prompt (a terminal on stdin); `--count` never prompts.
+    pub interactive: bool,
+    /// Refuse if state would then track more worktrees than this (checked under the state lock).
+    pub max_worktrees: Option<usize>,
+}
+
+struct Target {
+    name: String,
+    branch: String,
+    path: PathBuf,
+}
+
+// Settings shared by every worktree of one `wrt new`.
+struct Plan {
+    from_ref: String,
+    pr: Option<u64>,
+    /// Remote ref the PR head was fetched from just now (None: not fetched).
+    pr_head: Option<String>,
+    remote: Option<String>,
+    fetch: bool,
+    sparse: Vec<String>,
+    /// When `wrt gc` may remove the worktrees (from --ttl, RFC 3339).
+    expires_at: Option<String>,
+    group: Option<String>,
+    isolation: Isolation,
+    /// Services from .wrt.json and the `wrt proxy` port, for WRT_URL_<SERVICE>.
+    services: Vec<(String, i32)>,
+    proxy_port: u16,
+    meta: MetaArgs,
+    profile: Option<String>,
+    /// Files the profile copies into the worktree.
+    files: Vec<String>,
+    keep_on_failure: bool,
+    sb: String,
+    install: String,
+    db_mode: String,
+}
+
+// Spawned is one row of the `--count` summary.
+#[derive(Serialize)]
+struct Spawned {
+    name: String,
+    branch: String,
+    path: String,
+    block: Option<i32>,
+    offset: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    group: Option<String>,
+    ok: bool,
+    error: Option<String>,
 }
 
 pub fn cmd_new(
     log: &ui::Logger,
     repo: &gitx::Repo,
+    cfg: &Config,
     st: &mut State,
     opts: NewOpts<'_>,
 ) -> Result<i32> {
-    let wt_name = worktree::slug(opts.name);
-    let wt_path = repo.root.join(".worktrees").join(&wt_name);
-
-    let mut br = opts.branch.unwrap_or("").trim().to_string();
-    if br.is_empty() {
-        br = opts.name.to_string();
+    if opts.count.is_some() {
+        return spawn(log, repo, cfg, st, &opts);
     }
-    br = worktree::normalize_branch(&br);
-
-    if st.allocations.contains_key(&wt_name) {
-        log.errorf(&format!(
-            "worktree \"{wt_name}\" already exists in state; use `wrt ls`"
-        ));
-        return Ok(2);
+    // With --cd, stdout is eval'd by the shell: only the `cd` line may go there.
+    if opts.emit_cd {
+        child_stdout_to_stderr(true);
     }
+    match new_worktree(log, repo, cfg, st, &opts) {
+        Ok(a) => {
+            if opts.emit_cd {
+                println!("cd {}", sh_quote(&a.path));
+            }
+            Ok(0)
+        }
+        Err(e) => Ok(fail(log, st, &e)),
+    }
+}
 
-    let block = match st.allocate_block() {
-        Ok(b) => b,
+// new_worktree creates one worktree (`wrt new` without --count): checkout, port block and state
+// entry under the state lock, then the setup steps outside it. On failure what was done is rolled
+// back, or with `keep_on_failure` kept and marked failed.
+pub fn new_worktree(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    cfg: &Config,
+    st: &mut State,
+    opts: &NewOpts<'_>,
+) -> Result<Allocation, Error> {
+    let (targets, plan) = prepare(log, repo, cfg, opts)?;
+    let lock = lock_for(repo, st, &targets, opts.max_worktrees)?;
+
+    let t = &targets[0];
+    let mut journal = Journal::default();
+    let alloc = match create(log, repo, st, &plan, t, &mut journal) {
+        Ok(a) => a,
         Err(e) => {
-            log.errorf(&format!("allocate port block: {e}"));
-            return Ok(1);
+            abandon(log, repo, st, &plan, &t.name, journal, &e.to_string());
+            return Err(e);
         }
     };
-    let offset = block * 100;
+    drop(lock);
 
-    log.infof(&format!(
-        "creating worktree: {wt_name} ({br}) at {}",
-        wt_path.display()
-    ));
+    let p = setup(log, repo, &plan, &alloc, "", opts.interactive, &mut journal);
+    // Record the completed steps, or give up on the worktree, under the lock again.
+    let _lock = relock(repo, st)?;
+    if let Some(a) = st.allocations.get_mut(&t.name) {
+        p.note(a);
+    }
+    if let Some((_, e)) = &p.failed {
+        abandon(log, repo, st, &plan, &t.name, journal, &e.to_string());
+        return Err(Error::failed(e.to_string()));
+    }
+    st.save(&repo.common_dir)
+        .map_err(|e| Error::failed(format!("state save failed: {e}")))?;
+    Ok(st.allocations.get(&t.name).cloned().unwrap_or(alloc))
+}
 
-    worktree::ensure_dir(wt_path.parent().unwrap())?;
+// spawn is `wrt new --count`: numbered worktrees in one group, set up `jobs` at a time, then a
+// summary (or JSON) of which ones made it.
+fn spawn(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    cfg: &Config,
+    st: &mut State,
+    opts: &NewOpts<'_>,
+) -> Result<i32> {
+    // The summary (or JSON) owns stdout.
+    child_stdout_to_stderr(true);
 
-    if let Err(e) = worktree::add(&repo.root, &wt_path, &br, opts.from_ref) {
-        log.errorf(&format!("git worktree add failed: {e}"));
-        return Ok(1);
+    let (targets, plan) = match prepare(log, repo, cfg, opts) {
+        Ok(p) => p,
+        Err(e) => return Ok(fail(log, st, &e)),
+    };
+    let lock = match lock_for(repo, st, &targets, opts.max_worktrees) {
+        Ok(l) => l,
+        Err(e) => return Ok(fail(log, st, &e)),
+    };
+
+    let mut results: Vec<Spawned> = Vec::new();
+    let mut created: Vec<(usize, Allocation, Mutex<Journal>)> = Vec::new();
+    for t in &targets {
+        let mut row = Spawned {
+            name: t.name.clone(),
+            branch: t.branch.clone(),
+            path: t.path.to_string_lossy().to_string(),
+            block: None,
+            offset: None,
+            host: None,
+            group: plan.group.clone(),
+            ok: false,
+            error: None,
+        };
+        let mut journal = Journal::default();
+        match create(log, repo, st, &plan, t, &mut journal) {
+            Ok(a) => {
+                row.block = Some(a.block);
+                row.offset = Some(a.offset);
+                row.host = a.host.clone();
+                created.push((results.len(), a, Mutex::new(journal)));
+            }
+            Err(e) => {
+                log.errorf(&format!("{}: {e}", t.name));
+                abandon(log, repo, st, &plan, &t.name, journal, &e.to_string());
+                row.error = Some(e.to_string());
+            }
+        }
+        results.push(row);
     }
+    drop(lock);
 
-    let created_at = chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
-    let alloc = Allocation {
-        name: wt_name.clone(),
-        branch: br.clone(),
-        path: wt_path.to_string_lossy().to_string(),
-        block,
-        offset,
-        created_at,
+    // Setup runs concurrently, at most `jobs` at a time. DB setup never prompts here.
+    let outcomes: Mutex<Vec<(usize, Provisioned)>> = Mutex::new(Vec::new());
+    let next = AtomicUsize::new(0);
+    let workers = opts.jobs.max(1).min(created.len());
+    thread::scope(|s| {
+        for _ in 0..workers {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let Some((_, a, journal)) = created.get(i) else {
+                    break;
+                };
+                let who = format!("{}: ", a.name);
+                let mut journal = journal.lock().unwrap();
+                let p = setup(log, repo, &plan, a, &who, false, &mut journal);
+                if let Some((_, e)) = &p.failed {
+                    log.errorf(&format!("{who}{e}"));
+                }
+                outcomes.lock().unwrap().push((i, p));
+            });
+        }
+    });
+    // Record the completed steps, and roll back (or mark) the failures, under the lock again.
+    let lock = match created.is_empty() {
+        true => None,
+        false => match relock(repo, st) {
+            Ok(l) => Some(l),
+            Err(e) => {
+                log.errorf(&format!("{e}"));
+                None
+            }
+        },
     };
+    for (i, p) in outcomes.into_inner().unwrap() {
+        let (row, a, journal) = &mut created[i];
+        if let Some((_, e)) = &p.failed {
+            results[*row].error = Some(e.to_string());
+        }
+        if lock.is_none() {
+            continue;
+        }
+        if let Some(a) = st.allocations.get_mut(&a.name) {
+            p.note(a);
+        }
+        if let Some((_, e)) = &p.failed {
+            let journal = std::mem::take(journal.get_mut().unwrap());
+            abandon(log, repo, st, &plan, &a.name, journal, &e.to_string());
+        }
+    }
+    if lock.is_some() {
+        if let Err(e) = st.save(&repo.common_dir) {
+            log.errorf(&format!("state save failed: {e}"));
+        }
+    }
+    drop(lock);
+    for (row, _, _) in &created {
+        results[*row].ok = results[*row].error.is_none();
+    }
 
-    st.allocations.insert(wt_name.clone(), alloc.clone());
-    if let Err(e) = st.save(&repo.common_dir) {
-        log.errorf(&format!("state save failed: {e}"));
-        return Ok(1);
+    let failed = results.iter().filter(|r| !r.ok).count();
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        println!("{:<28}  {:>5}  {:<28}  RESULT", "NAME", "BLOCK", "BRANCH");
+        for r in &results {
+            let block = r.block.map(|b| b.to_string()).unwrap_or_else(|| "-".into());
+            let result = match &r.error {
+                None => "ok".to_string(),
+                Some(e) => format!("failed: {e}"),
+            };
+            println!("{:<28}  {:>5}  {:<28}  {result}", r.name, block, r.branch);
+        }
+        println!(
+            "group \"{}\": {} created, {failed} failed",
+            plan.group.as_deref().unwrap_or(""),
+            results.len() - failed
+        );
     }
 
-    if let Err(e) = worktree::write_env_file(&wt_path, &alloc) {
-        log.errorf(&format!("write env file: {e}"));
+    if failed > 0 {
         return Ok(1);
     }
+    Ok(0)
+}
+
+// fail reports why `wrt new` failed, pointing at the command that helps, and returns the exit code.
+fn fail(log: &ui::Logger, st: &State, e: &Error) -> i32 {
+    let hint = match e {
+        Error::AlreadyExists(name) => match st.allocations.get(name).map(|a| &a.failed) {
+            Some(Some(_)) => format!(
+                "; its setup failed; retry with `wrt setup {name}` or remove it with `wrt rm {name} --force`"
+            ),
+            _ => "; use `wrt ls`".to_string(),
+        },
+        Error::Archived(name) => format!("; use `wrt restore {name}`"),
+        _ => String::new(),
+    };
+    log.errorf(&format!("{e}{hint}"));
+    e.exit_code()
+}
 
-    match worktree::copy_repo_env(&repo.root, &wt_path) {
-        Ok(true) => log.infof("copied .env from repo root"),
-        Ok(false) => {}
-        Err(e) => log.infof(&format!("copy .env failed: {e}")),
+// prepare checks the options and resolves them into the worktrees to create and the settings they
+// share, then fetches. Nothing is locked or created yet.
+fn prepare(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    cfg: &Config,
+    opts: &NewOpts<'_>,
+) -> Result<(Vec<Target>, Plan), Error> {
+    let wt_name = worktree::slug(opts.name);
+
+    let mut br = opts.branch.unwrap_or("").trim().to_string();
+    if br.is_empty() {
+        br = match opts.pr {
+            Some(pr) => format!("pr/{pr}"),
+            None => opts.name.to_string(),
+        };
     }
+    br = worktree::normalize_branch(&br);
 
-    let sb = opts.sb_mode.trim().to_lowercase();
-    let install = opts.install_mode.trim().to_lowercase();
-    let db_mode = opts.db_mode.trim().to_lowercase();
+    let worktrees_dir = repo.root.join(".worktrees");
+    let targets: Vec<Target> = match opts.count {
+        None => vec![Target {
+            path: worktrees_dir.join(&wt_name),
+            name: wt_name.clone(),
+            branch: br.clone(),
+        }],
+        Some(0) => return Err(Error::Invalid("--count must be at least 1".into())),
+        Some(n) => (1..=n)
+            .map(|i| {
+                let name = format!("{wt_name}-{i}");
+                Target {
+                    path: worktrees_dir.join(&name),
+                    name,
+                    branch: format!("{br}-{i}"),
+                }
+            })
+            .collect(),
+    };
 
-    if sb == "true" || (sb == "auto" && supabase::has_config(&wt_path)) {
-        log.infof("supabase detected: patching config for isolation (project_id + ports)");
-        if let Err(e) = supabase::patch_config(&wt_path, &wt_name, offset) {
-            log.errorf(&format!("supabase patch failed: {e}"));
-            return Ok(1);
+    let profile_name = opts.profile.or(cfg.default_profile.as_deref());
+    let profile = match profile_name {
+        None => Profile::default(),
+        Some(name) => match cfg.profiles.get(name) {
+            Some(p) => p.clone(),
+            None => return Err(Error::UnknownProfile(name.to_string())),
+        },
+    };
+
+    let mut sparse: Vec<String> = profile.sparse.clone();
+    if let Some(profile) = opts.sparse_profile {
+        let Some(paths) = cfg.sparse_profiles.get(profile) else {
+            return Err(Error::Invalid(format!(
+                "unknown sparse profile: \"{profile}\""
+            )));
+        };
+        sparse.extend(paths.iter().cloned());
+    }
+    for p in opts.sparse {
+        if !sparse.contains(p) {
+            sparse.push(p.clone());
         }
-        let _ = run_cmd(
-            &wt_path,
-            "git",
-            &["update-index", "--skip-worktree", "supabase/config.toml"],
-        );
     }
 
-    if install == "true" || (install == "auto" && pm::has_project(&wt_path)) {
-        if let Some((cmd, args)) = pm::detect_install_command(&wt_path) {
-            log.infof(&format!("install: {cmd} {}", args.join(" ")));
-            let arg_refs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-            if let Err(e) = run_cmd(&wt_path, &cmd, &arg_refs) {
-                log.errorf(&format!("install failed: {e}"));
-                return Ok(1);
+    // The profile's labels come first, so --label overrides them.
+    let mut meta = opts.meta.clone();
+    let labels = profile.labels.iter().map(|(k, v)| format!("{k}={v}"));
+    meta.label = labels.chain(meta.label).collect();
+    apply_meta(&mut Allocation::default(), &meta).map_err(|e| Error::Invalid(e.to_string()))?;
+
+    // Resolve --ttl to a timestamp now: an overflow must fail before anything is created.
+    let expires_at = match opts.ttl.map(parse_duration).transpose() {
+        Ok(None) => None,
+        Ok(Some(d)) => match chrono::Utc::now().checked_add_signed(d) {
+            Some(t) => Some(t.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            None => {
+                return Err(Error::Invalid(format!(
+                    "--ttl: \"{}\" is too far in the future",
+                    opts.ttl.unwrap_or_default()
+                )))
             }
-        } else {
-            log.infof("no package manager detected; skipping install");
+        },
+        Err(e) => return Err(Error::Invalid(format!("--ttl: {e}"))),
+    };
+
+    let remote = worktree::resolve_remote(&repo.root, opts.remote.or(cfg.remote.as_deref()))
+        .map_err(|e| Error::Invalid(e.to_string()))?;
+    let fetch = !opts.no_fetch && cfg.fetch.unwrap_or(true);
+
+    let mut plan = Plan {
+        from_ref: opts
+            .from_ref
+            .or(profile.from.as_deref())
+            .unwrap_or("HEAD")
+            .to_string(),
+        pr: opts.pr,
+        pr_head: None,
+        remote,
+        fetch,
+        sparse,
+        expires_at,
+        group: opts.count.map(|_| wt_name.clone()),
+        isolation: cfg.isolation.unwrap_or_default(),
+        services: proxy::services(&repo.root),
+        proxy_port: cfg.proxy_port.unwrap_or(proxy::DEFAULT_PORT),
+        meta,
+        profile: profile_name.map(str::to_string),
+        files: profile.files.clone(),
+        keep_on_failure: opts.keep_on_failure,
+        sb: mode(opts.sb_mode, profile.supabase.as_deref()),
+        install: mode(opts.install_mode, profile.install.as_deref()),
+        db_mode: mode(opts.db_mode, profile.db.as_deref()),
+    };
+
+    // PR checkouts fetch only the PR head. Fetch before taking the lock: it may be slow.
+    if let (true, Some(r)) = (plan.fetch, plan.remote.as_deref()) {
+        match plan.pr {
+            Some(pr) => plan.pr_head = maybe_fetch_pr(log, repo, cfg, r, pr),
+            None => maybe_fetch(log, repo, cfg, r),
         }
     }
 
-    if sb == "true" || (sb == "auto" && supabase::has_config(&wt_path)) {
-        if which("supabase").is_none() {
-            log.infof("supabase CLI not found; skipping `supabase start` (config patched)");
-        } else {
-            log.infof("supabase start (isolated ports, project_id suffix)");
-            if let Err(e) = run_cmd(&wt_path, "supabase", &["start"]) {
-                log.errorf(&format!("supabase start failed: {e}"));
-                return Ok(1);
-            }
-        }
+    if plan.isolation == Isolation::Loopback && !cfg!(target_os = "linux") {
+        log.warnf(
+            "isolation \"loopback\": only 127.0.0.1 is up by default on this OS; add an alias for \
+             each worktree's WRT_HOST (e.g. `sudo ifconfig lo0 alias 127.0.1.1 up`)",
+        );
     }
 
-    if db_mode != "false" {
-        if let Err(e) = maybe_run_db_setup(log, repo, &alloc, &wt_path, &db_mode) {
-            log.errorf(&format!("db setup failed: {e}"));
-            return Ok(1);
+    Ok((targets, plan))
+}
+
+// lock_for takes the state lock to create `targets`, with state reloaded, after checking their
+// names are free and the worktree limit allows them.
+//
+// Hold the lock while blocks are handed out and checkouts created, so concurrent `wrt new` calls
+// can't pick the same block. `git worktree add` itself runs one at a time: git fails (rather than
+// waits) when another process holds its config lock.
+fn lock_for(
+    repo: &gitx::Repo,
+    st: &mut State,
+    targets: &[Target],
+    max_worktrees: Option<usize>,
+) -> Result<FileLock, Error> {
+    let lock = relock(repo, st)?;
+    for t in targets {
+        if st.allocations.contains_key(&t.name) {
+            return Err(Error::AlreadyExists(t.name.clone()));
+        }
+        if st.archived.contains_key(&t.name) {
+            return Err(Error::Archived(t.name.clone()));
         }
     }
+    if let Some(max) = max_worktrees {
+        if st.allocations.len() + targets.len() > max {
+            return Err(Error::LimitReached {
+                existing: st.allocations.len(),
+                max,
+            });
+        }
+    }
+    Ok(lock)
+}
 
-    if opts.emit_cd {
-        println!("cd {}", sh_quote(&wt_path.to_string_lossy()));
+// create checks out one worktree and records it in state with its port block. Call with the state
+// lock held. Completed steps go into `journal`, to be undone if this or a later step fails.
+fn create(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    st: &mut State,
+    plan: &Plan,
+    t: &Target,
+    journal: &mut Journal,
+) -> Result<Allocation, Error> {
+    let wt_name = &t.name;
+    let wt_path = &t.path;
+    let br = &t.branch;
+
+    let block = st
+        .allocate_block()
+        .map_err(|e| Error::failed(format!("allocate port block: {e}")))?;
+
+    log.infof(&format!(
+        "creating worktree: {wt_name} ({br}) at {}",
+        wt_path.display()
+    ));
+
+    worktree::ensure_dir(wt_path.parent().unwrap())?;
+
+    if !plan.sparse.is_empty() {
+        log.infof(&format!(
+            "sparse-checkout (cone): {}",
+            plan.sparse.join(" ")
+        ));
+    }
+    let add_opts = worktree::AddOpts {
+        branch: br,
+        from_ref: &plan.from_ref,
+        remote: plan.remote.as_deref(),
+        sparse: &plan.sparse,
+    };
+    // An existing branch is the user's; only one made here goes on rollback.
+    let had_branch = worktree::branch_exists(&repo.root, br);
+    let added = match plan.pr {
+        Some(pr) => worktree::add_pr(
+            &repo.root,
+            wt_path,
+            &add_opts,
+            pr,
+            plan.fetch,
+            plan.pr_head.as_deref(),
+        )
+        .map(Some),
+        None => worktree::add(&repo.root, wt_path, &add_opts).map(|()| None),
+    };
+    if !had_branch && worktree::branch_exists(&repo.root, br) {
+        journal.record(Undo::Branch(br.clone()));
+    }
+    match (added, plan.pr) {
+        (Ok(Some(worktree::PrHead::Fetched(src))), Some(pr)) => {
+            log.infof(&format!("checked out #{pr} ({src}) on branch {br}"))
+        }
+        (Ok(Some(worktree::PrHead::Cached)), Some(pr)) => log.warnf(&format!(
+            "#{pr} was not fetched; using the copy from an earlier fetch (may be stale)"
+        )),
+        (Ok(_), _) => {}
+        (Err(e), _) => return Err(Error::Git(format!("git worktree add failed: {e}"))),
     }
+    journal.record(Undo::Worktree(wt_path.clone()));
 
-    Ok(0)
+    // "HEAD" means nothing later on; record the branch it pointed at. PRs are compared against
+    // the default branch.
+    let from_ref = match plan.pr {
+        Some(_) => worktree::default_branch(&repo.root, plan.remote.as_deref()),
+        None if plan.from_ref == "HEAD" => worktree::head_branch(&repo.root),
+        None => Some(plan.from_ref.clone()),
+    };
+
+    let now = chrono::Utc::now();
+    let created_at = now.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let mut alloc = Allocation {
+        name: wt_name.clone(),
+        branch: br.clone(),
+        path: wt_path.to_string_lossy().to_string(),
+        created_at,
+        from_ref,
+        group: plan.group.clone(),
+        sparse: plan.sparse.clone(),
+        pr: plan.pr,
+        expires_at: plan.expires_at.clone(),
+        ..Default::default()
+    };
+    alloc.assign_block(block, plan.isolation);
+    alloc.urls = proxy::urls(wt_name, &plan.services, plan.proxy_port);
+    // Labels were validated before anything was created.
+    alloc.profile = plan.profile.clone();
+    apply_meta(&mut alloc, &plan.meta).map_err(|e| Error::Invalid(e.to_string()))?;
+
+    st.allocations.insert(wt_name.clone(), alloc.clone());
+    journal.record(Undo::State(wt_name.clone()));
+    st.save(&repo.common_dir)
+        .map_err(|e| Error::failed(format!("state save failed: {e}")))?;
+
+    Ok(alloc)
 }
 
-fn maybe_run_db_setup(
+// setup runs the provisioning steps (.wrt.env, .env copy, supabase, install, DB setup) outside
+// the state lock. `who` prefixes log lines when several worktrees are set up at once.
+fn setup(
     log: &ui::Logger,
     repo: &gitx::Repo,
+    plan: &Plan,
     alloc: &Allocation,
-    wt_path: &Path,
-    db_mode: &str,
-) -> Result<()> {
-    let mut kind_hint: Option<String> = None;
-    let mut reset_cmd: Option<Vec<String>> = None;
-
-    let cfg_path = repo.root.join(".wrt.json");
-    if cfg_path.exists() {
-        if let Ok(s) = fs::read_to_string(&cfg_path) {
-            if let Ok(d) = serde_json::from_str::<codex::Discovery>(&s) {
-                if d.database.detected {
-                    kind_hint = d.database.kind.clone();
-                }
-                reset_cmd = d.database.reset_command.clone();
-            } else {
-                log.infof("could not parse .wrt.json; skipping DB setup from config");
-            }
+    who: &str,
+    interactive: bool,
+    journal: &mut Journal,
+) -> Provisioned {
+    let opts = StepOpts {
+        supabase: &plan.sb,
+        install: &plan.install,
+        db: &plan.db_mode,
+        files: &plan.files,
+        interactive,
+    };
+    let p = provision(log, repo, alloc, &Step::ALL, &opts, who);
+    // A start that fails half way may leave containers behind; stop them too.
+    if p.ran(Step::Supabase) {
+        journal.record(Undo::Services(PathBuf::from(&alloc.path)));
+    }
+    p
+}
+
+// Undo takes back one completed step of creating a worktree.
+enum Undo {
+    /// A branch `git worktree add` created (an existing one is left alone).
+    Branch(String),
+    /// The checkout, and with it .wrt.env, the copied files and the supabase config patch.
+    Worktree(PathBuf),
+    /// The state entry, which holds the port block.
+    State(String),
+    /// Containers of a `supabase start`.
+    Services(PathBuf),
+}
+
+impl fmt::Display for Undo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Undo::Branch(b) => write!(f, "delete branch {b}"),
+            Undo::Worktree(p) => write!(f, "remove worktree {}", p.display()),
+            Undo::State(name) => write!(f, "forget \"{name}\" in state"),
+            Undo::Services(_) => write!(f, "stop supabase"),
         }
     }
+}
+
+// Journal records the steps of one worktree's creation as they complete, so a failure can undo
+// them newest first.
+#[derive(Default)]
+struct Journal(Vec<Undo>);
 
-    if reset_cmd.is_none() && db::has_supabase_seed_or_migrations(wt_path) {
-        kind_hint = kind_hint.or(Some("supabase".into()));
-        reset_cmd = Some(vec!["supabase".into(), "db".into(), "reset".into()]);
+impl Journal {
+    fn record(&mut self, u: Undo) {
+        self.0.push(u);
     }
 
-    let Some(argv) = reset_cmd else {
-        let mut hints: Vec<&str> = Vec::new();
-        if db::has_prisma_schema(wt_path) {
-            hints.push("prisma");
-        }
-        if db::has_sqlx_markers(wt_path) {
-            hints.push("sqlx");
-        }
-        if !hints.is_empty() {
-            log.infof(&format!(
-                "db tooling detected ({}) but no reset command known; run `wrt init` to generate .wrt.json or use `wrt db <name> seed|migrate`",
-                hints.join(", ")
-            ));
+    // rollback undoes every recorded step. A step that can't be undone is reported and the
+    // rest still run.
+    fn rollback(self, log: &ui::Logger, repo: &gitx::Repo, st: &mut State) {
+        for u in self.0.into_iter().rev() {
+            let res = match &u {
+                Undo::Branch(b) => run_cmd(&repo.root, "git", &["branch", "-D", b]),
+                Undo::Worktree(p) => worktree::remove(&repo.root, p, true),
+                Undo::State(name) => {
+                    st.allocations.remove(name);
+                    st.save(&repo.common_dir)
+                }
+                Undo::Services(p) => services::stop(log, p).map(|_| ()),
+            };
+            if let Err(e) = res {
+                log.warnf(&format!("rollback: could not {u}: {e}"));
+            }
         }
-        return Ok(());
-    };
-
-    if argv.is_empty() {
-        return Ok(());
     }
+}
 
-    let label = kind_hint.as_deref().unwrap_or("database");
-    let cmd_str = argv.join(" ");
+// abandon deals with a worktree whose creation failed: it rolls back what was done, or with
+// --keep-on-failure leaves it in place and marks it failed in state. Call with the state lock
+// held.
+fn abandon(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    st: &mut State,
+    plan: &Plan,
+    name: &str,
+    journal: Journal,
+    err: &str,
+) {
+    if !plan.keep_on_failure {
+        log.infof(&format!("rolling back {name}"));
+        journal.rollback(log, repo, st);
+        return;
+    }
+    let Some(a) = st.allocations.get_mut(name) else {
+        // It failed before it was recorded: there is nothing to mark.
+        return;
+    };
+    a.failed = Some(err.to_string());
+    if let Err(e) = st.save(&repo.common_dir) {
+        log.errorf(&format!("state save failed: {e}"));
+        return;
+    }
+    log.infof(&format!(
+        "kept {name} (--keep-on-failure); `wrt setup {name}` retries, `wrt rm {name} --force` removes it"
+    ));
+}
 
-    match db_mode {
-        "true" => {
-            log.infof(&format!("{label}: running db setup: {cmd_str}"));
-            run_argv_with_wrt_env(wt_path, alloc, &argv)?;
-        }
-        "auto" => {
-            if !std::io::stdin().is_terminal() {
-                log.infof(&format!(
-                    "{label}: db setup available ({cmd_str}) but skipping in non-interactive mode; rerun with `--db true` to run"
-                ));
-                return Ok(());
-            }
+// relock takes the state lock (again, after the unlocked setup steps), with state reloaded.
+fn relock(repo: &gitx::Repo, st: &mut State) -> Result<FileLock, Error> {
+    let lock = State::lock(&repo.common_dir)?;
+    st.reload(&repo.common_dir)
+        .map_err(|e| Error::failed(format!("state load failed: {e}")))?;
+    Ok(lock)
+}
 
-            if !confirm(&format!(
-                "{label}: run DB reset/seed now? This may delete local data. [{cmd_str}] (y/N): "
-            ))? {
-                log.infof(&format!("{label}: skipping db setup"));
-                return Ok(());
-            }
+// maybe_fetch refreshes remote refs before branch lookup. Failures are not fatal: being offline
+// should not block creating a worktree from refs we already have.
+fn maybe_fetch(log: &ui::Logger, repo: &gitx::Repo, cfg: &Config, remote: &str) {
+    let timeout = Duration::from_secs(cfg.fetch_timeout_secs.unwrap_or(60));
+    match worktree::fetch_shared(&repo.root, &repo.common_dir, remote, timeout) {
+        Ok(worktree::Fetch::Done) => {}
+        Ok(worktree::Fetch::Reused) => log.infof(&format!(
+            "fetch: reusing a fetch of {remote} that just completed"
+        )),
+        Err(e) => log.warnf(&format!(
+            "git fetch {remote} failed ({e}); continuing with existing refs"
+        )),
+    }
+}
 
-            log.infof(&format!("{label}: running db setup: {cmd_str}"));
-            run_argv_with_wrt_env(wt_path, alloc, &argv)?;
-        }
-        "false" => {}
-        _ => {
-            log.infof("invalid --db value (expected auto|true|false); skipping db setup");
+// maybe_fetch_pr fetches the head of `pr` and returns the remote ref it came from. Like
+// maybe_fetch, failures only warn: `add_pr` falls back to a head fetched earlier.
+fn maybe_fetch_pr(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    cfg: &Config,
+    remote: &str,
+    pr: u64,
+) -> Option<String> {
+    let timeout = Duration::from_secs(cfg.fetch_timeout_secs.unwrap_or(60));
+    match worktree::fetch_pr(&repo.root, &repo.common_dir, remote, pr, timeout) {
+        Ok(src) => src,
+        Err(e) => {
+            log.warnf(&format!(
+                "fetching #{pr} from {remote} failed ({e}); continuing with existing refs"
+            ));
+            None
         }
     }
-
-    Ok(())
 }
@@ -0,0 +1,66 @@
+use anyhow::Result;
+use std::net::TcpListener;
+
+use crate::config::Config;
+use crate::gitx;
+use crate::proxy::{self, Table};
+use crate::ui;
+
+// cmd_proxy serves every worktree's services under stable hostnames on one local port, so
+// http://a4.web.localhost:8080 reaches agent 4's web app wherever its offset put it.
+pub fn cmd_proxy(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    cfg: &Config,
+    port: Option<u16>,
+    routes_only: bool,
+) -> Result<i32> {
+    let port = port.or(cfg.proxy_port).unwrap_or(proxy::DEFAULT_PORT);
+    let table = Table::new(&repo.root, &repo.common_dir);
+    let routes = match table.current() {
+        Ok(r) => r,
+        Err(e) => {
+            log.errorf(&format!("{e}"));
+            return Ok(1);
+        }
+    };
+
+    if routes_only {
+        for (host, upstream) in &routes {
+            println!("http://{host}:{port}  ->  {upstream}");
+        }
+        return Ok(0);
+    }
+
+    if proxy::services(&repo.root).is_empty() {
+        log.warnf(
+            "no services with a base_port in .wrt.json (run `wrt init`); nothing to route yet",
+        );
+    }
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(l) => l,
+        Err(e) => {
+            log.errorf(&format!("listen on 127.0.0.1:{port}: {e}"));
+            return Ok(1);
+        }
+    };
+    if port != cfg.proxy_port.unwrap_or(proxy::DEFAULT_PORT) {
+        log.warnf(&format!(
+            "WRT_URL_* use port {}; set \"proxy_port\": {port} in config to match",
+            cfg.proxy_port.unwrap_or(proxy::DEFAULT_PORT)
+        ));
+    }
+    log.infof(&format!(
+        "proxy listening on 127.0.0.1:{port} ({} routes; reloads when worktrees change, Ctrl-C stops)",
+        routes.len()
+    ));
+    for (host, upstream) in &routes {
+        log.infof(&format!("  http://{host}:{port} -> {upstream}"));
+    }
+
+    if let Err(e) = proxy::serve(listener, &table) {
+        log.errorf(&format!("proxy: {e}"));
+        return Ok(1);
+    }
+    Ok(0)
+}
@@ -2,6 +2,7 @@ use anyhow::Result;
 use std::path::Path;
 
 use crate::gitx;
+use crate::registry;
 use crate::state::State;
 use crate::ui;
 use crate::util::run_cmd;
@@ -13,25 +14,31 @@ pub fn cmd_prune(log: &ui::Logger, repo: &gitx::Repo, st: &mut State) -> Result<
         return Ok(1);
     }
 
-    let mut removed = 0;
-    let keys: Vec<String> = st.allocations.keys().cloned().collect();
-    for k in keys {
-        let missing = st
-            .allocations
-            .get(&k)
-            .map(|a| !Path::new(&a.path).exists())
-            .unwrap_or(false);
-        if missing {
-            st.allocations.remove(&k);
-            removed += 1;
+    let pruned = st.update(&repo.common_dir, |s| {
+        let before = s.allocations.len();
+        s.allocations.retain(|_, a| Path::new(&a.path).exists());
+        before - s.allocations.len()
+    });
+    match pruned {
+        Ok(0) => {}
+        Ok(removed) => log.infof(&format!("state: removed {removed} missing worktrees")),
+        Err(e) => {
+            log.errorf(&format!("state save failed: {e}"));
+            return Ok(1);
         }
     }
 
-    if removed > 0 {
-        log.infof(&format!("state: removed {removed} missing worktrees"));
-        if let Err(e) = st.save(&repo.common_dir) {
-            log.errorf(&format!("state save failed: {e}"));
-            return Ok(1);
+    if let Some(link) = &st.registry {
+        match registry::forget_missing(&link.path) {
+            Ok(roots) => {
+                for r in roots {
+                    log.infof(&format!("registry: dropped {r} (repo no longer exists)"));
+                }
+            }
+            Err(e) => {
+                log.errorf(&format!("registry: {e}"));
+                return Ok(1);
+            }
         }
     }
 
@@ -1,11 +1,12 @@
 use anyhow::Result;
 use std::path::Path;
 
+use crate::api::{Error, RemoveOpts};
 use crate::gitx;
-use crate::state::State;
-use crate::supabase;
+use crate::services;
+use crate::state::{Allocation, State};
 use crate::ui;
-use crate::util::{run_cmd, which};
+use crate::util::run_cmd;
 use crate::worktree;
 
 pub fn cmd_rm(
@@ -13,47 +14,150 @@ pub fn cmd_rm(
     repo: &gitx::Repo,
     st: &mut State,
     name: &str,
-    force: bool,
-    delete_branch: bool,
+    opts: &RemoveOpts,
 ) -> Result<i32> {
+    match remove_worktree(log, repo, st, name, opts) {
+        Ok(()) => Ok(0),
+        Err(Error::WouldLoseWork { name, at_risk }) => {
+            log.errorf(&format!("refusing to remove \"{name}\"; this would lose:"));
+            for line in &at_risk {
+                log.detailf(line);
+            }
+            log.errorf("pass --backup to save it under refs/wrt-archive/ first, or --force");
+            Ok(1)
+        }
+        Err(e) => {
+            log.errorf(&format!("{e}"));
+            Ok(e.exit_code())
+        }
+    }
+}
+
+// remove_worktree stops the worktree's services and removes its checkout, state entry and (with
+// `delete_branch`) branch. Without `force` or `backup` it removes nothing that would lose work.
+// The state entry is dropped under the state lock, so concurrent `wrt new` runs keep theirs.
+pub fn remove_worktree(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    st: &mut State,
+    name: &str,
+    opts: &RemoveOpts,
+) -> Result<(), Error> {
     let key = worktree::slug(name);
     let Some(a) = st.allocations.get(&key).cloned() else {
-        log.errorf(&format!("unknown worktree: \"{key}\""));
-        return Ok(2);
+        return Err(Error::UnknownWorktree(key));
     };
 
+    let wt_path = Path::new(&a.path);
+    let remote = worktree::resolve_remote(&repo.root, None).unwrap_or(None);
+
+    if opts.backup {
+        let push_to = remote.as_deref().filter(|_| opts.push);
+        backup_branch(log, repo, &a, push_to)
+            .map_err(|e| Error::Git(format!("backup failed: {e}")))?;
+    } else if !opts.force {
+        let at_risk = at_risk(repo, &a, opts.delete_branch, remote.as_deref())
+            .map_err(|e| Error::failed(format!("safety check failed: {e}")))?;
+        if !at_risk.is_empty() {
+            return Err(Error::WouldLoseWork {
+                name: a.name,
+                at_risk,
+            });
+        }
+    }
+
     log.infof(&format!("removing worktree: {} ({})", a.name, a.path));
 
-    let wt_path = Path::new(&a.path);
-    if wt_path.exists() && supabase::has_config(wt_path) && which("supabase").is_some() {
-        log.infof("stopping supabase containers");
-        if let Err(e) = run_cmd(wt_path, "supabase", &["stop"]) {
-            log.errorf(&format!("supabase stop failed: {e}"));
-            if !force {
-                return Ok(1);
-            }
-            log.infof("continuing anyway (--force)");
+    if let Err(e) = services::stop(log, wt_path) {
+        if !opts.force {
+            return Err(Error::failed(format!("supabase stop failed: {e}")));
         }
+        log.errorf(&format!("supabase stop failed: {e}"));
+        log.infof("continuing anyway (--force)");
+    }
+
+    // A backup captured the uncommitted changes, so the checkout can go even if dirty.
+    worktree::remove(&repo.root, wt_path, opts.force || opts.backup)
+        .map_err(|e| Error::Git(format!("git worktree remove failed: {e}")))?;
+
+    if opts.delete_branch {
+        log.infof(&format!("deleting branch: {}", a.branch));
+        run_cmd(&repo.root, "git", &["branch", "-D", &a.branch])
+            .map_err(|e| Error::Git(format!("branch delete failed: {e}")))?;
     }
 
-    if let Err(e) = worktree::remove(&repo.root, wt_path, force) {
-        log.errorf(&format!("git worktree remove failed: {e}"));
-        return Ok(1);
+    st.update(&repo.common_dir, |s| s.allocations.remove(&key))
+        .map_err(Error::from)?;
+
+    Ok(())
+}
+
+// at_risk lists work that exists only in this worktree or branch: uncommitted files are always at
+// risk; commits that are on no remote and not in the default branch, and stashes made on the
+// branch, only when the branch is deleted too.
+fn at_risk(
+    repo: &gitx::Repo,
+    a: &Allocation,
+    delete_branch: bool,
+    remote: Option<&str>,
+) -> Result<Vec<String>> {
+    let mut out = Vec::new();
+    let wt_path = Path::new(&a.path);
+
+    if wt_path.exists() {
+        for f in worktree::dirty_files(wt_path)? {
+            out.push(format!("uncommitted: {f}"));
+        }
     }
 
     if delete_branch {
-        log.infof(&format!("deleting branch: {}", a.branch));
-        if let Err(e) = run_cmd(&repo.root, "git", &["branch", "-D", &a.branch]) {
-            log.errorf(&format!("branch delete failed: {e}"));
-            return Ok(1);
+        let base = worktree::default_branch(&repo.root, remote);
+        for c in worktree::unique_commits(&repo.root, &a.branch, base.as_deref())? {
+            out.push(format!("unpushed commit: {c}"));
+        }
+        for s in worktree::branch_stashes(&repo.root, &a.branch)? {
+            out.push(format!("stash: {s}"));
         }
     }
 
-    st.allocations.remove(&key);
-    if let Err(e) = st.save(&repo.common_dir) {
-        log.errorf(&format!("state save failed: {e}"));
-        return Ok(1);
+    Ok(out)
+}
+
+// backup_branch saves the branch tip, plus a snapshot of uncommitted changes if any, under
+// refs/wrt-archive/, and pushes them to `push_to` if given.
+fn backup_branch(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    a: &Allocation,
+    push_to: Option<&str>,
+) -> Result<()> {
+    let mut refs: Vec<String> = Vec::new();
+
+    let head_ref = format!("{}/{}", worktree::ARCHIVE_REF_PREFIX, a.branch);
+    worktree::update_ref(&repo.root, &head_ref, &format!("refs/heads/{}", a.branch))?;
+    refs.push(head_ref);
+
+    let wt_path = Path::new(&a.path);
+    if wt_path.exists() {
+        let msg = format!("wrt: uncommitted changes of {}", a.name);
+        if let Some(commit) = worktree::snapshot(wt_path, &msg)? {
+            let wip_ref = format!("{}/{}-wip", worktree::ARCHIVE_REF_PREFIX, a.branch);
+            worktree::update_ref(&repo.root, &wip_ref, &commit)?;
+            refs.push(wip_ref);
+        }
     }
 
-    Ok(0)
+    for r in &refs {
+        log.infof(&format!("backup: {r}"));
+    }
+    match push_to {
+        Some(remote) => {
+            for r in &refs {
+                worktree::push_ref(&repo.root, remote, r)?;
+            }
+            log.infof(&format!("backup pushed to {remote}"));
+        }
+        None => log.infof("backup refs are local only"),
+    }
+    Ok(())
 }
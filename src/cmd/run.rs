@@ -35,10 +35,7 @@ pub fn cmd_run(log: &ui::Logger, st: &State, name: &str, command: &[String]) ->
     let cmd_args = &command[1..];
 
     let mut envs: Vec<(String, String)> = env::vars().collect();
-    envs.push(("WRT_NAME".into(), a.name.clone()));
-    envs.push(("WRT_BRANCH".into(), a.branch.clone()));
-    envs.push(("WRT_PORT_BLOCK".into(), a.block.to_string()));
-    envs.push(("WRT_PORT_OFFSET".into(), a.offset.to_string()));
+    envs.extend(worktree::env_file_vars(a));
 
     log.infof(&format!(
         "run: {cmd} {} (in {})",
@@ -0,0 +1,424 @@
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::Path;
+
+use crate::codex;
+use crate::config::{Config, Profile};
+use crate::db;
+use crate::gitx;
+use crate::pm;
+use crate::state::{Allocation, State};
+use crate::supabase;
+use crate::ui;
+use crate::util::{confirm, run_argv_with_wrt_env, run_cmd, which};
+use crate::worktree;
+
+// Step is one provisioning step of a worktree. `wrt new` runs them all, in this order; `wrt setup`
+// re-runs any of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Step {
+    /// Write .wrt.env.
+    Env,
+    /// Copy .env from the repo root (an existing one is kept).
+    Files,
+    /// Patch supabase/config.toml for isolation, then `supabase start`.
+    Supabase,
+    /// Install dependencies with the detected package manager.
+    Install,
+    /// DB reset/seed from .wrt.json (or `supabase db reset`).
+    Db,
+}
+
+impl Step {
+    pub const ALL: [Step; 5] = [
+        Step::Env,
+        Step::Files,
+        Step::Supabase,
+        Step::Install,
+        Step::Db,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Step::Env => "env",
+            Step::Files => "files",
+            Step::Supabase => "supabase",
+            Step::Install => "install",
+            Step::Db => "db",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Step> {
+        Step::ALL.into_iter().find(|step| step.name() == s)
+    }
+}
+
+// StepOpts says how the steps run: the auto|true|false modes of the optional ones (`--supabase`,
+// `--install`, `--db`), the profile's files to copy and whether DB setup may prompt.
+pub struct StepOpts<'a> {
+    pub supabase: &'a str,
+    pub install: &'a str,
+    pub db: &'a str,
+    pub files: &'a [String],
+    pub interactive: bool,
+}
+
+// mode resolves an auto|true|false setting: the flag, else the profile's, else auto.
+pub fn mode(flag: Option<&str>, profile: Option<&str>) -> String {
+    flag.or(profile).unwrap_or("auto").trim().to_lowercase()
+}
+
+// Provisioned is how far a run of steps got.
+#[derive(Default)]
+pub struct Provisioned {
+    /// Steps that completed: they ran, or ("auto") found nothing to do.
+    pub done: Vec<Step>,
+    /// The step that failed and why; the steps after it did not run.
+    pub failed: Option<(Step, anyhow::Error)>,
+}
+
+impl Provisioned {
+    // ran reports whether `step` was started, whether or not it completed.
+    pub fn ran(&self, step: Step) -> bool {
+        self.done.contains(&step) || self.failed.as_ref().is_some_and(|(s, _)| *s == step)
+    }
+
+    // note records the outcome on the allocation: completed steps are added, a failed one is no
+    // longer complete, and `failed` is set, or cleared when everything asked for went fine.
+    pub fn note(&self, a: &mut Allocation) {
+        let mut steps: Vec<Step> = a.steps.iter().filter_map(|s| Step::parse(s)).collect();
+        steps.extend(&self.done);
+        if let Some((failed, _)) = &self.failed {
+            steps.retain(|s| s != failed);
+        }
+        steps.sort();
+        steps.dedup();
+        a.steps = steps.iter().map(|s| s.name().to_string()).collect();
+        a.failed = self.failed.as_ref().map(|(_, e)| e.to_string());
+    }
+}
+
+pub struct SetupOpts<'a> {
+    pub name: &'a str,
+    /// Steps to run (empty: all of them).
+    pub steps: &'a [String],
+    /// Skip the steps before this one.
+    pub from_step: Option<&'a str>,
+    /// auto|true|false (default: the worktree's profile's, else auto); likewise sb_mode and db_mode.
+    pub install_mode: Option<&'a str>,
+    pub sb_mode: Option<&'a str>,
+    pub db_mode: Option<&'a str>,
+    /// Whether DB setup may prompt (a terminal on stdin).
+    pub interactive: bool,
+}
+
+pub fn cmd_setup(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    cfg: &Config,
+    st: &mut State,
+    opts: SetupOpts<'_>,
+) -> Result<i32> {
+    let key = worktree::slug(opts.name);
+    let Some(a) = st.allocations.get(&key).cloned() else {
+        log.errorf(&format!("unknown worktree: \"{key}\""));
+        return Ok(2);
+    };
+
+    let mut steps: Vec<Step> = Vec::new();
+    for s in opts.steps {
+        let Some(step) = Step::parse(s.trim()) else {
+            log.errorf(&format!(
+                "unknown step: \"{s}\" (expected {})",
+                step_names()
+            ));
+            return Ok(2);
+        };
+        steps.push(step);
+    }
+    if steps.is_empty() {
+        steps = Step::ALL.to_vec();
+    }
+    if let Some(from) = opts.from_step {
+        let Some(from) = Step::parse(from.trim()) else {
+            log.errorf(&format!(
+                "unknown step: \"{from}\" (expected {})",
+                step_names()
+            ));
+            return Ok(2);
+        };
+        steps.retain(|s| *s >= from);
+    }
+    steps.sort();
+    steps.dedup();
+    if steps.is_empty() {
+        log.errorf("no steps to run");
+        return Ok(2);
+    }
+
+    let profile = match &a.profile {
+        None => Profile::default(),
+        Some(name) => cfg.profiles.get(name).cloned().unwrap_or_else(|| {
+            log.warnf(&format!(
+                "profile \"{name}\" of {key} is no longer in config; using the defaults"
+            ));
+            Profile::default()
+        }),
+    };
+    let step_opts = StepOpts {
+        supabase: &mode(opts.sb_mode, profile.supabase.as_deref()),
+        install: &mode(opts.install_mode, profile.install.as_deref()),
+        db: &mode(opts.db_mode, profile.db.as_deref()),
+        files: &profile.files,
+        interactive: opts.interactive,
+    };
+    log.infof(&format!(
+        "setting up {key}: {}",
+        steps
+            .iter()
+            .map(|s| s.name())
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+    let p = provision(log, repo, &a, &steps, &step_opts, "");
+    if let Some((_, e)) = &p.failed {
+        log.errorf(&format!("{e}"));
+    }
+
+    let saved = st.update(&repo.common_dir, |s| {
+        if let Some(a) = s.allocations.get_mut(&key) {
+            p.note(a);
+        }
+    });
+    if let Err(e) = saved {
+        log.errorf(&format!("state save failed: {e}"));
+        return Ok(1);
+    }
+
+    if p.failed.is_some() {
+        return Ok(1);
+    }
+    Ok(0)
+}
+
+fn step_names() -> String {
+    Step::ALL.map(Step::name).join(", ")
+}
+
+// provision runs `steps` in order on the worktree of `a`, stopping at the first that fails. Every
+// step may be run again: it redoes its work rather than piling on top of an earlier run. `who`
+// prefixes log lines when several worktrees are set up at once.
+pub fn provision(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    a: &Allocation,
+    steps: &[Step],
+    opts: &StepOpts<'_>,
+    who: &str,
+) -> Provisioned {
+    let mut out = Provisioned::default();
+    for &step in steps {
+        match run(log, repo, a, step, opts, who) {
+            Ok(true) => out.done.push(step),
+            Ok(false) => {}
+            Err(e) => {
+                out.failed = Some((step, e));
+                break;
+            }
+        }
+    }
+    out
+}
+
+// run runs one step; Ok(false) means it was skipped (turned off, or a DB reset nobody confirmed).
+fn run(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    a: &Allocation,
+    step: Step,
+    opts: &StepOpts<'_>,
+    who: &str,
+) -> Result<bool> {
+    let wt_path = Path::new(&a.path);
+    match step {
+        Step::Env => {
+            worktree::write_env_file(wt_path, a).map_err(|e| anyhow!("write env file: {e}"))?;
+            Ok(true)
+        }
+        Step::Files => {
+            let copied = worktree::copy_repo_env(&repo.root, wt_path)
+                .map_err(|e| anyhow!("copy .env failed: {e}"))?;
+            if copied {
+                log.infof(&format!("{who}copied .env from repo root"));
+            }
+            for f in opts.files {
+                match worktree::copy_from_root(&repo.root, wt_path, f) {
+                    Ok(true) => log.infof(&format!("{who}copied {f} from repo root")),
+                    Ok(false) => log.warnf(&format!(
+                        "{who}{f} does not exist in the repo root; not copied"
+                    )),
+                    Err(e) => return Err(anyhow!("copy {f} failed: {e}")),
+                }
+            }
+            Ok(true)
+        }
+        Step::Supabase => {
+            if !wanted(opts.supabase, supabase::has_config(wt_path)) {
+                return Ok(opts.supabase == "auto");
+            }
+            log.infof(&format!(
+                "{who}supabase detected: patching config for isolation (project_id + {})",
+                if a.host.is_some() { "host" } else { "ports" }
+            ));
+            patch_supabase(wt_path, a).map_err(|e| anyhow!("supabase patch failed: {e}"))?;
+            if which("supabase").is_none() {
+                log.infof(&format!(
+                    "{who}supabase CLI not found; skipping `supabase start` (config patched)"
+                ));
+                return Ok(true);
+            }
+            log.infof(&format!(
+                "{who}supabase start (isolated ports, project_id suffix)"
+            ));
+            run_cmd(wt_path, "supabase", &["start"])
+                .map_err(|e| anyhow!("supabase start failed: {e}"))?;
+            Ok(true)
+        }
+        Step::Install => {
+            if !wanted(opts.install, pm::has_project(wt_path)) {
+                return Ok(opts.install == "auto");
+            }
+            let Some((cmd, args)) = pm::detect_install_command(wt_path) else {
+                log.infof(&format!(
+                    "{who}no package manager detected; skipping install"
+                ));
+                return Ok(true);
+            };
+            log.infof(&format!("{who}install: {cmd} {}", args.join(" ")));
+            let arg_refs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+            run_cmd(wt_path, &cmd, &arg_refs).map_err(|e| anyhow!("install failed: {e}"))?;
+            Ok(true)
+        }
+        Step::Db => maybe_run_db_setup(log, repo, a, wt_path, opts.db, opts.interactive)
+            .map_err(|e| anyhow!("db setup failed: {e}")),
+    }
+}
+
+// wanted reports whether a step with this auto|true|false mode runs.
+fn wanted(mode: &str, detected: bool) -> bool {
+    mode == "true" || (mode == "auto" && detected)
+}
+
+// patch_supabase applies the worktree's isolation to supabase/config.toml. A config patched before
+// is first restored from HEAD, so ports are never shifted twice and upstream changes are picked up;
+// unless it has edits of its own, which are kept (as is, when already patched for this block).
+fn patch_supabase(wt_path: &Path, a: &Allocation) -> Result<()> {
+    if let Some(off) = supabase::patched_offset(wt_path, &a.name)? {
+        if supabase::has_local_edits(wt_path)? {
+            if off == a.offset {
+                return Ok(());
+            }
+            return Err(anyhow!(
+                "supabase/config.toml is patched for offset {off} and has local edits; re-patch it by hand"
+            ));
+        }
+        supabase::restore_config(wt_path)?;
+    }
+    supabase::patch_config(wt_path, &a.name, a.offset, a.host.as_deref())?;
+    let _ = run_cmd(
+        wt_path,
+        "git",
+        &["update-index", "--skip-worktree", "supabase/config.toml"],
+    );
+    Ok(())
+}
+
+fn maybe_run_db_setup(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    alloc: &Allocation,
+    wt_path: &Path,
+    db_mode: &str,
+    interactive: bool,
+) -> Result<bool> {
+    if db_mode == "false" {
+        return Ok(false);
+    }
+
+    let mut kind_hint: Option<String> = None;
+    let mut reset_cmd: Option<Vec<String>> = None;
+
+    let cfg_path = repo.root.join(".wrt.json");
+    if cfg_path.exists() {
+        if let Ok(s) = fs::read_to_string(&cfg_path) {
+            if let Ok(d) = serde_json::from_str::<codex::Discovery>(&s) {
+                if d.database.detected {
+                    kind_hint = d.database.kind.clone();
+                }
+                reset_cmd = d.database.reset_command.clone();
+            } else {
+                log.infof("could not parse .wrt.json; skipping DB setup from config");
+            }
+        }
+    }
+
+    if reset_cmd.is_none() && db::has_supabase_seed_or_migrations(wt_path) {
+        kind_hint = kind_hint.or(Some("supabase".into()));
+        reset_cmd = Some(vec!["supabase".into(), "db".into(), "reset".into()]);
+    }
+
+    let Some(argv) = reset_cmd else {
+        let mut hints: Vec<&str> = Vec::new();
+        if db::has_prisma_schema(wt_path) {
+            hints.push("prisma");
+        }
+        if db::has_sqlx_markers(wt_path) {
+            hints.push("sqlx");
+        }
+        if !hints.is_empty() {
+            log.infof(&format!(
+                "db tooling detected ({}) but no reset command known; run `wrt init` to generate .wrt.json or use `wrt db <name> seed|migrate`",
+                hints.join(", ")
+            ));
+        }
+        return Ok(true);
+    };
+
+    if argv.is_empty() {
+        return Ok(true);
+    }
+
+    let label = kind_hint.as_deref().unwrap_or("database");
+    let cmd_str = argv.join(" ");
+
+    match db_mode {
+        "true" => {
+            log.infof(&format!("{label}: running db setup: {cmd_str}"));
+            run_argv_with_wrt_env(wt_path, alloc, &argv)?;
+        }
+        "auto" => {
+            if !interactive {
+                log.infof(&format!(
+                    "{label}: db setup available ({cmd_str}) but skipping in non-interactive mode; rerun with `--db true` to run"
+                ));
+                return Ok(false);
+            }
+
+            if !confirm(&format!(
+                "{label}: run DB reset/seed now? This may delete local data. [{cmd_str}] (y/N): "
+            ))? {
+                log.infof(&format!("{label}: skipping db setup"));
+                return Ok(false);
+            }
+
+            log.infof(&format!("{label}: running db setup: {cmd_str}"));
+            run_argv_with_wrt_env(wt_path, alloc, &argv)?;
+        }
+        _ => {
+            log.infof("invalid --db value (expected auto|true|false); skipping db setup");
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
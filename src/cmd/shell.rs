@@ -0,0 +1,149 @@
+use anyhow::Result;
+use std::env;
+use std::io::IsTerminal;
+use std::path::Path;
+
+use crate::gitx::Repo;
+use crate::shell;
+use crate::state::State;
+use crate::ui;
+use crate::worktree;
+
+pub fn cmd_shell_init(log: &ui::Logger, shell_name: &str) -> Result<i32> {
+    let Some(script) = shell::init_script(shell_name) else {
+        log.errorf(&format!(
+            "unsupported shell (available: {})",
+            shell::SHELLS.join(", ")
+        ));
+        return Ok(2);
+    };
+    print!("{script}");
+    Ok(0)
+}
+
+// cmd_cd prints the path of the worktree matching `query` (the main workdir without one); the
+// `wrt` function from `wrt shell-init` changes into it.
+pub fn cmd_cd(log: &ui::Logger, repo: &Repo, st: &State, query: Option<&str>) -> Result<i32> {
+    let path = match query.map(str::trim).filter(|q| !q.is_empty()) {
+        None => repo.root.to_string_lossy().to_string(),
+        Some(q) => {
+            let names: Vec<&str> = st.allocations.keys().map(String::as_str).collect();
+            match fuzzy_match(&names, q) {
+                Ok(name) => st.allocations[name].path.clone(),
+                Err(matches) if matches.is_empty() => {
+                    log.errorf(&format!("no worktree matches \"{q}\""));
+                    return Ok(2);
+                }
+                Err(matches) => {
+                    log.errorf(&format!("\"{q}\" is ambiguous: {}", matches.join(", ")));
+                    return Ok(2);
+                }
+            }
+        }
+    };
+    println!("{path}");
+    if std::io::stdout().is_terminal() {
+        log.detailf("to change directory, load the shell integration: eval \"$(wrt shell-init zsh)\" (or bash, fish)");
+    }
+    Ok(0)
+}
+
+// fuzzy_match resolves `query` to one of `names`, trying in turn: the exact (slugged) name, names
+// starting with it, names containing it, and names containing its characters in order. The first
+// rule with exactly one match wins; otherwise the matches of the first rule that had any are
+// returned (empty if none matched at all).
+pub fn fuzzy_match<'a>(names: &[&'a str], query: &str) -> Result<&'a str, Vec<&'a str>> {
+    let slug = worktree::slug(query);
+    let q = query.to_lowercase();
+    let rules: [&dyn Fn(&str) -> bool; 4] = [
+        &|n| n == slug,
+        &|n| n.starts_with(&q) || n.starts_with(&slug),
+        &|n| n.contains(&q) || n.contains(&slug),
+        &|n| is_subsequence(&q, n),
+    ];
+    let mut first: Vec<&str> = Vec::new();
+    for rule in rules {
+        let matches: Vec<&str> = names.iter().copied().filter(|n| rule(n)).collect();
+        if matches.len() == 1 {
+            return Ok(matches[0]);
+        }
+        if first.is_empty() {
+            first = matches;
+        }
+    }
+    Err(first)
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut hay = haystack.chars();
+    needle.chars().all(|c| hay.any(|h| h == c))
+}
+
+// cmd_prompt prints a prompt segment for the worktree the current directory is in, or nothing
+// outside one. It only reads the nearest .wrt.env (no git, no state) so it is cheap enough to run
+// on every prompt.
+pub fn cmd_prompt(format: Option<&str>) -> Result<i32> {
+    let Ok(cwd) = env::current_dir() else {
+        return Ok(0);
+    };
+    let Some(root) = cwd.ancestors().find(|d| d.join(".wrt.env").is_file()) else {
+        return Ok(0);
+    };
+    if let Some(segment) = prompt_segment(root, format) {
+        println!("{segment}");
+    }
+    Ok(0)
+}
+
+fn prompt_segment(root: &Path, format: Option<&str>) -> Option<String> {
+    let vars = worktree::read_env_file(root).ok()?;
+    let get = |k: &str| {
+        vars.iter()
+            .find(|(key, _)| key == k)
+            .map(|(_, v)| v.as_str())
+            .unwrap_or_default()
+    };
+    let name = get("WRT_NAME");
+    if name.is_empty() {
+        return None;
+    }
+    let host = get("WRT_HOST");
+    let default = if host.is_empty() {
+        "{name}+{offset}"
+    } else {
+        "{name}@{host}"
+    };
+    Some(
+        format
+            .unwrap_or(default)
+            .replace("{name}", name)
+            .replace("{branch}", get("WRT_BRANCH"))
+            .replace("{offset}", get("WRT_PORT_OFFSET"))
+            .replace("{block}", get("WRT_PORT_BLOCK"))
+            .replace("{host}", host),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_match_prefers_exact_then_prefix_then_substring_then_subsequence() {
+        let names = ["api", "api-v2", "web", "a-gpt-login-timeout"];
+        assert_eq!(fuzzy_match(&names, "api"), Ok("api"));
+        assert_eq!(fuzzy_match(&names, "api-v"), Ok("api-v2"));
+        assert_eq!(fuzzy_match(&names, "W"), Ok("web"));
+        assert_eq!(fuzzy_match(&names, "login"), Ok("a-gpt-login-timeout"));
+        assert_eq!(
+            fuzzy_match(&names, "a/gpt/login-timeout"),
+            Ok("a-gpt-login-timeout")
+        );
+        assert_eq!(fuzzy_match(&names, "glt"), Ok("a-gpt-login-timeout"));
+        assert_eq!(
+            fuzzy_match(&names, "a"),
+            Err(vec!["api", "api-v2", "a-gpt-login-timeout"])
+        );
+        assert_eq!(fuzzy_match(&names, "zzz"), Err(vec![]));
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::cli::SparseAction;
+use crate::gitx;
+use crate::state::State;
+use crate::ui;
+use crate::worktree;
+
+pub fn cmd_sparse(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    st: &mut State,
+    name: &str,
+    action: SparseAction,
+) -> Result<i32> {
+    let key = worktree::slug(name);
+    let Some(a) = st.allocations.get(&key).cloned() else {
+        log.errorf(&format!("unknown worktree: \"{key}\""));
+        return Ok(2);
+    };
+    let wt_path = Path::new(&a.path);
+    if !wt_path.exists() {
+        log.errorf(&format!("worktree path missing: {}", a.path));
+        return Ok(1);
+    }
+
+    let res = match &action {
+        SparseAction::List => {
+            match worktree::sparse_list(wt_path)? {
+                Some(paths) => {
+                    for p in paths {
+                        println!("{p}");
+                    }
+                }
+                None => println!("(sparse-checkout disabled; full tree)"),
+            }
+            return Ok(0);
+        }
+        SparseAction::Add { paths } => {
+            // `sparse-checkout add` requires sparse-checkout to be enabled already.
+            if worktree::sparse_list(wt_path)?.is_some() {
+                worktree::sparse_add(wt_path, paths)
+            } else {
+                worktree::sparse_set(wt_path, paths)
+            }
+        }
+        SparseAction::Set { paths } => worktree::sparse_set(wt_path, paths),
+        SparseAction::Disable => worktree::sparse_disable(wt_path),
+    };
+    if let Err(e) = res {
+        log.errorf(&format!("{e}"));
+        return Ok(1);
+    }
+
+    let paths = worktree::sparse_list(wt_path)?.unwrap_or_default();
+    let saved = st.update(&repo.common_dir, |s| {
+        if let Some(a) = s.allocations.get_mut(&key) {
+            a.sparse = paths;
+        }
+    });
+    if let Err(e) = saved {
+        log.errorf(&format!("state save failed: {e}"));
+        return Ok(1);
+    }
+    Ok(0)
+}
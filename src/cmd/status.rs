@@ -0,0 +1,195 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::filter;
+use crate::gitx;
+use crate::services;
+use crate::state::{Allocation, State};
+use crate::supabase;
+use crate::ui;
+use crate::util::{fmt_age, which};
+use crate::worktree;
+
+// Status is one worktree's row in `wrt status` (and `wrt ui`).
+#[derive(Clone, Debug, Default, Serialize)]
+pub(super) struct Status {
+    pub name: String,
+    pub branch: String,
+    /// Number of changed or untracked files (None if the checkout is missing).
+    pub dirty: Option<usize>,
+    pub upstream: Option<String>,
+    /// (ahead, behind) relative to the upstream.
+    pub upstream_ab: Option<(u32, u32)>,
+    pub base: Option<String>,
+    /// (ahead, behind) relative to the ref the worktree was created from.
+    pub base_ab: Option<(u32, u32)>,
+    /// Files that would conflict when merging into the default branch (None: not checked).
+    pub conflicts: Option<Vec<String>>,
+    pub last_commit_at: Option<i64>,
+    pub last_commit_subject: Option<String>,
+    /// Whether the worktree's services are up (None: no services, or docker unavailable).
+    pub services: Option<bool>,
+}
+
+pub(super) const COLUMNS: &[&str] = &[
+    "NAME",
+    "STATE",
+    "UPSTREAM",
+    "BASE",
+    "CONFLICTS",
+    "SVC",
+    "AGE",
+    "LAST COMMIT",
+];
+
+impl Status {
+    // cells renders the row as display strings, one per COLUMNS entry.
+    pub fn cells(&self, now: i64) -> Vec<String> {
+        let ab = |v: Option<(u32, u32)>| match v {
+            Some((a, b)) => format!("+{a}/-{b}"),
+            None => "-".to_string(),
+        };
+        let state = match self.dirty {
+            None => "missing".to_string(),
+            Some(0) => "clean".to_string(),
+            Some(n) => format!("dirty:{n}"),
+        };
+        let conflicts = match &self.conflicts {
+            None => "?".to_string(),
+            Some(c) if c.is_empty() => "none".to_string(),
+            Some(c) => c.len().to_string(),
+        };
+        let svc = match self.services {
+            None => "-",
+            Some(true) => "up",
+            Some(false) => "down",
+        };
+        let age = self
+            .last_commit_at
+            .map(|t| fmt_age(now - t))
+            .unwrap_or_else(|| "-".into());
+        vec![
+            self.name.clone(),
+            state,
+            ab(self.upstream_ab),
+            ab(self.base_ab),
+            conflicts,
+            svc.to_string(),
+            age,
+            self.last_commit_subject.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+pub fn cmd_status(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    cfg: &Config,
+    st: &State,
+    filters: &[String],
+    json: bool,
+) -> Result<i32> {
+    let filters = match filter::parse_all(filters) {
+        Ok(f) => f,
+        Err(e) => {
+            log.errorf(&format!("{e}"));
+            return Ok(2);
+        }
+    };
+
+    let allocs: Vec<Allocation> = st
+        .sorted_allocations()
+        .into_iter()
+        .filter(|a| filter::matches_all(&filters, a))
+        .collect();
+    let rows = collect(repo, cfg, &allocs);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(0);
+    }
+
+    if rows.is_empty() {
+        println!("(no worktrees tracked by wrt)");
+        return Ok(0);
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let line = |c: &[String]| {
+        format!(
+            "{:<28}  {:<8}  {:<9}  {:<9}  {:<9}  {:<4}  {:>4}  {}",
+            c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7]
+        )
+    };
+    let header: Vec<String> = COLUMNS.iter().map(|c| c.to_string()).collect();
+    println!("{}", line(&header));
+    for s in &rows {
+        println!("{}", line(&s.cells(now)));
+    }
+    Ok(0)
+}
+
+// collect gathers the status of each allocation (in order).
+pub(super) fn collect(repo: &gitx::Repo, cfg: &Config, allocs: &[Allocation]) -> Vec<Status> {
+    let remote = worktree::resolve_remote(&repo.root, cfg.remote.as_deref()).unwrap_or(None);
+    let main = worktree::default_branch(&repo.root, remote.as_deref());
+
+    let has_services = allocs
+        .iter()
+        .any(|a| supabase::has_config(Path::new(&a.path)));
+    let containers = if has_services && which("docker").is_some() {
+        supabase::running_containers().ok()
+    } else {
+        None
+    };
+
+    allocs
+        .iter()
+        .map(|a| status_of(repo, a, main.as_deref(), containers.as_deref()))
+        .collect()
+}
+
+// status_of gathers everything best-effort: a failing git call leaves its field empty rather than
+// hiding the other worktrees.
+fn status_of(
+    repo: &gitx::Repo,
+    a: &Allocation,
+    main: Option<&str>,
+    containers: Option<&[String]>,
+) -> Status {
+    let wt_path = Path::new(&a.path);
+    let mut s = Status {
+        name: a.name.clone(),
+        branch: a.branch.clone(),
+        ..Default::default()
+    };
+    if !wt_path.exists() {
+        return s;
+    }
+
+    s.dirty = worktree::dirty_files(wt_path).ok().map(|f| f.len());
+    if let Ok((at, subject)) = worktree::last_commit(wt_path) {
+        s.last_commit_at = Some(at);
+        s.last_commit_subject = Some(subject);
+    }
+
+    s.upstream = worktree::upstream(wt_path);
+    if let Some(up) = &s.upstream {
+        s.upstream_ab = worktree::ahead_behind(wt_path, "HEAD", up).ok();
+    }
+
+    s.base = a.from_ref.clone().or_else(|| main.map(str::to_string));
+    if let Some(base) = &s.base {
+        s.base_ab = worktree::ahead_behind(wt_path, "HEAD", base).ok();
+    }
+
+    if let Some(main) = main.filter(|m| *m != a.branch) {
+        let head = format!("refs/heads/{}", a.branch);
+        s.conflicts = worktree::merge_conflicts(&repo.root, main, &head).ok();
+    }
+
+    s.services = containers.and_then(|c| services::running(wt_path, c));
+    s
+}
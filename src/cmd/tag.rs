@@ -0,0 +1,101 @@
+use anyhow::Result;
+
+use crate::cli::MetaArgs;
+use crate::filter;
+use crate::gitx;
+use crate::state::{Allocation, State};
+use crate::ui;
+use crate::worktree;
+
+pub fn cmd_tag(
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    st: &mut State,
+    name: &str,
+    meta: &MetaArgs,
+    unlabel: &[String],
+) -> Result<i32> {
+    let key = worktree::slug(name);
+    let Some(a) = st.allocations.get(&key) else {
+        log.errorf(&format!("unknown worktree: \"{key}\""));
+        return Ok(2);
+    };
+
+    let changed = !unlabel.is_empty() || has_meta(meta);
+    if !changed {
+        print_meta(a);
+        return Ok(0);
+    }
+
+    if let Err(e) = apply_meta(&mut Allocation::default(), meta) {
+        log.errorf(&format!("{e}"));
+        return Ok(2);
+    }
+    let saved = st.update(&repo.common_dir, |s| {
+        let Some(a) = s.allocations.get_mut(&key) else {
+            return false;
+        };
+        // Validated above; only the labels can fail.
+        let _ = apply_meta(a, meta);
+        for k in unlabel {
+            a.labels.remove(k.trim());
+        }
+        true
+    });
+    match saved {
+        Ok(true) => Ok(0),
+        Ok(false) => {
+            log.errorf(&format!("worktree \"{key}\" was removed meanwhile"));
+            Ok(1)
+        }
+        Err(e) => {
+            log.errorf(&format!("state save failed: {e}"));
+            Ok(1)
+        }
+    }
+}
+
+fn has_meta(m: &MetaArgs) -> bool {
+    !m.label.is_empty()
+        || m.note.is_some()
+        || m.owner.is_some()
+        || m.agent.is_some()
+        || m.task_url.is_some()
+}
+
+// apply_meta copies the given metadata onto the allocation; empty strings clear a field and
+// labels are merged (an existing key is overwritten).
+pub fn apply_meta(a: &mut Allocation, m: &MetaArgs) -> Result<()> {
+    let labels = m
+        .label
+        .iter()
+        .map(|s| filter::parse_label(s))
+        .collect::<Result<Vec<_>>>()?;
+    a.labels.extend(labels);
+
+    let set = |field: &mut Option<String>, v: &Option<String>| {
+        if let Some(v) = v {
+            *field = Some(v.trim().to_string()).filter(|s| !s.is_empty());
+        }
+    };
+    set(&mut a.note, &m.note);
+    set(&mut a.owner, &m.owner);
+    set(&mut a.agent, &m.agent);
+    set(&mut a.task_url, &m.task_url);
+    Ok(())
+}
+
+fn print_meta(a: &Allocation) {
+    let opt = |v: &Option<String>| v.clone().unwrap_or_else(|| "-".to_string());
+    println!("name:     {}", a.name);
+    println!("owner:    {}", opt(&a.owner));
+    println!("agent:    {}", opt(&a.agent));
+    println!("task_url: {}", opt(&a.task_url));
+    println!("note:     {}", opt(&a.note));
+    if a.labels.is_empty() {
+        println!("labels:   -");
+    } else {
+        let labels: Vec<String> = a.labels.iter().map(|(k, v)| format!("{k}={v}")).collect();
+        println!("labels:   {}", labels.join(" "));
+    }
+}
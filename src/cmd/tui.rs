@@ -0,0 +1,362 @@
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::ExecutableCommand;
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState};
+use ratatui::Terminal;
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Stdout, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::api::RemoveOpts;
+use crate::config::Config;
+use crate::gitx;
+use crate::services;
+use crate::state::{Allocation, State};
+use crate::ui;
+use crate::util::run_argv_with_wrt_env;
+
+use super::status::{self, Status, COLUMNS};
+use super::{cmd_archive, cmd_rm};
+
+const REFRESH_EVERY: Duration = Duration::from_secs(10);
+const HELP: &str = "j/k move  enter shell  e editor  d diff  u/x start/stop services  \
+                    A archive  D remove  r refresh  q quit";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    Up,
+    Down,
+    Shell,
+    Editor,
+    Diff,
+    Start,
+    Stop,
+    Archive,
+    Remove,
+    Refresh,
+    Quit,
+}
+
+fn action_for(code: KeyCode) -> Option<Action> {
+    let a = match code {
+        KeyCode::Up | KeyCode::Char('k') => Action::Up,
+        KeyCode::Down | KeyCode::Char('j') => Action::Down,
+        KeyCode::Enter | KeyCode::Char('s') => Action::Shell,
+        KeyCode::Char('e') => Action::Editor,
+        KeyCode::Char('d') => Action::Diff,
+        KeyCode::Char('u') => Action::Start,
+        KeyCode::Char('x') => Action::Stop,
+        KeyCode::Char('A') => Action::Archive,
+        KeyCode::Char('D') => Action::Remove,
+        KeyCode::Char('r') => Action::Refresh,
+        KeyCode::Char('q') | KeyCode::Esc => Action::Quit,
+        _ => return None,
+    };
+    Some(a)
+}
+
+struct App {
+    allocs: Vec<Allocation>,
+    rows: Vec<Status>,
+    table: TableState,
+    message: String,
+    // Destructive actions wait for a `y`.
+    confirm: Option<Action>,
+    loading: Option<mpsc::Receiver<Vec<Status>>>,
+    refreshed_at: Instant,
+}
+
+impl App {
+    fn selected(&self) -> Option<&Allocation> {
+        self.allocs.get(self.table.selected()?)
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        if self.allocs.is_empty() {
+            self.table.select(None);
+            return;
+        }
+        let cur = self.table.selected().unwrap_or(0) as isize;
+        let next = (cur + delta).clamp(0, self.allocs.len() as isize - 1);
+        self.table.select(Some(next as usize));
+    }
+}
+
+type Term = Terminal<CrosstermBackend<Stdout>>;
+
+// cmd_ui is an interactive front end over the same state and command functions as the CLI.
+// Anything that prints or needs the terminal (shells, editors, rm/archive output) runs with the
+// TUI suspended.
+pub fn cmd_ui(log: &ui::Logger, repo: &gitx::Repo, cfg: &Config, st: &mut State) -> Result<i32> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        log.errorf("wrt ui needs an interactive terminal");
+        return Ok(2);
+    }
+
+    let mut app = App {
+        allocs: Vec::new(),
+        rows: Vec::new(),
+        table: TableState::default(),
+        message: String::new(),
+        confirm: None,
+        loading: None,
+        refreshed_at: Instant::now(),
+    };
+    reload(repo, cfg, st, &mut app);
+
+    let mut term = enter()?;
+    let res = event_loop(&mut term, log, repo, cfg, st, &mut app);
+    leave()?;
+    res
+}
+
+fn event_loop(
+    term: &mut Term,
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    cfg: &Config,
+    st: &mut State,
+    app: &mut App,
+) -> Result<i32> {
+    loop {
+        if let Some(rx) = &app.loading {
+            if let Ok(rows) = rx.try_recv() {
+                app.rows = rows;
+                app.loading = None;
+                app.refreshed_at = Instant::now();
+            }
+        } else if app.refreshed_at.elapsed() >= REFRESH_EVERY {
+            refresh(repo, cfg, app);
+        }
+
+        term.draw(|f| draw(f, app))?;
+
+        if !event::poll(Duration::from_millis(200))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        if let Some(pending) = app.confirm.take() {
+            if key.code == KeyCode::Char('y') {
+                run_action(term, log, repo, cfg, st, app, pending)?;
+            } else {
+                app.message = "cancelled".to_string();
+            }
+            continue;
+        }
+
+        let Some(action) = action_for(key.code) else {
+            continue;
+        };
+        match action {
+            Action::Quit => return Ok(0),
+            Action::Up => app.move_by(-1),
+            Action::Down => app.move_by(1),
+            Action::Refresh => reload(repo, cfg, st, app),
+            Action::Archive | Action::Remove => {
+                if let Some(a) = app.selected() {
+                    let verb = if action == Action::Archive {
+                        "archive"
+                    } else {
+                        "remove"
+                    };
+                    app.message = format!("{verb} {}? (y/N)", a.name);
+                    app.confirm = Some(action);
+                }
+            }
+            _ => run_action(term, log, repo, cfg, st, app, action)?,
+        }
+    }
+}
+
+fn run_action(
+    term: &mut Term,
+    log: &ui::Logger,
+    repo: &gitx::Repo,
+    cfg: &Config,
+    st: &mut State,
+    app: &mut App,
+    action: Action,
+) -> Result<()> {
+    let Some(a) = app.selected().cloned() else {
+        return Ok(());
+    };
+    let wt_path = Path::new(&a.path);
+    let base = app
+        .rows
+        .iter()
+        .find(|r| r.name == a.name)
+        .and_then(|r| r.base.clone());
+
+    leave()?;
+    let (res, pause) = match action {
+        Action::Shell => {
+            let shell = env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
+            eprintln!("[wrt] {}: exit the shell to return to wrt ui", a.name);
+            (
+                run_argv_with_wrt_env(wt_path, &a, &[shell]).map(|_| 0),
+                false,
+            )
+        }
+        Action::Editor => {
+            let editor = env::var("VISUAL")
+                .or_else(|_| env::var("EDITOR"))
+                .unwrap_or_else(|_| "vi".to_string());
+            // Allow values like "code --wait".
+            let mut argv: Vec<String> = editor.split_whitespace().map(str::to_string).collect();
+            argv.push(".".to_string());
+            (run_argv_with_wrt_env(wt_path, &a, &argv).map(|_| 0), false)
+        }
+        Action::Diff => {
+            let base = base.unwrap_or_else(|| "HEAD".to_string());
+            let argv: Vec<String> = vec!["git".into(), "diff".into(), format!("{base}...HEAD")];
+            (run_argv_with_wrt_env(wt_path, &a, &argv).map(|_| 0), true)
+        }
+        Action::Start => (services::start(log, wt_path).map(|_| 0), true),
+        Action::Stop => (services::stop(log, wt_path).map(|_| 0), true),
+        Action::Archive => (cmd_archive(log, repo, st, &a.name), true),
+        Action::Remove => (cmd_rm(log, repo, st, &a.name, &RemoveOpts::default()), true),
+        _ => (Ok(0), false),
+    };
+    app.message = match &res {
+        Ok(0) => String::new(),
+        Ok(code) => format!("{}: exited with {code}", a.name),
+        Err(e) => format!("{}: {e}", a.name),
+    };
+    if pause {
+        eprint!("[wrt] press Enter to return to wrt ui");
+        io::stderr().flush().ok();
+        let _ = io::stdin().lock().read_line(&mut String::new());
+    }
+    *term = enter()?;
+    reload(repo, cfg, st, app);
+    Ok(())
+}
+
+// reload re-reads state.json (other wrt processes may have changed it) and refreshes statuses.
+fn reload(repo: &gitx::Repo, cfg: &Config, st: &mut State, app: &mut App) {
+    let _ = st.reload(&repo.common_dir);
+    let selected = app.selected().map(|a| a.name.clone());
+    app.allocs = st.sorted_allocations();
+    let idx = selected
+        .and_then(|n| app.allocs.iter().position(|a| a.name == n))
+        .unwrap_or(0);
+    app.table.select((!app.allocs.is_empty()).then_some(idx));
+    app.rows = app
+        .allocs
+        .iter()
+        .map(|a| Status {
+            name: a.name.clone(),
+            branch: a.branch.clone(),
+            ..Default::default()
+        })
+        .collect();
+    refresh(repo, cfg, app);
+}
+
+// refresh collects statuses on a background thread; git calls for many worktrees take a while.
+fn refresh(repo: &gitx::Repo, cfg: &Config, app: &mut App) {
+    let (tx, rx) = mpsc::channel();
+    let (repo, cfg, allocs) = (repo.clone(), cfg.clone(), app.allocs.clone());
+    thread::spawn(move || {
+        let _ = tx.send(status::collect(&repo, &cfg, &allocs));
+    });
+    app.loading = Some(rx);
+    app.refreshed_at = Instant::now();
+}
+
+fn draw(f: &mut ratatui::Frame, app: &mut App) {
+    let [main, footer] =
+        Layout::vertical([Constraint::Min(3), Constraint::Length(2)]).areas(f.area());
+
+    let now = chrono::Utc::now().timestamp();
+    let rows: Vec<Row> = app.rows.iter().map(|s| Row::new(s.cells(now))).collect();
+    let widths = [
+        Constraint::Length(24),
+        Constraint::Length(9),
+        Constraint::Length(9),
+        Constraint::Length(9),
+        Constraint::Length(9),
+        Constraint::Length(4),
+        Constraint::Length(4),
+        Constraint::Fill(1),
+    ];
+    let title = if app.loading.is_some() {
+        " wrt (refreshing...) "
+    } else {
+        " wrt "
+    };
+    let table = Table::new(rows, widths)
+        .header(Row::new(COLUMNS.to_vec()).style(Style::new().add_modifier(Modifier::BOLD)))
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    f.render_stateful_widget(table, main, &mut app.table);
+
+    let status = if app.allocs.is_empty() {
+        "(no worktrees tracked by wrt)".to_string()
+    } else {
+        app.message.clone()
+    };
+    f.render_widget(
+        Paragraph::new(vec![Line::from(status), Line::from(HELP)]),
+        footer,
+    );
+}
+
+fn enter() -> Result<Term> {
+    terminal::enable_raw_mode()?;
+    io::stdout().execute(EnterAlternateScreen)?;
+    let mut term = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    term.clear()?;
+    Ok(term)
+}
+
+fn leave() -> Result<()> {
+    terminal::disable_raw_mode()?;
+    io::stdout().execute(LeaveAlternateScreen)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_map_to_actions_and_selection_clamps() {
+        assert_eq!(action_for(KeyCode::Char('j')), Some(Action::Down));
+        assert_eq!(action_for(KeyCode::Enter), Some(Action::Shell));
+        assert_eq!(action_for(KeyCode::Char('D')), Some(Action::Remove));
+        assert_eq!(action_for(KeyCode::Char('d')), Some(Action::Diff));
+        assert_eq!(action_for(KeyCode::Char('z')), None);
+
+        let mut app = App {
+            allocs: vec![Allocation::default(), Allocation::default()],
+            rows: Vec::new(),
+            table: TableState::default(),
+            message: String::new(),
+            confirm: None,
+            loading: None,
+            refreshed_at: Instant::now(),
+        };
+        app.move_by(-1);
+        assert_eq!(app.table.selected(), Some(0));
+        app.move_by(5);
+        assert_eq!(app.table.selected(), Some(1));
+        app.allocs.clear();
+        app.move_by(1);
+        assert_eq!(app.table.selected(), None);
+    }
+}
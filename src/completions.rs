@@ -1,93 +1,443 @@
-pub fn zsh_script() -> &'static str {
-    r#"#compdef wrt
+use clap::{Arg, Command, CommandFactory};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
 
-_wrt_worktrees() {
-  local -a names
-  names=(${(f)"$(wrt ls 2>/dev/null | awk 'NF && $1 !~ /^\\(/ {print $1}')"})
-  _describe -t worktrees 'worktree' names
+use crate::cli::Cli;
+use crate::{config, gitx, state};
+
+// Completion works the same in every shell: the script only collects the words on the command line
+// and runs the hidden `wrt __complete --index <i> -- <words...>`, which walks the clap definitions in
+// cli.rs and prints one candidate per line. No candidates means "complete file names".
+
+/// Shells `wrt completions` has a script for.
+pub const SHELLS: [&str; 5] = ["bash", "zsh", "fish", "nushell", "powershell"];
+
+const MODES: [&str; 3] = ["auto", "true", "false"];
+
+/// Where dynamic values come from, keyed by the `value_name` of the argument in cli.rs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// WORKTREE: tracked worktrees.
+    Worktree,
+    /// ARCHIVED: archived worktrees.
+    Archived,
+    /// GROUP: groups of `wrt new --count`.
+    Group,
+    /// PROFILE: config `profiles`.
+    Profile,
+    /// SPARSE_PROFILE: config `sparse_profiles`.
+    SparseProfile,
+}
+
+impl Source {
+    fn for_value_name(name: &str) -> Option<Source> {
+        match name {
+            "WORKTREE" => Some(Source::Worktree),
+            "ARCHIVED" => Some(Source::Archived),
+            "GROUP" => Some(Source::Group),
+            "PROFILE" => Some(Source::Profile),
+            "SPARSE_PROFILE" => Some(Source::SparseProfile),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub value: String,
+    /// One-line description (empty if none); shown by zsh, fish, nushell and PowerShell.
+    pub help: String,
+}
+
+impl Candidate {
+    fn new(value: impl Into<String>, help: impl fmt::Display) -> Candidate {
+        let help = help.to_string();
+        Candidate {
+            value: value.into(),
+            help: help.lines().next().unwrap_or_default().trim().to_string(),
+        }
+    }
+}
+
+// The `wrt __complete` output format: `<value>` or `<value>\t<help>`.
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.help.is_empty() {
+            write!(f, "{}", self.value)
+        } else {
+            write!(f, "{}\t{}", self.value, self.help)
+        }
+    }
+}
+
+pub fn script(shell: &str) -> Option<&'static str> {
+    match shell.trim().to_lowercase().as_str() {
+        "bash" => Some(BASH),
+        "zsh" => Some(ZSH),
+        "fish" => Some(FISH),
+        "nushell" | "nu" => Some(NUSHELL),
+        "powershell" | "pwsh" => Some(POWERSHELL),
+        _ => None,
+    }
+}
+
+// complete returns the candidates for words[index] (the word under the cursor, empty when index is
+// past the end), where words are the arguments after `wrt`. Subcommands, flags and possible values
+// come from cli.rs; the values of the arguments named in `Source` come from `values`.
+pub fn complete(
+    words: &[String],
+    index: usize,
+    values: &dyn Fn(Source) -> Vec<Candidate>,
+) -> Vec<Candidate> {
+    let mut root = Cli::command();
+    root.build();
+    let done = &words[..index.min(words.len())];
+    let cur = words.get(index).map(String::as_str).unwrap_or_default();
+
+    let mut cmd = &root;
+    let mut positional = 0;
+    let mut used: Vec<&str> = Vec::new();
+    let mut pending: Option<&Arg> = None;
+    for w in done {
+        if w == "--" {
+            // Everything after `--` is a command (`wrt run`, `wrt foreach`): complete file names.
+            return Vec::new();
+        }
+        if let Some(a) = pending {
+            if !w.starts_with('-') {
+                if !multiple(a) {
+                    pending = None;
+                }
+                continue;
+            }
+            pending = None;
+        }
+        if let Some(long) = w.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (long, None),
+            };
+            if let Some(a) = cmd.get_arguments().find(|a| a.get_long() == Some(name)) {
+                used.push(a.get_id().as_str());
+                if inline.is_none() && takes_value(a) {
+                    pending = Some(a);
+                }
+            }
+            continue;
+        }
+        if w.len() > 1 && w.starts_with('-') {
+            for c in w.chars().skip(1) {
+                if let Some(a) = cmd.get_arguments().find(|a| a.get_short() == Some(c)) {
+                    used.push(a.get_id().as_str());
+                    pending = takes_value(a).then_some(a);
+                }
+            }
+            continue;
+        }
+        if subcommands_allowed(cmd, positional) {
+            if let Some(sub) = cmd.find_subcommand(w) {
+                cmd = sub;
+                positional = 0;
+                used.clear();
+                continue;
+            }
+        }
+        positional += 1;
+    }
+
+    let mut out = Vec::new();
+    if cur.starts_with('-') {
+        if !cur.contains('=') {
+            out.extend(
+                cmd.get_arguments()
+                    .filter(|a| !a.is_positional() && !a.is_hide_set())
+                    .filter(|a| multiple(a) || !used.contains(&a.get_id().as_str()))
+                    .filter_map(|a| {
+                        let help = a.get_help().map(ToString::to_string).unwrap_or_default();
+                        a.get_long().map(|l| Candidate::new(format!("--{l}"), help))
+                    }),
+            );
+        }
+    } else if let Some(a) = pending {
+        out.extend(values_for(a, values));
+    } else {
+        if let Some(a) = positional_at(cmd, positional) {
+            out.extend(values_for(a, values));
+        }
+        if subcommands_allowed(cmd, positional) {
+            out.extend(
+                cmd.get_subcommands()
+                    .filter(|s| !s.is_hide_set())
+                    .map(|s| Candidate::new(s.get_name(), s.get_about().unwrap_or_default())),
+            );
+        }
+    }
+    out.retain(|c| c.value.starts_with(cur));
+    out
+}
+
+// repo_values reads the dynamic values from the repo at the current directory. Completion must never
+// print errors, so anything that fails yields no values.
+pub fn repo_values(source: Source) -> Vec<Candidate> {
+    let Some(repo) = env::current_dir()
+        .ok()
+        .and_then(|cwd| gitx::detect_repo(&cwd).ok())
+    else {
+        return Vec::new();
+    };
+    match source {
+        Source::Worktree | Source::Archived | Source::Group => {
+            let Ok(st) = state::State::load(&repo.common_dir) else {
+                return Vec::new();
+            };
+            match source {
+                Source::Worktree => st
+                    .sorted_allocations()
+                    .into_iter()
+                    .map(|a| Candidate::new(a.name, a.branch))
+                    .collect(),
+                Source::Archived => st
+                    .archived
+                    .iter()
+                    .map(|(name, a)| Candidate::new(name.clone(), &a.alloc.branch))
+                    .collect(),
+                _ => {
+                    let mut groups: BTreeMap<String, usize> = BTreeMap::new();
+                    for a in st.allocations.values() {
+                        if let Some(g) = &a.group {
+                            *groups.entry(g.clone()).or_default() += 1;
+                        }
+                    }
+                    groups
+                        .into_iter()
+                        .map(|(g, n)| Candidate::new(g, format!("{n} worktrees")))
+                        .collect()
+                }
+            }
+        }
+        Source::Profile | Source::SparseProfile => {
+            let Ok(cfg) = config::Config::load(&repo.common_dir) else {
+                return Vec::new();
+            };
+            if source == Source::Profile {
+                cfg.profiles
+                    .into_keys()
+                    .map(|p| Candidate::new(p, ""))
+                    .collect()
+            } else {
+                cfg.sparse_profiles
+                    .into_iter()
+                    .map(|(p, paths)| Candidate::new(p, paths.join(" ")))
+                    .collect()
+            }
+        }
+    }
+}
+
+fn takes_value(a: &Arg) -> bool {
+    a.get_action().takes_values()
+}
+
+// multiple reports whether an argument takes several values (`--sparse a b`, `sparse x add a b`).
+fn multiple(a: &Arg) -> bool {
+    a.get_num_args().is_some_and(|n| n.max_values() > 1)
+}
+
+fn positional_at(cmd: &Command, i: usize) -> Option<&Arg> {
+    let args: Vec<&Arg> = cmd.get_positionals().filter(|a| !a.is_last_set()).collect();
+    match args.get(i) {
+        Some(a) => Some(a),
+        None => args.last().copied().filter(|a| multiple(a)),
+    }
+}
+
+// subcommands_allowed reports whether a subcommand can come after `filled` positionals: clap only
+// looks for one once the required positionals (`wrt sparse <name> add`) are given.
+fn subcommands_allowed(cmd: &Command, filled: usize) -> bool {
+    cmd.has_subcommands()
+        && cmd
+            .get_positionals()
+            .filter(|a| a.is_required_set())
+            .count()
+            <= filled
 }
 
+fn values_for(a: &Arg, values: &dyn Fn(Source) -> Vec<Candidate>) -> Vec<Candidate> {
+    let possible: Vec<Candidate> = a
+        .get_possible_values()
+        .into_iter()
+        .filter(|v| !v.is_hide_set())
+        .map(|v| {
+            let help = v.get_help().map(ToString::to_string).unwrap_or_default();
+            Candidate::new(v.get_name(), help)
+        })
+        .collect();
+    if !possible.is_empty() {
+        return possible;
+    }
+    let name = a
+        .get_value_names()
+        .and_then(|n| n.first())
+        .map(|n| n.as_str())
+        .unwrap_or_default();
+    if name == "MODE" {
+        return MODES.iter().map(|m| Candidate::new(*m, "")).collect();
+    }
+    Source::for_value_name(name).map(values).unwrap_or_default()
+}
+
+const BASH: &str = r#"# wrt completions for bash: source this file (e.g. from ~/.bashrc).
 _wrt() {
-  local context state state_descr line
-
-  _arguments -C \
-    '1:command:->cmds' \
-    '*::arg:->args'
-
-  case $state in
-    cmds)
-      _values 'command' \
-        'help[Print usage]' \
-        'init[Generate repo-local config]' \
-        'new[Create a new worktree]' \
-        'db[Run database utilities]' \
-        'ls[List tracked worktrees]' \
-        'list[Alias for ls]' \
-        'path[Print worktree path]' \
-        'env[Print exports for a worktree]' \
-        'rm[Remove a worktree]' \
-        'remove[Alias for rm]' \
-        'prune[Prune git worktrees and state]' \
-        'run[Run a command in a worktree]' \
-        'completions[Generate zsh completions]'
-      return
-      ;;
-    args)
-      case $words[2] in
-        rm|remove)
-          _arguments -C \
-            '1:worktree:_wrt_worktrees' \
-            '--force[Force remove]' \
-            '--delete-branch[Delete branch]'
-          return
-          ;;
-        path)
-          _arguments '1:worktree:_wrt_worktrees'
-          return
-          ;;
-        env)
-          _arguments '1::worktree:_wrt_worktrees'
-          return
-          ;;
-        run)
-          _arguments -C '1:worktree:_wrt_worktrees' '*::command:_command_names -e'
-          return
-          ;;
-        db)
-          _arguments -C \
-            '1::worktree:_wrt_worktrees' \
-            '2:action:(reset seed migrate)' \
-            '--print[Print the command that would be run and exit]' \
-            '--yes[Skip interactive prompts (reset only)]'
-          return
-          ;;
-        new)
-          _arguments -C \
-            '1:name:' \
-            '--from=[Start ref]:ref:' \
-            '--branch=[Branch name]:branch:' \
-            '--install=[Install deps]:mode:(auto true false)' \
-            '--supabase=[Supabase]:mode:(auto true false)' \
-            '--db=[DB setup]:mode:(auto true false)' \
-            '--cd[Print cd snippet]'
-          return
-          ;;
-        init)
-          _arguments -C \
-            '--force[Overwrite existing .wrt.json]' \
-            '--print[Print config and exit]' \
-            '--model=[Codex model]:model:'
-          return
-          ;;
-        completions)
-          _arguments '1:shell:(zsh)'
-          return
-          ;;
-      esac
-      ;;
-  esac
-}
-
-_wrt "$@"
-"#
+  local -a words cands
+  local cword IFS=$'\n'
+  if declare -F _get_comp_words_by_ref >/dev/null; then
+    _get_comp_words_by_ref -n =: -w words -i cword
+  else
+    words=("${COMP_WORDS[@]}")
+    cword=$COMP_CWORD
+  fi
+  cands=($(command wrt __complete --index $((cword - 1)) -- "${words[@]:1:cword}" 2>/dev/null | cut -f1))
+  COMPREPLY=("${cands[@]}")
+}
+complete -o default -F _wrt wrt
+"#;
+
+const ZSH: &str = r#"#compdef wrt
+
+_wrt() {
+  local -a out cands
+  local line
+  out=("${(@f)$(command wrt __complete --index $((CURRENT - 2)) -- "${(@)words[2,CURRENT]}" 2>/dev/null)}")
+  for line in $out; do
+    [[ -n $line ]] || continue
+    if [[ $line == *$'\t'* ]]; then
+      cands+=("${${line%%$'\t'*}//:/\\:}:${line#*$'\t'}")
+    else
+      cands+=("${line//:/\\:}")
+    fi
+  done
+  if (( $#cands )); then
+    _describe -t values 'wrt' cands
+  else
+    _files
+  fi
+}
+
+if [[ $zsh_eval_context[-1] == loadautofunc ]]; then
+  _wrt "$@"
+elif (( $+functions[compdef] )); then
+  compdef _wrt wrt
+fi
+"#;
+
+const FISH: &str = r#"# wrt completions for fish: save as ~/.config/fish/completions/wrt.fish
+function __wrt_complete
+    set -l words (commandline -opc)
+    set -e words[1]
+    set -l cands (command wrt __complete --index (count $words) -- $words (commandline -ct) 2>/dev/null)
+    if test (count $cands) -gt 0
+        printf '%s\n' $cands
+    else
+        __fish_complete_path (commandline -ct)
+    end
+end
+
+complete -c wrt -f -a '(__wrt_complete)'
+"#;
+
+const NUSHELL: &str = r#"# wrt completions for nushell: save to a file and `source` it from config.nu.
+# Other commands still go to the external completer that was configured before.
+def wrt-complete [spans: list<string>] {
+    let words = ($spans | skip 1)
+    let cands = (
+        ^wrt __complete --index (($words | length) - 1) -- ...$words
+        | lines
+        | each {|l| let p = ($l | split row "\t"); {value: $p.0, description: ($p | skip 1 | str join " ")} }
+    )
+    if ($cands | is-empty) { null } else { $cands }
+}
+
+let wrt_previous_completer = $env.config.completions.external.completer?
+$env.config.completions.external.enable = true
+$env.config.completions.external.completer = {|spans|
+    if ($spans.0 == "wrt") {
+        wrt-complete $spans
+    } else if ($wrt_previous_completer != null) {
+        do $wrt_previous_completer $spans
+    }
+}
+"#;
+
+const POWERSHELL: &str = r#"# wrt completions for PowerShell: add `wrt completions powershell | Out-String | Invoke-Expression` to $PROFILE.
+Register-ArgumentCompleter -Native -CommandName wrt -ScriptBlock {
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $words = @($commandAst.CommandElements | Select-Object -Skip 1 |
+        Where-Object { $_.Extent.StartOffset -lt $cursorPosition } | ForEach-Object { $_.ToString() })
+    $index = $words.Count
+    if ($wordToComplete) { $index -= 1 }
+    wrt __complete --index $index '--' @words 2>$null | ForEach-Object {
+        $value, $help = $_ -split "`t", 2
+        if (-not $help) { $help = $value }
+        [System.Management.Automation.CompletionResult]::new($value, $value, 'ParameterValue', $help)
+    }
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(line: &str, values: &dyn Fn(Source) -> Vec<Candidate>) -> Vec<String> {
+        let mut words: Vec<String> = line.split(' ').map(String::from).collect();
+        let index = words.len() - 1;
+        if words[index].is_empty() {
+            words.pop();
+        }
+        complete(&words, index, values)
+            .into_iter()
+            .map(|c| c.value)
+            .collect()
+    }
+
+    fn fake(source: Source) -> Vec<Candidate> {
+        match source {
+            Source::Worktree => vec![Candidate::new("api", "api"), Candidate::new("web", "web")],
+            Source::Profile => vec![Candidate::new("review", "")],
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn completes_subcommands_flags_and_values() {
+        let top = run("s", &fake);
+        assert_eq!(top, ["setup", "sparse", "status", "shell-init"]);
+        assert!(!run("", &fake).contains(&"__complete".to_string()));
+
+        assert_eq!(run("rm ", &fake), ["api", "web"]);
+        assert_eq!(run("rm w", &fake), ["web"]);
+        assert_eq!(run("rm web --f", &fake), ["--force"]);
+        assert_eq!(run("rm web --force --f", &fake), Vec::<String>::new());
+        assert_eq!(run("new x --profile ", &fake), ["review"]);
+        assert_eq!(run("new x --db ", &fake), ["auto", "true", "false"]);
+        assert_eq!(run("pick web --mode s", &fake), ["squash"]);
+        assert_eq!(run("setup web --steps ", &fake).len(), 5);
+        assert_eq!(run("new x --label k=v --no-f", &fake), ["--no-fetch"]);
+        // A new worktree's name is not one of the existing ones.
+        assert!(run("new ", &fake).is_empty());
+    }
+
+    #[test]
+    fn completes_db_actions_and_nested_subcommands() {
+        assert_eq!(
+            run("db ", &fake),
+            ["api", "web", "reset", "seed", "migrate"]
+        );
+        assert_eq!(run("db web ", &fake), ["reset", "seed", "migrate"]);
+        assert_eq!(run("db web reset --y", &fake), ["--yes"]);
+        assert_eq!(run("sparse ", &fake), ["api", "web"]);
+        assert_eq!(run("sparse web ", &fake), ["list", "add", "set", "disable"]);
+        assert!(run("run web -- ", &fake).is_empty());
+        assert_eq!(run("completions p", &fake), ["powershell"]);
+    }
 }
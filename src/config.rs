@@ -0,0 +1,173 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CONFIG_DIR_NAME: &str = ".wrt";
+const CONFIG_FILE_NAME: &str = "config.json";
+
+// Config holds wrt's own settings (as opposed to .wrt.json, which describes the repo and is
+// regenerated by `wrt init`). It is merged from the user file (~/.config/wrt/config.json) and the
+// repo file (<git-common-dir>/.wrt/config.json); repo values win.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Config {
+    /// Named sets of cone-mode sparse-checkout paths, used via `wrt new --sparse-profile <name>`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sparse_profiles: BTreeMap<String, Vec<String>>,
+    /// Named bundles of `wrt new` settings, used via `wrt new --profile <name>`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Profile `wrt new` uses when no --profile is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    /// Remote `wrt new` fetches from and resolves upstream branches against (default: origin,
+    /// else the first remote).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    /// Set to false to never fetch in `wrt new` (same as always passing --no-fetch).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetch: Option<bool>,
+    /// Give up on a fetch after this many seconds and continue with local refs (default 60).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetch_timeout_secs: Option<u64>,
+    /// Shell command `wrt compare` runs in each worktree of a group (e.g. "npm test"); a zero
+    /// exit status counts as passing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check: Option<String>,
+    /// Allocate port blocks machine-wide (~/.local/state/wrt/registry.json) instead of per repo, so
+    /// worktrees of different repos never share ports. Usually set in the user config.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry: Option<bool>,
+    /// How new worktrees are kept apart: "ports" (default) shifts ports by WRT_PORT_OFFSET;
+    /// "loopback" gives each its own 127.x.y.1 address (WRT_HOST) and keeps the default ports.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isolation: Option<Isolation>,
+    /// Port `wrt proxy` listens on (default 8080); part of the WRT_URL_<SERVICE> URLs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_port: Option<u16>,
+    /// Most worktrees the repo may have before `wrt mcp` refuses to create more.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mcp_max_worktrees: Option<usize>,
+}
+
+// Profile is a named set of `wrt new` settings (e.g. "review": no install, no supabase, no DB).
+// Flags given to `wrt new` win over the profile's.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    /// Ref to branch from (`--from`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// auto|true|false, as `--install`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub install: Option<String>,
+    /// auto|true|false, as `--supabase`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supabase: Option<String>,
+    /// auto|true|false, as `--db`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub db: Option<String>,
+    /// Files or directories copied from the main workdir into the worktree (besides .env).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+    /// Cone-mode sparse-checkout paths, as `--sparse`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sparse: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Isolation {
+    #[default]
+    Ports,
+    Loopback,
+}
+
+impl Config {
+    pub fn load(git_common_dir: &Path) -> Result<Config> {
+        let mut cfg = Config::default();
+        if let Some(p) = user_file_path() {
+            if let Some(user) = read_file(&p)? {
+                cfg.merge(user);
+            }
+        }
+        if let Some(repo) = read_file(&repo_file_path(git_common_dir))? {
+            cfg.merge(repo);
+        }
+        Ok(cfg)
+    }
+
+    fn merge(&mut self, other: Config) {
+        self.sparse_profiles.extend(other.sparse_profiles);
+        self.profiles.extend(other.profiles);
+        self.default_profile = other.default_profile.or(self.default_profile.take());
+        self.remote = other.remote.or(self.remote.take());
+        self.fetch = other.fetch.or(self.fetch);
+        self.fetch_timeout_secs = other.fetch_timeout_secs.or(self.fetch_timeout_secs);
+        self.check = other.check.or(self.check.take());
+        self.registry = other.registry.or(self.registry);
+        self.isolation = other.isolation.or(self.isolation);
+        self.proxy_port = other.proxy_port.or(self.proxy_port);
+        self.mcp_max_worktrees = other.mcp_max_worktrees.or(self.mcp_max_worktrees);
+    }
+}
+
+fn read_file(p: &Path) -> Result<Option<Config>> {
+    let b = match fs::read(p) {
+        Ok(b) => b,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("read {}", p.display())),
+    };
+    let cfg = serde_json::from_slice(&b).with_context(|| format!("parse {}", p.display()))?;
+    Ok(Some(cfg))
+}
+
+fn repo_file_path(git_common_dir: &Path) -> PathBuf {
+    git_common_dir.join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME)
+}
+
+fn user_file_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        Some(v) => PathBuf::from(v),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("wrt").join(CONFIG_FILE_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn repo_config_overrides_user_config() {
+        let td = TempDir::new().unwrap();
+        let mut cfg: Config =
+            serde_json::from_str(r#"{"sparse_profiles":{"web":["apps/web"],"api":["apps/api"]}}"#)
+                .unwrap();
+        cfg.merge(serde_json::from_str(r#"{"sparse_profiles":{"web":["web"]}}"#).unwrap());
+        assert_eq!(cfg.sparse_profiles["web"], vec!["web".to_string()]);
+        assert_eq!(cfg.sparse_profiles["api"], vec!["apps/api".to_string()]);
+
+        cfg.merge(serde_json::from_str(r#"{"isolation":"loopback"}"#).unwrap());
+        assert_eq!(cfg.isolation, Some(Isolation::Loopback));
+
+        cfg.merge(
+            serde_json::from_str(
+                r#"{"default_profile":"review","profiles":{"review":{"install":"false"}}}"#,
+            )
+            .unwrap(),
+        );
+        cfg.merge(serde_json::from_str(r#"{"profiles":{"full":{"db":"true"}}}"#).unwrap());
+        assert_eq!(cfg.default_profile.as_deref(), Some("review"));
+        assert_eq!(cfg.profiles["review"].install.as_deref(), Some("false"));
+        assert_eq!(cfg.profiles["full"].db.as_deref(), Some("true"));
+
+        assert!(read_file(&td.path().join("missing.json"))
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{anyhow, Result};
+
+use crate::state::Allocation;
+
+// Filter is one `--filter` expression. Several filters must all match.
+//
+//   label=<key>           the label is set (any value)
+//   label=<key>=<value>   the label has this value
+//   <field>=<value>       name, branch, group, owner, agent, note or task_url equals value
+//
+// A value ending in `*` matches by prefix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+    HasLabel(String),
+    Label(String, String),
+    Field(Field, String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Name,
+    Branch,
+    Group,
+    Owner,
+    Agent,
+    Note,
+    TaskUrl,
+}
+
+impl Filter {
+    pub fn parse(s: &str) -> Result<Filter> {
+        let Some((key, value)) = s.split_once('=') else {
+            return Err(anyhow!("invalid filter \"{s}\" (expected <field>=<value>)"));
+        };
+        let field = match key.trim() {
+            "label" => {
+                return match value.split_once('=') {
+                    Some((k, v)) if !k.is_empty() => Ok(Filter::Label(k.into(), v.into())),
+                    None if !value.is_empty() => Ok(Filter::HasLabel(value.into())),
+                    _ => Err(anyhow!("invalid filter \"{s}\" (expected label=<key>[=<value>])")),
+                };
+            }
+            "name" => Field::Name,
+            "branch" => Field::Branch,
+            "group" => Field::Group,
+            "owner" => Field::Owner,
+            "agent" => Field::Agent,
+            "note" => Field::Note,
+            "task_url" | "task-url" => Field::TaskUrl,
+            other => {
+                return Err(anyhow!(
+                    "unknown filter field \"{other}\" (expected label, name, branch, group, owner, agent, note or task_url)"
+                ))
+            }
+        };
+        Ok(Filter::Field(field, value.to_string()))
+    }
+
+    pub fn matches(&self, a: &Allocation) -> bool {
+        match self {
+            Filter::HasLabel(k) => a.labels.contains_key(k),
+            Filter::Label(k, v) => a.labels.get(k).is_some_and(|got| value_matches(v, got)),
+            Filter::Field(f, v) => {
+                let got = match f {
+                    Field::Name => Some(a.name.as_str()),
+                    Field::Branch => Some(a.branch.as_str()),
+                    Field::Group => a.group.as_deref(),
+                    Field::Owner => a.owner.as_deref(),
+                    Field::Agent => a.agent.as_deref(),
+                    Field::Note => a.note.as_deref(),
+                    Field::TaskUrl => a.task_url.as_deref(),
+                };
+                got.is_some_and(|got| value_matches(v, got))
+            }
+        }
+    }
+}
+
+pub fn parse_all(exprs: &[String]) -> Result<Vec<Filter>> {
+    exprs.iter().map(|s| Filter::parse(s)).collect()
+}
+
+pub fn matches_all(filters: &[Filter], a: &Allocation) -> bool {
+    filters.iter().all(|f| f.matches(a))
+}
+
+fn value_matches(pattern: &str, got: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => got.starts_with(prefix),
+        None => got == pattern,
+    }
+}
+
+// parse_label splits a `--label k=v` argument.
+pub fn parse_label(s: &str) -> Result<(String, String)> {
+    match s.split_once('=') {
+        Some((k, v)) if !k.trim().is_empty() => Ok((k.trim().to_string(), v.to_string())),
+        _ => Err(anyhow!("invalid label \"{s}\" (expected <key>=<value>)")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_match_labels_and_fields() {
+        let mut a = Allocation {
+            name: "login-fix".into(),
+            branch: "agent/login-fix".into(),
+            agent: Some("gpt".into()),
+            ..Default::default()
+        };
+        a.labels.insert("agent".into(), "gpt".into());
+        a.labels.insert("team".into(), "web".into());
+
+        let ok = |s: &str| Filter::parse(s).unwrap().matches(&a);
+        assert!(ok("label=agent=gpt"));
+        assert!(!ok("label=agent=claude"));
+        assert!(ok("label=team"));
+        assert!(!ok("label=missing"));
+        assert!(ok("agent=gpt"));
+        assert!(!ok("owner=me"));
+        assert!(ok("branch=agent/*"));
+        assert!(ok("name=login-fix"));
+
+        assert!(Filter::parse("label=").is_err());
+        assert!(Filter::parse("colour=red").is_err());
+        assert!(Filter::parse("agent").is_err());
+        assert_eq!(
+            parse_label("k=a=b").unwrap(),
+            ("k".to_string(), "a=b".to_string())
+        );
+        assert!(parse_label("=v").is_err());
+    }
+}
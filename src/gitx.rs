@@ -38,6 +38,9 @@ pub fn detect_repo(cwd: &Path) -> Result<Repo> {
     Ok(Repo { root, common_dir })
 }
 
+// Patterns wrt keeps in <git-common-dir>/info/exclude to reduce accidental commits.
+pub const WRT_EXCLUDES: &[&str] = &[".worktrees/", ".wrt.env", ".wrt.json"];
+
 pub fn ensure_info_exclude(common_dir: &Path, patterns: &[&str]) -> Result<()> {
     let exclude_path = common_dir.join("info").join("exclude");
     if let Some(parent) = exclude_path.parent() {
@@ -0,0 +1,69 @@
+//! wrt as a library: create, list and remove a repo's worktrees (each with its own port block)
+//! from Rust, with structured errors and progress events instead of terminal output.
+//!
+//! ```no_run
+//! use wrt::{CreateOpts, Mode, Wrt};
+//!
+//! let mut wrt = Wrt::open(".")?.with_sink(|ev| eprintln!("{ev}"));
+//! let a = wrt.create(&CreateOpts {
+//!     name: "agent-4".into(),
+//!     install: Some(Mode::Never),
+//!     ..Default::default()
+//! })?;
+//! println!("{} on port offset {}", a.path, a.offset);
+//! for (k, v) in wrt.env_for("agent-4")? {
+//!     println!("{k}={v}");
+//! }
+//! wrt.remove("agent-4", &Default::default())?;
+//! # Ok::<(), wrt::Error>(())
+//! ```
+//!
+//! Only the items re-exported here are the stable API; the modules below are the CLI's internals.
+
+mod api;
+
+pub use api::{CreateOpts, Error, Mode, RemoveOpts, Wrt};
+pub use config::Isolation;
+pub use state::Allocation;
+pub use ui::{Event, Level};
+
+#[doc(hidden)]
+pub mod cli;
+#[doc(hidden)]
+pub mod cmd;
+#[doc(hidden)]
+pub mod codex;
+#[doc(hidden)]
+pub mod completions;
+#[doc(hidden)]
+pub mod config;
+#[doc(hidden)]
+pub mod db;
+#[doc(hidden)]
+pub mod filter;
+#[doc(hidden)]
+pub mod gitx;
+#[doc(hidden)]
+pub mod lock;
+#[doc(hidden)]
+pub mod mcp;
+#[doc(hidden)]
+pub mod pm;
+#[doc(hidden)]
+pub mod proxy;
+#[doc(hidden)]
+pub mod registry;
+#[doc(hidden)]
+pub mod services;
+#[doc(hidden)]
+pub mod shell;
+#[doc(hidden)]
+pub mod state;
+#[doc(hidden)]
+pub mod supabase;
+#[doc(hidden)]
+pub mod ui;
+#[doc(hidden)]
+pub mod util;
+#[doc(hidden)]
+pub mod worktree;
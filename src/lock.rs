@@ -0,0 +1,118 @@
+use anyhow::{Context, Result};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+// Locks older than this are assumed to be left over from a crashed process.
+const STALE_AFTER: Duration = Duration::from_secs(10 * 60);
+const POLL_EVERY: Duration = Duration::from_millis(50);
+
+// FileLock is a cooperative lock backed by a `create_new` lock file. It avoids platform-specific
+// flock APIs (and extra deps); the file holds the owner's pid so stale locks can be broken.
+#[derive(Debug)]
+pub struct FileLock {
+    path: PathBuf,
+}
+
+impl FileLock {
+    pub fn acquire(path: &Path, timeout: Duration) -> Result<FileLock> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("mkdir {}", parent.display()))?;
+        }
+
+        let start = SystemTime::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut f) => {
+                    let _ = writeln!(f, "{}", std::process::id());
+                    return Ok(FileLock {
+                        path: path.to_path_buf(),
+                    });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if is_stale(path) {
+                        let _ = fs::remove_file(path);
+                        continue;
+                    }
+                }
+                Err(e) => return Err(e).with_context(|| format!("create {}", path.display())),
+            }
+
+            if start.elapsed().unwrap_or_default() >= timeout {
+                return Err(TimedOut(path.to_path_buf()).into());
+            }
+            thread::sleep(POLL_EVERY);
+        }
+    }
+}
+
+// TimedOut is the error of FileLock::acquire when another process held the lock throughout.
+#[derive(Debug)]
+pub struct TimedOut(pub PathBuf);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "timed out waiting for lock {} (remove it if no wrt process is running)",
+            self.0.display()
+        )
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn is_stale(path: &Path) -> bool {
+    // On Linux we can tell whether the owner is still alive; elsewhere fall back to age.
+    if let Ok(s) = fs::read_to_string(path) {
+        if let Ok(pid) = s.trim().parse::<u32>() {
+            let proc_dir = Path::new("/proc");
+            if proc_dir.is_dir() && !proc_dir.join(pid.to_string()).exists() {
+                return true;
+            }
+        }
+    }
+    let Ok(modified) = fs::metadata(path).and_then(|m| m.modified()) else {
+        return false;
+    };
+    modified.elapsed().map(|d| d > STALE_AFTER).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn lock_is_exclusive_until_dropped() {
+        let td = TempDir::new().unwrap();
+        let p = td.path().join("x.lock");
+
+        let held = FileLock::acquire(&p, Duration::from_millis(10)).unwrap();
+        assert!(FileLock::acquire(&p, Duration::from_millis(100)).is_err());
+        drop(held);
+        assert!(!p.exists());
+        FileLock::acquire(&p, Duration::from_millis(10)).unwrap();
+    }
+
+    #[test]
+    fn lock_from_dead_pid_is_broken() {
+        let td = TempDir::new().unwrap();
+        let p = td.path().join("x.lock");
+        if !Path::new("/proc").is_dir() {
+            return;
+        }
+        // Pids are bounded well below this on Linux.
+        fs::write(&p, "4294967295\n").unwrap();
+        FileLock::acquire(&p, Duration::from_millis(10)).unwrap();
+    }
+}
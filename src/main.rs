@@ -1,26 +1,18 @@
 use anyhow::Result;
 use clap::Parser;
 use std::env;
+use std::io::IsTerminal;
 use std::process::ExitCode;
 
-mod cli;
-mod cmd;
-mod codex;
-mod completions;
-mod db;
-mod gitx;
-mod pm;
-mod state;
-mod supabase;
-mod ui;
-mod util;
-mod worktree;
-
-use cli::{Cli, Cmd, USAGE_TEXT};
-use cmd::{
-    cmd_db, cmd_env, cmd_init, cmd_ls, cmd_new, cmd_path, cmd_prune, cmd_rm, cmd_run,
-    raw_run_has_sep, NewOpts,
+use wrt::cli::{Cli, Cmd, USAGE_TEXT};
+use wrt::cmd::{
+    cmd_adopt, cmd_archive, cmd_cd, cmd_compare, cmd_db, cmd_doctor, cmd_du, cmd_env, cmd_foreach,
+    cmd_gc, cmd_init, cmd_ls, cmd_ls_all, cmd_mcp, cmd_mv, cmd_new, cmd_path, cmd_pick, cmd_prompt,
+    cmd_proxy, cmd_prune, cmd_restore, cmd_rm, cmd_run, cmd_setup, cmd_shell_init, cmd_sparse,
+    cmd_status, cmd_tag, cmd_ui, raw_run_has_sep, AdoptOpts, CompareOpts, GcOpts, NewOpts,
+    PickOpts, SetupOpts,
 };
+use wrt::{completions, config, gitx, proxy, registry, state, ui, worktree, RemoveOpts};
 
 fn main() -> ExitCode {
     match run() {
@@ -33,7 +25,7 @@ fn main() -> ExitCode {
 }
 
 fn run() -> Result<i32> {
-    let log = ui::Logger;
+    let log = ui::Logger::default();
     let raw_args: Vec<String> = env::args().collect();
 
     let cli = Cli::parse();
@@ -48,13 +40,35 @@ fn run() -> Result<i32> {
             return Ok(0);
         }
         Cmd::Completions { shell } => {
-            if shell.trim().eq_ignore_ascii_case("zsh") {
-                print!("{}", completions::zsh_script());
-                return Ok(0);
+            let Some(script) = completions::script(&shell) else {
+                log.errorf(&format!(
+                    "unsupported shell (available: {})",
+                    completions::SHELLS.join(", ")
+                ));
+                return Ok(2);
+            };
+            print!("{script}");
+            return Ok(0);
+        }
+        Cmd::ShellInit { shell } => return cmd_shell_init(&log, &shell),
+        // Runs on every prompt: no repo detection, no state.
+        Cmd::Prompt { format } => return cmd_prompt(format.as_deref()),
+        Cmd::Complete { index, words } => {
+            let index = index.unwrap_or(words.len().saturating_sub(1));
+            for c in completions::complete(&words, index, &completions::repo_values) {
+                println!("{c}");
             }
-            log.errorf("unsupported shell (only zsh is available)");
-            return Ok(2);
+            return Ok(0);
+        }
+        // The registry lives outside any repo.
+        Cmd::Ls {
+            filter,
+            all_repos: true,
         }
+        | Cmd::List {
+            filter,
+            all_repos: true,
+        } => return cmd_ls_all(&log, &filter),
         other => other,
     };
 
@@ -67,7 +81,10 @@ fn run() -> Result<i32> {
         }
     };
 
-    let _ = gitx::ensure_info_exclude(&repo.common_dir, &[".worktrees/", ".wrt.env", ".wrt.json"]);
+    // doctor reports (and with --fix repairs) missing excludes itself.
+    if !matches!(cmd, Cmd::Doctor { .. }) {
+        let _ = gitx::ensure_info_exclude(&repo.common_dir, gitx::WRT_EXCLUDES);
+    }
 
     let mut st = match state::State::load(&repo.common_dir) {
         Ok(s) => s,
@@ -77,12 +94,23 @@ fn run() -> Result<i32> {
         }
     };
 
-    match cmd {
-        Cmd::Help => {
-            print!("{USAGE_TEXT}");
-            Ok(0)
+    let cfg = match config::Config::load(&repo.common_dir) {
+        Ok(c) => c,
+        Err(e) => {
+            log.errorf(&format!("config load failed: {e}"));
+            return Ok(1);
+        }
+    };
+
+    if cfg.registry == Some(true) {
+        let linked = registry::Link::for_repo(&repo).and_then(|link| st.link_registry(link));
+        if let Err(e) = linked {
+            log.errorf(&format!("machine-wide registry: {e}"));
+            return Ok(1);
         }
+    }
 
+    match cmd {
         Cmd::Init {
             force,
             print,
@@ -91,25 +119,95 @@ fn run() -> Result<i32> {
 
         Cmd::New {
             name,
+            profile,
             from,
             branch,
+            pr,
+            remote,
+            no_fetch,
             install,
             supabase,
             db,
+            sparse,
+            sparse_profile,
+            meta,
+            ttl,
+            count,
+            keep_on_failure,
+            jobs,
+            json,
             cd,
         } => {
+            let name = match (name, pr) {
+                (Some(n), _) => n,
+                (None, Some(pr)) => format!("pr-{pr}"),
+                (None, None) => unreachable!("clap requires <name> without --pr"),
+            };
             let opts = NewOpts {
                 name: &name,
-                from_ref: &from,
+                profile: profile.as_deref(),
+                from_ref: from.as_deref(),
                 branch: branch.as_deref(),
-                install_mode: &install,
-                sb_mode: &supabase,
-                db_mode: &db,
+                install_mode: install.as_deref(),
+                sb_mode: supabase.as_deref(),
+                db_mode: db.as_deref(),
+                sparse: &sparse,
+                sparse_profile: sparse_profile.as_deref(),
+                pr,
+                remote: remote.as_deref(),
+                no_fetch,
+                meta: &meta,
+                ttl: ttl.as_deref(),
+                count,
+                keep_on_failure,
+                jobs,
+                json,
                 emit_cd: cd,
+                interactive: std::io::stdin().is_terminal(),
+                max_worktrees: None,
             };
-            cmd_new(&log, &repo, &mut st, opts)
+            cmd_new(&log, &repo, &cfg, &mut st, opts)
         }
 
+        Cmd::Adopt {
+            path,
+            all,
+            name,
+            supabase,
+        } => {
+            let opts = AdoptOpts {
+                path: path.as_deref(),
+                all,
+                name: name.as_deref(),
+                sb_mode: &supabase,
+                isolation: cfg.isolation.unwrap_or_default(),
+                proxy_port: cfg.proxy_port.unwrap_or(proxy::DEFAULT_PORT),
+            };
+            cmd_adopt(&log, &repo, &mut st, opts)
+        }
+
+        Cmd::Setup {
+            name,
+            steps,
+            from_step,
+            install,
+            supabase,
+            db,
+        } => {
+            let opts = SetupOpts {
+                name: &name,
+                steps: &steps,
+                from_step: from_step.as_deref(),
+                install_mode: install.as_deref(),
+                sb_mode: supabase.as_deref(),
+                db_mode: db.as_deref(),
+                interactive: std::io::stdin().is_terminal(),
+            };
+            cmd_setup(&log, &repo, &cfg, &mut st, opts)
+        }
+
+        Cmd::Sparse { name, action } => cmd_sparse(&log, &repo, &mut st, &name, action),
+
         Cmd::Db {
             name,
             worktree,
@@ -123,9 +221,24 @@ fn run() -> Result<i32> {
             action,
         ),
 
-        Cmd::Ls | Cmd::List => cmd_ls(&st),
+        Cmd::Ls { filter, .. } | Cmd::List { filter, .. } => cmd_ls(&log, &st, &filter),
+
+        Cmd::Status { filter, json } => cmd_status(&log, &repo, &cfg, &st, &filter, json),
+
+        Cmd::Ui => cmd_ui(&log, &repo, &cfg, &mut st),
+
+        Cmd::Proxy { port, routes } => cmd_proxy(&log, &repo, &cfg, port, routes),
+
+        Cmd::Mcp => cmd_mcp(&log, &repo, &cfg, &mut st),
+
+        Cmd::Tag {
+            name,
+            meta,
+            unlabel,
+        } => cmd_tag(&log, &repo, &mut st, &name, &meta, &unlabel),
 
         Cmd::Path { name } => cmd_path(&log, &st, &name),
+        Cmd::Cd { name } => cmd_cd(&log, &repo, &st, name.as_deref()),
 
         Cmd::Env { name } => cmd_env(&log, &st, name.as_deref()),
 
@@ -133,15 +246,84 @@ fn run() -> Result<i32> {
             name,
             force,
             delete_branch,
+            backup,
         }
         | Cmd::Remove {
             name,
             force,
             delete_branch,
-        } => cmd_rm(&log, &repo, &mut st, &name, force, delete_branch),
+            backup,
+        } => {
+            // `--backup` also pushes the refs when there is a remote.
+            let opts = RemoveOpts {
+                force,
+                delete_branch,
+                backup,
+                push: backup,
+            };
+            cmd_rm(&log, &repo, &mut st, &name, &opts)
+        }
+
+        Cmd::Mv { old, new, branch } => cmd_mv(&log, &repo, &mut st, &old, &new, branch.as_deref()),
+
+        Cmd::Archive { name } => cmd_archive(&log, &repo, &mut st, &name),
+
+        Cmd::Restore { name } => cmd_restore(&log, &repo, &mut st, &name),
+
+        Cmd::Compare {
+            group,
+            check,
+            no_check,
+            json,
+        } => {
+            let opts = CompareOpts {
+                group: &group,
+                check: check.as_deref(),
+                no_check,
+                json,
+            };
+            cmd_compare(&log, &repo, &cfg, &st, opts)
+        }
+
+        Cmd::Pick {
+            name,
+            into,
+            mode,
+            others,
+        } => {
+            let how = match mode.as_str() {
+                "squash" => worktree::Integrate::Squash,
+                "cherry-pick" => worktree::Integrate::CherryPick,
+                _ => worktree::Integrate::Merge,
+            };
+            let opts = PickOpts {
+                name: &name,
+                into: into.as_deref(),
+                how,
+                others: &others,
+            };
+            cmd_pick(&log, &repo, &cfg, &mut st, opts)
+        }
+
+        Cmd::Du { sort, json } => cmd_du(&log, &st, &sort, json),
 
         Cmd::Prune => cmd_prune(&log, &repo, &mut st),
 
+        Cmd::Gc {
+            older_than,
+            merged,
+            dry_run,
+        } => {
+            let opts = GcOpts {
+                older_than: older_than.as_deref(),
+                merged,
+                dry_run,
+            };
+            cmd_gc(&log, &repo, &cfg, &mut st, opts)
+        }
+
+        Cmd::Doctor { fix } => cmd_doctor(&log, &repo, &mut st, fix),
+
         Cmd::Run { name, command } => {
             if !raw_run_has_sep(&raw_args) {
                 log.errorf("usage: wrt run <name> -- <command> [args...]");
@@ -149,6 +331,11 @@ fn run() -> Result<i32> {
             }
             cmd_run(&log, &st, &name, &command)
         }
-        Cmd::Completions { .. } => Ok(0),
+        Cmd::Foreach { filter, command } => cmd_foreach(&log, &st, &filter, &command),
+        Cmd::Help
+        | Cmd::Completions { .. }
+        | Cmd::Complete { .. }
+        | Cmd::ShellInit { .. }
+        | Cmd::Prompt { .. } => unreachable!("handled before repo detection"),
     }
 }
@@ -0,0 +1,181 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Map, Value};
+use std::io::{BufRead, Write};
+
+// Protocol revisions we speak; the newest is offered when the client asks for one we don't know.
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+// Tool is one tool of the server: its name, what it does and the JSON schema of its arguments.
+pub struct Tool {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub input_schema: Value,
+}
+
+// Outcome is a tool's result; `value` is returned as structured content (and as JSON text for
+// clients that only read text).
+pub struct Outcome {
+    pub value: Value,
+    pub is_error: bool,
+}
+
+impl Outcome {
+    pub fn ok(value: Value) -> Outcome {
+        Outcome {
+            value,
+            is_error: false,
+        }
+    }
+
+    pub fn error(msg: &str) -> Outcome {
+        Outcome {
+            value: json!({ "error": msg }),
+            is_error: true,
+        }
+    }
+}
+
+// serve speaks the Model Context Protocol (JSON-RPC 2.0, one message per line) until the input
+// ends. `call` runs a tool; it gets the tool name, its arguments and the client's name from
+// `initialize`, if it gave one.
+pub fn serve(
+    input: impl BufRead,
+    mut output: impl Write,
+    tools: &[Tool],
+    mut call: impl FnMut(&str, &Map<String, Value>, Option<&str>) -> Outcome,
+) -> Result<()> {
+    let mut client: Option<String> = None;
+    for line in input.lines() {
+        let line = line.context("read request")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let msg: Value = match serde_json::from_str(&line) {
+            Ok(v) => v,
+            Err(e) => {
+                reply(
+                    &mut output,
+                    &Value::Null,
+                    Err((-32700, format!("parse error: {e}"))),
+                )?;
+                continue;
+            }
+        };
+        // Notifications (initialized, cancelled) and responses need no answer.
+        let (Some(id), Some(method)) = (msg.get("id"), msg.get("method").and_then(Value::as_str))
+        else {
+            continue;
+        };
+        let params = msg.get("params").cloned().unwrap_or(Value::Null);
+
+        let res = match method {
+            "initialize" => {
+                client = params
+                    .pointer("/clientInfo/name")
+                    .and_then(Value::as_str)
+                    .map(str::to_string);
+                let asked = params.get("protocolVersion").and_then(Value::as_str);
+                let version = asked
+                    .filter(|v| PROTOCOL_VERSIONS.contains(v))
+                    .unwrap_or(PROTOCOL_VERSIONS[0]);
+                Ok(json!({
+                    "protocolVersion": version,
+                    "capabilities": { "tools": { "listChanged": false } },
+                    "serverInfo": { "name": "wrt", "version": env!("CARGO_PKG_VERSION") },
+                }))
+            }
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({
+                "tools": tools
+                    .iter()
+                    .map(|t| json!({
+                        "name": t.name,
+                        "description": t.description,
+                        "inputSchema": t.input_schema,
+                    }))
+                    .collect::<Vec<_>>(),
+            })),
+            "tools/call" => {
+                let name = params.get("name").and_then(Value::as_str).unwrap_or("");
+                let empty = Map::new();
+                let args = params
+                    .get("arguments")
+                    .and_then(Value::as_object)
+                    .unwrap_or(&empty);
+                if tools.iter().any(|t| t.name == name) {
+                    let out = call(name, args, client.as_deref());
+                    Ok(json!({
+                        "content": [{ "type": "text", "text": serde_json::to_string_pretty(&out.value)? }],
+                        "structuredContent": out.value,
+                        "isError": out.is_error,
+                    }))
+                } else {
+                    Err((-32602, format!("unknown tool: \"{name}\"")))
+                }
+            }
+            _ => Err((-32601, format!("method not found: {method}"))),
+        };
+        reply(&mut output, id, res)?;
+    }
+    Ok(())
+}
+
+fn reply(out: &mut impl Write, id: &Value, res: Result<Value, (i32, String)>) -> Result<()> {
+    let msg = match res {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }),
+    };
+    writeln!(out, "{msg}").context("write response")?;
+    out.flush().context("write response")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_initialize_list_and_call() {
+        let input = [
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2024-11-05","clientInfo":{"name":"agent-7"}}}"#,
+            r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"echo","arguments":{"x":1}}}"#,
+            r#"{"jsonrpc":"2.0","id":4,"method":"tools/call","params":{"name":"nope"}}"#,
+            r#"{"jsonrpc":"2.0","id":5,"method":"resources/list"}"#,
+            "not json",
+        ]
+        .join("\n");
+        let tools = [Tool {
+            name: "echo",
+            description: "echo the arguments",
+            input_schema: json!({ "type": "object" }),
+        }];
+        let mut out = Vec::new();
+        serve(input.as_bytes(), &mut out, &tools, |_, args, client| {
+            Outcome::ok(json!({ "args": args, "client": client }))
+        })
+        .unwrap();
+
+        let replies: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(replies.len(), 6);
+        assert_eq!(replies[0]["result"]["protocolVersion"], "2024-11-05");
+        assert_eq!(replies[1]["result"]["tools"][0]["name"], "echo");
+        assert_eq!(
+            replies[2]["result"]["structuredContent"],
+            json!({ "args": { "x": 1 }, "client": "agent-7" })
+        );
+        assert_eq!(replies[2]["result"]["isError"], false);
+        assert_eq!(replies[3]["error"]["code"], -32602);
+        assert_eq!(replies[4]["error"]["code"], -32601);
+        assert_eq!(replies[5]["error"]["code"], -32700);
+    }
+}
@@ -0,0 +1,242 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::codex;
+use crate::state::{Allocation, State};
+use crate::worktree;
+
+pub const DEFAULT_PORT: u16 = 8080;
+
+// Request heads larger than this are refused; they are not something a dev server needs.
+const MAX_HEAD: usize = 64 * 1024;
+
+// services reads the services with a known base port from the repo's .wrt.json, as
+// (hostname label, base port). Missing or unparseable files mean no services.
+pub fn services(repo_root: &Path) -> Vec<(String, i32)> {
+    let Ok(s) = fs::read_to_string(repo_root.join(".wrt.json")) else {
+        return Vec::new();
+    };
+    let Ok(d) = serde_json::from_str::<codex::Discovery>(&s) else {
+        return Vec::new();
+    };
+    d.services
+        .iter()
+        .filter_map(|s| Some((worktree::slug(&s.name), s.base_port?)))
+        .collect()
+}
+
+// hostname is the name the proxy routes to a worktree's service: <name>.<service>.localhost.
+pub fn hostname(name: &str, service: &str) -> String {
+    format!("{name}.{service}.localhost")
+}
+
+// urls maps each service to the worktree's stable proxy URL (exported as WRT_URL_<SERVICE>).
+pub fn urls(name: &str, services: &[(String, i32)], port: u16) -> BTreeMap<String, String> {
+    services
+        .iter()
+        .map(|(svc, _)| {
+            (
+                svc.clone(),
+                format!("http://{}:{port}", hostname(name, svc)),
+            )
+        })
+        .collect()
+}
+
+// routes maps every worktree's service hostnames to the address the service listens on: its
+// base port shifted by the worktree's offset, on the worktree's own host with loopback isolation.
+pub fn routes<'a>(
+    services: &[(String, i32)],
+    allocs: impl IntoIterator<Item = &'a Allocation>,
+) -> BTreeMap<String, String> {
+    let mut out = BTreeMap::new();
+    for a in allocs {
+        let host = a.host.as_deref().unwrap_or("127.0.0.1");
+        for (svc, base) in services {
+            out.insert(
+                hostname(&a.name, svc),
+                format!("{host}:{}", base + a.offset),
+            );
+        }
+    }
+    out
+}
+
+// route_key normalizes a Host header for lookup: lowercased, without port or trailing dot.
+fn route_key(host: &str) -> String {
+    let host = host.trim().to_lowercase();
+    let host = match host.rsplit_once(':') {
+        Some((h, port)) if port.chars().all(|c| c.is_ascii_digit()) => h.to_string(),
+        _ => host,
+    };
+    host.trim_end_matches('.').to_string()
+}
+
+// Table is the proxy's routing table. It is rebuilt whenever state.json or .wrt.json change, so
+// worktrees added or removed while the proxy runs are picked up on the next request.
+pub struct Table {
+    repo_root: PathBuf,
+    git_common_dir: PathBuf,
+    cache: Mutex<Cached>,
+}
+
+#[derive(Default)]
+struct Cached {
+    /// Modification times of .wrt.json and state.json the routes were built from.
+    stamps: Vec<Option<SystemTime>>,
+    routes: BTreeMap<String, String>,
+}
+
+impl Table {
+    pub fn new(repo_root: &Path, git_common_dir: &Path) -> Table {
+        Table {
+            repo_root: repo_root.into(),
+            git_common_dir: git_common_dir.into(),
+            cache: Mutex::new(Cached::default()),
+        }
+    }
+
+    pub fn current(&self) -> Result<BTreeMap<String, String>> {
+        let stamps: Vec<Option<SystemTime>> = [
+            self.repo_root.join(".wrt.json"),
+            self.git_common_dir.join(".wrt").join("state.json"),
+        ]
+        .iter()
+        .map(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+        .collect();
+
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if cache.stamps.is_empty() || cache.stamps != stamps {
+            let st = State::load(&self.git_common_dir)?;
+            cache.routes = routes(&services(&self.repo_root), st.allocations.values());
+            cache.stamps = stamps;
+        }
+        Ok(cache.routes.clone())
+    }
+}
+
+// serve accepts connections until the listener fails, one thread per connection.
+pub fn serve(listener: TcpListener, table: &Table) -> Result<()> {
+    thread::scope(|s| -> Result<()> {
+        for conn in listener.incoming() {
+            let conn = conn.context("accept")?;
+            s.spawn(move || {
+                let _ = handle(conn, table);
+            });
+        }
+        Ok(())
+    })
+}
+
+// handle reads the request head, picks the upstream by Host and from then on shuttles bytes both
+// ways, so keep-alive, streaming and WebSocket upgrades pass through untouched.
+fn handle(client: TcpStream, table: &Table) -> io::Result<()> {
+    client.set_read_timeout(Some(Duration::from_secs(30)))?;
+    let mut reader = BufReader::new(client.try_clone()?);
+    let mut head = Vec::new();
+    let mut host = None;
+    loop {
+        let n = reader.read_until(b'\n', &mut head)?;
+        if n == 0 || head.len() > MAX_HEAD {
+            return Ok(());
+        }
+        let line = String::from_utf8_lossy(&head[head.len() - n..]).to_string();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((k, v)) = line.split_once(':') {
+            if k.trim().eq_ignore_ascii_case("host") {
+                host = Some(route_key(v));
+            }
+        }
+    }
+
+    let mut client = client;
+    let routes = match table.current() {
+        Ok(r) => r,
+        Err(e) => return respond(&mut client, "500 Internal Server Error", &format!("{e}\n")),
+    };
+    let Some(upstream) = host.as_deref().and_then(|h| routes.get(h)) else {
+        let mut body = format!(
+            "wrt proxy: no worktree service at \"{}\"\n\nknown hosts:\n",
+            host.as_deref().unwrap_or("")
+        );
+        for h in routes.keys() {
+            body.push_str(&format!("  {h}\n"));
+        }
+        return respond(&mut client, "404 Not Found", &body);
+    };
+    let mut server = match TcpStream::connect(upstream) {
+        Ok(s) => s,
+        Err(e) => {
+            let body =
+                format!("wrt proxy: {upstream} is not answering ({e}); is the service up?\n");
+            return respond(&mut client, "502 Bad Gateway", &body);
+        }
+    };
+    client.set_read_timeout(None)?;
+    server.write_all(&head)?;
+
+    let mut upstream_rd = server.try_clone()?;
+    let mut client_wr = client.try_clone()?;
+    let back = thread::spawn(move || {
+        let _ = io::copy(&mut upstream_rd, &mut client_wr);
+        let _ = client_wr.shutdown(Shutdown::Write);
+    });
+    // The reader still holds whatever the client sent past the head (a request body).
+    let _ = io::copy(&mut reader, &mut server);
+    let _ = server.shutdown(Shutdown::Write);
+    let _ = back.join();
+    Ok(())
+}
+
+fn respond(client: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    write!(
+        client,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    client.flush()?;
+    // Drain what is left of the request so the client sees the response rather than a reset.
+    let _ = client.shutdown(Shutdown::Write);
+    let _ = client.read(&mut [0u8; 1024]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_follow_offsets_and_hosts() {
+        let services = vec![("web".to_string(), 3000), ("api".to_string(), 8000)];
+        let mut a = Allocation {
+            name: "a4".into(),
+            offset: 400,
+            ..Default::default()
+        };
+        let b = Allocation {
+            name: "b".into(),
+            host: Some("127.0.2.1".into()),
+            ..Default::default()
+        };
+        let r = routes(&services, [&a, &b]);
+        assert_eq!(r["a4.web.localhost"], "127.0.0.1:3400");
+        assert_eq!(r["a4.api.localhost"], "127.0.0.1:8400");
+        assert_eq!(r["b.web.localhost"], "127.0.2.1:3000");
+
+        a.urls = urls(&a.name, &services, 8080);
+        assert_eq!(a.urls["web"], "http://a4.web.localhost:8080");
+
+        assert_eq!(route_key(" A4.Web.localhost:8080"), "a4.web.localhost");
+        assert_eq!(route_key("a4.web.localhost."), "a4.web.localhost");
+    }
+}
@@ -0,0 +1,283 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::gitx;
+use crate::lock::FileLock;
+use crate::state::Allocation;
+
+const CURRENT_VER: i32 = 1;
+
+// Registry is the optional machine-wide record of every repo's worktrees
+// (~/.local/state/wrt/registry.json). With it, port blocks are unique across repos rather than
+// per repo, so worktrees of different repos can run side by side.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Registry {
+    #[serde(default)]
+    pub version: i32,
+    /// Keyed by the repo's git common dir.
+    #[serde(default)]
+    pub repos: BTreeMap<String, RepoEntry>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RepoEntry {
+    pub root: String,
+    #[serde(default)]
+    pub worktrees: BTreeMap<String, Allocation>,
+    /// Blocks handed out by `wrt new` whose worktree isn't in state.json yet.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub reserved: BTreeSet<i32>,
+}
+
+impl Registry {
+    pub fn load(p: &Path) -> Result<Registry> {
+        let b = match fs::read(p) {
+            Ok(b) => b,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Registry {
+                    version: CURRENT_VER,
+                    ..Default::default()
+                })
+            }
+            Err(e) => return Err(e).with_context(|| format!("read {}", p.display())),
+        };
+        let mut reg: Registry =
+            serde_json::from_slice(&b).with_context(|| format!("parse {}", p.display()))?;
+        if reg.version == 0 {
+            reg.version = CURRENT_VER;
+        }
+        Ok(reg)
+    }
+
+    fn save(&self, p: &Path) -> Result<()> {
+        if let Some(dir) = p.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("mkdir {}", dir.display()))?;
+        }
+        let mut b = serde_json::to_vec_pretty(self).context("json format")?;
+        b.push(b'\n');
+        // Other repos' wrt processes read this file without the lock; never leave it half-written.
+        let tmp = p.with_extension("json.tmp");
+        fs::write(&tmp, &b).with_context(|| format!("write {}", tmp.display()))?;
+        fs::rename(&tmp, p).with_context(|| format!("write {}", p.display()))?;
+        Ok(())
+    }
+
+    // blocks lists the blocks held by every repo except `key` (worktrees and reservations).
+    fn blocks_except(&self, key: &str) -> BTreeSet<i32> {
+        self.repos
+            .iter()
+            .filter(|(k, _)| k.as_str() != key)
+            .flat_map(|(_, r)| {
+                r.worktrees
+                    .values()
+                    .map(|a| a.block)
+                    .chain(r.reserved.iter().copied())
+            })
+            .collect()
+    }
+}
+
+// Link ties a repo's State to the registry.
+#[derive(Clone, Debug)]
+pub struct Link {
+    pub path: PathBuf,
+    /// The repo's git common dir (its key in the registry).
+    pub key: String,
+    pub root: String,
+}
+
+impl Link {
+    pub fn new(path: PathBuf, git_common_dir: &Path, root: &Path) -> Link {
+        let common = fs::canonicalize(git_common_dir).unwrap_or_else(|_| git_common_dir.into());
+        Link {
+            path,
+            key: common.to_string_lossy().to_string(),
+            root: root.to_string_lossy().to_string(),
+        }
+    }
+
+    // for_repo links the repo to the registry at its default location.
+    pub fn for_repo(repo: &gitx::Repo) -> Result<Link> {
+        let p =
+            default_path().ok_or_else(|| anyhow!("cannot locate ~/.local/state (HOME not set)"))?;
+        Ok(Link::new(p, &repo.common_dir, &repo.root))
+    }
+
+    // reserve picks the lowest block that is free in this repo (`used`) and in every other repo,
+    // and records it in the registry before releasing the lock.
+    pub fn reserve(&self, used: &BTreeSet<i32>) -> Result<i32> {
+        let _lock = lock(&self.path)?;
+        let mut reg = Registry::load(&self.path)?;
+        let taken = reg.blocks_except(&self.key);
+        let entry = self.entry(&mut reg);
+        // Block 0 is reserved for the main workdir (default ports).
+        let block = (1..10000)
+            .find(|b| !used.contains(b) && !taken.contains(b) && !entry.reserved.contains(b))
+            .ok_or_else(|| anyhow!("no free port blocks"))?;
+        entry.reserved.insert(block);
+        reg.save(&self.path)?;
+        Ok(block)
+    }
+
+    // claim reserves a given block (e.g. one a worktree already uses) unless another repo holds
+    // it; false if one does.
+    pub fn claim(&self, block: i32) -> Result<bool> {
+        let _lock = lock(&self.path)?;
+        let mut reg = Registry::load(&self.path)?;
+        if reg.blocks_except(&self.key).contains(&block) {
+            return Ok(false);
+        }
+        self.entry(&mut reg).reserved.insert(block);
+        reg.save(&self.path)?;
+        Ok(true)
+    }
+
+    // publish replaces this repo's entry with its current allocations (dropping reservations, which
+    // are either in state.json by now or belong to a `wrt new` that failed).
+    pub fn publish(&self, allocs: &BTreeMap<String, Allocation>) -> Result<()> {
+        let _lock = lock(&self.path)?;
+        let mut reg = Registry::load(&self.path)?;
+        if allocs.is_empty() {
+            reg.repos.remove(&self.key);
+        } else {
+            let entry = self.entry(&mut reg);
+            entry.worktrees = allocs.clone();
+            entry.reserved.clear();
+        }
+        reg.save(&self.path)
+    }
+
+    // holder names the worktree of another repo holding the block ("<root>: <name>"), if any. It
+    // reads without the lock; use reserve to actually claim a block.
+    pub fn holder(&self, block: i32) -> Option<String> {
+        let reg = Registry::load(&self.path).ok()?;
+        reg.repos
+            .iter()
+            .filter(|(k, _)| k.as_str() != self.key)
+            .find_map(|(_, r)| {
+                let name = match r.worktrees.values().find(|a| a.block == block) {
+                    Some(a) => a.name.as_str(),
+                    None if r.reserved.contains(&block) => "(being created)",
+                    None => return None,
+                };
+                Some(format!("{}: {name}", r.root))
+            })
+    }
+
+    pub fn in_use(&self, block: i32) -> bool {
+        self.holder(block).is_some()
+    }
+
+    // is_current reports whether the registry already lists exactly these allocations.
+    pub fn is_current(&self, allocs: &BTreeMap<String, Allocation>) -> bool {
+        let Ok(reg) = Registry::load(&self.path) else {
+            return false;
+        };
+        match reg.repos.get(&self.key) {
+            Some(e) => {
+                e.worktrees.keys().eq(allocs.keys())
+                    && e.worktrees.values().zip(allocs.values()).all(|(a, b)| {
+                        a.block == b.block && a.path == b.path && a.branch == b.branch
+                    })
+            }
+            None => allocs.is_empty(),
+        }
+    }
+
+    fn entry<'r>(&self, reg: &'r mut Registry) -> &'r mut RepoEntry {
+        let e = reg.repos.entry(self.key.clone()).or_default();
+        e.root = self.root.clone();
+        e
+    }
+}
+
+// forget_missing drops repos whose git dir no longer exists; returns their roots.
+pub fn forget_missing(p: &Path) -> Result<Vec<String>> {
+    let _lock = lock(p)?;
+    let mut reg = Registry::load(p)?;
+    let gone: Vec<String> = reg
+        .repos
+        .keys()
+        .filter(|k| !Path::new(k).exists())
+        .cloned()
+        .collect();
+    if gone.is_empty() {
+        return Ok(Vec::new());
+    }
+    let roots = gone
+        .iter()
+        .filter_map(|k| reg.repos.remove(k))
+        .map(|e| e.root)
+        .collect();
+    reg.save(p)?;
+    Ok(roots)
+}
+
+fn lock(p: &Path) -> Result<FileLock> {
+    FileLock::acquire(&p.with_extension("lock"), Duration::from_secs(120))
+}
+
+// default_path is $XDG_STATE_HOME/wrt/registry.json (~/.local/state/wrt/registry.json).
+pub fn default_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_STATE_HOME").filter(|v| !v.is_empty()) {
+        Some(v) => PathBuf::from(v),
+        None => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("state"),
+    };
+    Some(base.join("wrt").join("registry.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn blocks_are_unique_across_repos() {
+        let td = TempDir::new().unwrap();
+        let p = td.path().join("registry.json");
+        let a = Link::new(p.clone(), &td.path().join("a/.git"), &td.path().join("a"));
+        let b = Link::new(p.clone(), &td.path().join("b/.git"), &td.path().join("b"));
+
+        let mut allocs = BTreeMap::new();
+        allocs.insert(
+            "x".to_string(),
+            Allocation {
+                name: "x".into(),
+                block: 1,
+                ..Default::default()
+            },
+        );
+        a.publish(&allocs).unwrap();
+        assert!(b.in_use(1));
+        assert_eq!(
+            b.holder(1).unwrap(),
+            format!("{}: x", td.path().join("a").display())
+        );
+        assert!(!a.in_use(1));
+
+        // b's own state is empty, but a holds 1; a pending reservation holds 2.
+        assert_eq!(b.reserve(&BTreeSet::new()).unwrap(), 2);
+        assert_eq!(a.reserve(&BTreeSet::from([1])).unwrap(), 3);
+        assert!(a.in_use(2));
+        assert!(!a.claim(2).unwrap());
+        assert!(a.claim(4).unwrap());
+        assert!(b.in_use(4));
+
+        b.publish(&BTreeMap::new()).unwrap();
+        assert!(!a.in_use(2));
+        assert!(!a.is_current(&BTreeMap::new()));
+        a.publish(&allocs).unwrap();
+        assert!(a.is_current(&allocs));
+
+        // Neither a/.git nor b/.git exists on disk.
+        assert_eq!(forget_missing(&p).unwrap().len(), 1);
+        assert!(Registry::load(&p).unwrap().repos.is_empty());
+    }
+}
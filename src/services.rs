@@ -0,0 +1,37 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::supabase;
+use crate::ui;
+use crate::util::{run_cmd, which};
+
+// stop shuts down the services wrt manages for a worktree (today: its isolated Supabase stack).
+// Returns Ok(false) when there was nothing to stop.
+pub fn stop(log: &ui::Logger, wt_path: &Path) -> Result<bool> {
+    if !wt_path.exists() || !supabase::has_config(wt_path) || which("supabase").is_none() {
+        return Ok(false);
+    }
+    log.infof("stopping supabase containers");
+    run_cmd(wt_path, "supabase", &["stop"])?;
+    Ok(true)
+}
+
+// start brings the worktree's services back up (see `stop`).
+pub fn start(log: &ui::Logger, wt_path: &Path) -> Result<bool> {
+    if !supabase::has_config(wt_path) || which("supabase").is_none() {
+        return Ok(false);
+    }
+    log.infof("supabase start (isolated ports, project_id suffix)");
+    run_cmd(wt_path, "supabase", &["start"])?;
+    Ok(true)
+}
+
+// running reports whether the worktree's services are up, given the names from
+// supabase::running_containers. None when the worktree has no services.
+pub fn running(wt_path: &Path, containers: &[String]) -> Option<bool> {
+    if !supabase::has_config(wt_path) {
+        return None;
+    }
+    let id = supabase::project_id(wt_path).ok()??;
+    Some(containers.iter().any(|c| supabase::owned_by(c, &id)))
+}
@@ -0,0 +1,118 @@
+use crate::completions;
+
+// Shell integration printed by `wrt shell-init <shell>`: the completions, a `wrt` function that can
+// change the shell's directory (`wrt cd`, `wrt new` without --count) and a hook that exports a
+// worktree's .wrt.env while the shell is inside it (and unsets it again on the way out).
+
+/// Shells `wrt shell-init` supports.
+pub const SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+
+pub fn init_script(shell: &str) -> Option<String> {
+    let shell = shell.trim().to_lowercase();
+    let (wrapper, hook) = match shell.as_str() {
+        "bash" => (POSIX_WRAPPER, format!("{POSIX_HOOK}\n{BASH_REGISTER}")),
+        "zsh" => (POSIX_WRAPPER, format!("{POSIX_HOOK}\n{ZSH_REGISTER}")),
+        "fish" => (FISH_WRAPPER, FISH_HOOK.to_string()),
+        _ => return None,
+    };
+    let completions = completions::script(&shell)?;
+    Some(format!("{completions}\n{wrapper}\n{hook}"))
+}
+
+const POSIX_WRAPPER: &str = r#"wrt() {
+  case "$1" in
+    cd)
+      shift
+      local dir
+      dir="$(command wrt cd "$@")" || return
+      builtin cd -- "$dir"
+      ;;
+    new|spawn)
+      local arg out
+      for arg in "$@"; do
+        case "$arg" in
+          --count|--count=*|--json|--cd|--help|-h) command wrt "$@"; return ;;
+        esac
+      done
+      out="$(command wrt "$@" --cd)" || return
+      eval "$out"
+      ;;
+    *)
+      command wrt "$@"
+      ;;
+  esac
+}
+"#;
+
+// _wrt_env_hook finds the nearest .wrt.env above $PWD and exports its WRT_* lines verbatim; other
+// variables are ignored, so a .wrt.env committed to some repo can't set e.g. PATH.
+const POSIX_HOOK: &str = r#"_wrt_env_hook() {
+  local dir=$PWD line
+  while [[ -n $dir && ! -f $dir/.wrt.env ]]; do dir=${dir%/*}; done
+  [[ -n $dir && $dir/.wrt.env == "${_WRT_ENV_FILE-}" ]] && return 0
+  [[ -n ${_WRT_ENV_VARS-} ]] && eval "unset $_WRT_ENV_VARS"
+  _WRT_ENV_FILE= _WRT_ENV_VARS=
+  [[ -n $dir ]] || return 0
+  _WRT_ENV_FILE=$dir/.wrt.env
+  while IFS= read -r line || [[ -n $line ]]; do
+    [[ $line =~ ^WRT_[A-Za-z0-9_]*= ]] || continue
+    export "$line"
+    _WRT_ENV_VARS+=" ${line%%=*}"
+  done < "$_WRT_ENV_FILE"
+}
+"#;
+
+const BASH_REGISTER: &str = r#"case ";${PROMPT_COMMAND-};" in
+  *";_wrt_env_hook;"*) ;;
+  *) PROMPT_COMMAND="_wrt_env_hook${PROMPT_COMMAND:+;$PROMPT_COMMAND}" ;;
+esac
+_wrt_env_hook
+"#;
+
+const ZSH_REGISTER: &str = r#"autoload -Uz add-zsh-hook
+add-zsh-hook chpwd _wrt_env_hook
+_wrt_env_hook
+"#;
+
+const FISH_WRAPPER: &str = r#"function wrt --description 'wrt, with `wrt cd` and auto-cd after `wrt new`'
+    switch "$argv[1]"
+        case cd
+            set -e argv[1]
+            set -l dir (command wrt cd $argv); or return
+            cd $dir
+        case new spawn
+            if string match -qr -- '^(--count(=.*)?|--json|--cd|--help|-h)$' $argv
+                command wrt $argv
+                return
+            end
+            set -l out (command wrt $argv --cd); or return
+            eval $out
+        case '*'
+            command wrt $argv
+    end
+end
+"#;
+
+const FISH_HOOK: &str = r#"function __wrt_env_hook --on-variable PWD
+    set -l dir $PWD
+    while test -n "$dir"; and not test -f "$dir/.wrt.env"
+        set dir (string replace -r '/[^/]*$' '' -- $dir)
+    end
+    if test -n "$dir"; and test "$dir/.wrt.env" = "$__wrt_env_file"
+        return
+    end
+    for var in $__wrt_env_vars
+        set -e $var
+    end
+    set -g __wrt_env_vars
+    set -g __wrt_env_file
+    test -n "$dir"; or return 0
+    set -g __wrt_env_file $dir/.wrt.env
+    for line in (string match -r '^WRT_[A-Za-z0-9_]*=.*' < $__wrt_env_file)
+        set -l kv (string split -m1 = -- $line)
+        set -gx $kv[1] $kv[2]
+        set -a __wrt_env_vars $kv[1]
+    end
+end
+__wrt_env_hook
+"#;
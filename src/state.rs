@@ -3,9 +3,15 @@ use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::Isolation;
+use crate::lock::FileLock;
+use crate::registry;
 
 const STATE_DIR_NAME: &str = ".wrt";
 const STATE_FILE_NAME: &str = "state.json";
+const LOCK_FILE_NAME: &str = "state.lock";
 const CURRENT_VER: i32 = 1;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -14,17 +20,121 @@ pub struct State {
     pub version: i32,
     #[serde(default)]
     pub allocations: BTreeMap<String, Allocation>,
+    /// Worktrees parked with `wrt archive`: checkout removed, block released, metadata kept.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub archived: BTreeMap<String, ArchivedAllocation>,
+    /// Set when the machine-wide registry is on (config `registry`): blocks are then allocated
+    /// across all repos and every save is published to it.
+    #[serde(skip)]
+    pub registry: Option<registry::Link>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Allocation {
     pub name: String,
     pub branch: String,
     pub path: String,
     pub block: i32,
     pub offset: i32,
+    /// Loopback address of the worktree's own (config `isolation: "loopback"`), exported as
+    /// WRT_HOST. Such worktrees keep the default ports: offset is 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Stable `wrt proxy` URL of each service in .wrt.json, exported as WRT_URL_<SERVICE>.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub urls: BTreeMap<String, String>,
     #[serde(rename = "createdAt")]
     pub created_at: String,
+    /// Ref the worktree was branched from (`wrt status` compares against it).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_ref: Option<String>,
+    /// Batch the worktree was spawned in (`wrt new --count`), shared by its siblings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Cone-mode sparse-checkout paths (empty means a full checkout).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sparse: Vec<String>,
+    /// Pull/merge request number the worktree was created from (`wrt new --pr`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pr: Option<u64>,
+    /// Free-form key/value labels (`--label k=v`), e.g. agent=gpt or team=web.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Person responsible for the worktree.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Agent (or tool) working in the worktree.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+    /// Issue/ticket the work belongs to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_url: Option<String>,
+    /// When `wrt gc` may remove the worktree regardless of activity (`wrt new --ttl`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    /// Creation profile (`wrt new --profile`, or the configured default).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Provisioning steps that have completed (env, files, supabase, install, db; see `wrt setup`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<String>,
+    /// Why setup failed, when `wrt new --keep-on-failure` kept the worktree or a `wrt setup` failed;
+    /// a `wrt setup` that succeeds clears it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failed: Option<String>,
+}
+
+impl Allocation {
+    // assign_block gives the allocation a port block, and with it either a port offset or (loopback
+    // isolation) an address of its own.
+    pub fn assign_block(&mut self, block: i32, isolation: Isolation) {
+        self.block = block;
+        match isolation {
+            Isolation::Ports => {
+                self.offset = block * 100;
+                self.host = None;
+            }
+            Isolation::Loopback => {
+                self.offset = 0;
+                self.host = Some(loopback_host(block));
+            }
+        }
+    }
+
+    pub fn isolation(&self) -> Isolation {
+        match self.host {
+            Some(_) => Isolation::Loopback,
+            None => Isolation::Ports,
+        }
+    }
+
+    // is_consistent reports whether offset and host are the ones assign_block would set.
+    pub fn is_consistent(&self) -> bool {
+        let mut want = self.clone();
+        want.assign_block(self.block, self.isolation());
+        want.offset == self.offset && want.host == self.host
+    }
+}
+
+// loopback_host is the block's address in 127.0.0.0/8: 127.0.<block>.1 for blocks below 256.
+pub fn loopback_host(block: i32) -> String {
+    format!("127.{}.{}.1", block / 256, block % 256)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArchivedAllocation {
+    #[serde(flatten)]
+    pub alloc: Allocation,
+    #[serde(rename = "archivedAt")]
+    pub archived_at: String,
+    /// Ref holding a snapshot commit of the uncommitted changes, if there were any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wip_ref: Option<String>,
+    /// Whether supabase/config.toml was patched (so restore re-applies the patch).
+    #[serde(default)]
+    pub supabase_patched: bool,
 }
 
 impl State {
@@ -36,6 +146,8 @@ impl State {
                 return Ok(State {
                     version: CURRENT_VER,
                     allocations: BTreeMap::new(),
+                    archived: BTreeMap::new(),
+                    registry: None,
                 })
             }
             Err(e) => return Err(e).with_context(|| format!("read {}", p.display())),
@@ -49,13 +161,60 @@ impl State {
         Ok(st)
     }
 
+    // reload re-reads state.json, keeping the registry link.
+    pub fn reload(&mut self, git_common_dir: &Path) -> Result<()> {
+        let registry = self.registry.take();
+        *self = State::load(git_common_dir)?;
+        self.registry = registry;
+        Ok(())
+    }
+
+    // link_registry turns on the machine-wide registry, publishing this repo's worktrees if the
+    // registry doesn't list them yet (e.g. the first run after enabling it).
+    pub fn link_registry(&mut self, link: registry::Link) -> Result<()> {
+        if !link.is_current(&self.allocations) {
+            link.publish(&self.allocations)?;
+        }
+        self.registry = Some(link);
+        Ok(())
+    }
+
+    // lock serializes read-modify-write cycles of state.json across wrt processes. Load the state
+    // again after acquiring it; another process may have changed it meanwhile.
+    pub fn lock(git_common_dir: &Path) -> Result<FileLock> {
+        let p = git_common_dir.join(STATE_DIR_NAME).join(LOCK_FILE_NAME);
+        FileLock::acquire(&p, Duration::from_secs(120))
+    }
+
+    // update applies a change to state.json under the state lock: it reloads the state first, so
+    // whatever other wrt processes saved meanwhile is kept, applies `f` and saves. Every
+    // read-modify-write of the state should go through it (or hold the lock the same way).
+    pub fn update<R>(
+        &mut self,
+        git_common_dir: &Path,
+        f: impl FnOnce(&mut State) -> R,
+    ) -> Result<R> {
+        let _lock = State::lock(git_common_dir)?;
+        self.reload(git_common_dir)?;
+        let r = f(self);
+        self.save(git_common_dir)?;
+        Ok(r)
+    }
+
     pub fn save(&self, git_common_dir: &Path) -> Result<()> {
         let dir = git_common_dir.join(STATE_DIR_NAME);
         fs::create_dir_all(&dir).with_context(|| format!("mkdir {}", dir.display()))?;
         let mut b = serde_json::to_vec_pretty(self).context("json format")?;
         b.push(b'\n');
         let p = file_path(git_common_dir);
-        fs::write(&p, &b).with_context(|| format!("write {}", p.display()))?;
+        // Write a temp file and rename it over: readers never see a half-written state.json.
+        let tmp = p.with_extension("json.tmp");
+        fs::write(&tmp, &b).with_context(|| format!("write {}", tmp.display()))?;
+        fs::rename(&tmp, &p).with_context(|| format!("write {}", p.display()))?;
+        if let Some(link) = &self.registry {
+            link.publish(&self.allocations)
+                .context("update machine-wide registry")?;
+        }
         Ok(())
     }
 
@@ -64,6 +223,9 @@ impl State {
         for a in self.allocations.values() {
             used.insert(a.block);
         }
+        if let Some(link) = &self.registry {
+            return link.reserve(&used);
+        }
         // Block 0 is reserved for the main workdir (default ports).
         for i in 1..10000 {
             if !used.contains(&i) {
@@ -73,6 +235,11 @@ impl State {
         Err(anyhow!("no free port blocks"))
     }
 
+    pub fn block_in_use(&self, block: i32) -> bool {
+        self.allocations.values().any(|a| a.block == block)
+            || self.registry.as_ref().is_some_and(|l| l.in_use(block))
+    }
+
     pub fn sorted_allocations(&self) -> Vec<Allocation> {
         self.allocations.values().cloned().collect()
     }
@@ -91,6 +258,8 @@ mod tests {
         let mut st = State {
             version: CURRENT_VER,
             allocations: BTreeMap::new(),
+            archived: BTreeMap::new(),
+            registry: None,
         };
         st.allocations.insert(
             "a".to_string(),
@@ -101,6 +270,7 @@ mod tests {
                 block: 1,
                 offset: 100,
                 created_at: "x".to_string(),
+                ..Default::default()
             },
         );
         st.allocations.insert(
@@ -112,9 +282,43 @@ mod tests {
                 block: 3,
                 offset: 300,
                 created_at: "x".to_string(),
+                ..Default::default()
             },
         );
 
         assert_eq!(st.allocate_block().unwrap(), 2);
     }
+
+    #[test]
+    fn update_keeps_changes_saved_meanwhile() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut st = State::load(dir.path()).unwrap();
+        let mut other = State::load(dir.path()).unwrap();
+        other
+            .allocations
+            .insert("a".to_string(), Allocation::default());
+        other.save(dir.path()).unwrap();
+
+        st.update(dir.path(), |s| {
+            s.allocations.insert("b".to_string(), Allocation::default())
+        })
+        .unwrap();
+        let saved = State::load(dir.path()).unwrap();
+        assert_eq!(saved.allocations.keys().collect::<Vec<_>>(), ["a", "b"]);
+        assert!(!dir.path().join(".wrt/state.json.tmp").exists());
+    }
+
+    #[test]
+    fn loopback_isolation_assigns_hosts() {
+        let mut a = Allocation::default();
+        a.assign_block(3, Isolation::Loopback);
+        assert_eq!((a.offset, a.host.as_deref()), (0, Some("127.0.3.1")));
+        assert!(a.is_consistent());
+        assert_eq!(loopback_host(300), "127.1.44.1");
+
+        a.assign_block(3, Isolation::Ports);
+        assert_eq!((a.offset, a.host.as_deref()), (300, None));
+        a.offset = 200;
+        assert!(!a.is_consistent());
+    }
 }
@@ -2,6 +2,7 @@ use anyhow::{anyhow, Context, Result};
 use regex::Regex;
 use std::fs;
 use std::path::Path;
+use std::process::Command;
 
 fn re_port_assign() -> &'static Regex {
     static RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
@@ -17,9 +18,15 @@ fn re_project_id() -> &'static Regex {
 }
 
 fn re_local_url_port() -> &'static Regex {
+    static RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(https?://)(127\.0\.0\.1|localhost):(\d+)").expect("regex"))
+}
+
+// Like re_local_url_port, plus the 127.x.y.z hosts of loopback isolation.
+fn re_loopback_url_port() -> &'static Regex {
     static RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
     RE.get_or_init(|| {
-        Regex::new(r"((?:https?://)(?:127\.0\.0\.1|localhost)):(\d+)").expect("regex")
+        Regex::new(r"(https?://)(127\.\d+\.\d+\.\d+|localhost):(\d+)").expect("regex")
     })
 }
 
@@ -31,8 +38,14 @@ pub fn has_config(repo_root: &Path) -> bool {
 // supabase instances can run concurrently:
 // - project_id gets a suffix derived from worktree name
 // - port/shadow_port etc are incremented by offset
-// - localhost URLs with explicit ports get the same offset
-pub fn patch_config(worktree_root: &Path, worktree_name: &str, offset: i32) -> Result<()> {
+// - localhost URLs with explicit ports get the same offset, and with `host` (loopback isolation)
+//   point at that address instead
+pub fn patch_config(
+    worktree_root: &Path,
+    worktree_name: &str,
+    offset: i32,
+    host: Option<&str>,
+) -> Result<()> {
     let p = worktree_root.join("supabase").join("config.toml");
     let b = fs::read_to_string(&p).with_context(|| format!("read {}", p.display()))?;
 
@@ -78,13 +91,15 @@ pub fn patch_config(worktree_root: &Path, worktree_name: &str, offset: i32) -> R
 
         if line.contains("http://") || line.contains("https://") {
             let nline = re_local_url_port().replace_all(line, |caps: &regex::Captures| {
-                let host = caps.get(1).unwrap().as_str();
-                let port: i32 = caps.get(2).unwrap().as_str().parse().unwrap_or(0);
+                let scheme = caps.get(1).unwrap().as_str();
+                let old_host = caps.get(2).unwrap().as_str();
+                let port: i32 = caps.get(3).unwrap().as_str().parse().unwrap_or(0);
+                let h = host.unwrap_or(old_host);
                 let p2 = port + offset;
                 if !(1..=65535).contains(&p2) {
-                    return format!("{host}:{port}");
+                    return format!("{scheme}{h}:{port}");
                 }
-                format!("{host}:{p2}")
+                format!("{scheme}{h}:{p2}")
             });
             let nline = nline.to_string();
             if nline != *line {
@@ -107,6 +122,200 @@ pub fn patch_config(worktree_root: &Path, worktree_name: &str, offset: i32) -> R
     Ok(())
 }
 
+// patched_offset reports whether the worktree's supabase/config.toml was already patched for
+// `worktree_name` (project_id carries its suffix). If so, the applied port offset is recovered by
+// diffing port assignments against the committed, unpatched config; this lets `wrt adopt` reclaim
+// the block a worktree had before its state entry was lost.
+pub fn patched_offset(worktree_root: &Path, worktree_name: &str) -> Result<Option<i32>> {
+    let p = worktree_root.join("supabase").join("config.toml");
+    let current = fs::read_to_string(&p).with_context(|| format!("read {}", p.display()))?;
+    if !is_patched_for(&current, worktree_name) {
+        return Ok(None);
+    }
+    let original = committed_config(worktree_root)?;
+    Ok(Some(offset_between(&original, &current).unwrap_or(0)))
+}
+
+// restore_config overwrites the worktree's config with the committed version, undoing a patch.
+pub fn restore_config(worktree_root: &Path) -> Result<()> {
+    let original = committed_config(worktree_root)?;
+    let p = worktree_root.join("supabase").join("config.toml");
+    fs::write(&p, original.as_bytes()).with_context(|| format!("write {}", p.display()))?;
+    Ok(())
+}
+
+// has_local_edits reports whether the worktree's config differs from the committed one in more
+// than wrt's patch (the project_id suffix, ports, localhost URLs). restore_config would throw
+// such edits away.
+pub fn has_local_edits(worktree_root: &Path) -> Result<bool> {
+    let p = worktree_root.join("supabase").join("config.toml");
+    let current = fs::read_to_string(&p).with_context(|| format!("read {}", p.display()))?;
+    Ok(edited_beyond_patch(
+        &committed_config(worktree_root)?,
+        &current,
+    ))
+}
+
+// project_id returns the project_id from the worktree's supabase/config.toml, if set.
+pub fn project_id(worktree_root: &Path) -> Result<Option<String>> {
+    let p = worktree_root.join("supabase").join("config.toml");
+    let s = fs::read_to_string(&p).with_context(|| format!("read {}", p.display()))?;
+    Ok(s.lines().find_map(|line| {
+        re_project_id()
+            .captures(line)
+            .and_then(|c| c.get(2))
+            .map(|m| m.as_str().to_string())
+    }))
+}
+
+// volume_sizes lists docker volumes and their sizes in bytes (`docker system df -v`). The supabase
+// CLI names its volumes `supabase_<service>_<project_id>`; see volumes_size.
+pub fn volume_sizes() -> Result<Vec<(String, u64)>> {
+    #[derive(serde::Deserialize)]
+    struct Volume {
+        #[serde(rename = "Name")]
+        name: String,
+        #[serde(rename = "Size")]
+        size: String,
+    }
+
+    let out = Command::new("docker")
+        .args(["system", "df", "-v", "--format", "{{json .Volumes}}"])
+        .output()
+        .context("run docker")?;
+    if !out.status.success() {
+        return Err(anyhow!(
+            "docker system df failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+    let vols: Option<Vec<Volume>> =
+        serde_json::from_slice(&out.stdout).context("parse docker system df output")?;
+    Ok(vols
+        .unwrap_or_default()
+        .into_iter()
+        .map(|v| (v.name, parse_human_size(&v.size).unwrap_or(0)))
+        .collect())
+}
+
+// volumes_size sums the sizes of the supabase volumes belonging to project_id.
+pub fn volumes_size(vols: &[(String, u64)], project_id: &str) -> u64 {
+    vols.iter()
+        .filter(|(name, _)| owned_by(name, project_id))
+        .map(|(_, size)| size)
+        .sum()
+}
+
+// running_containers lists the names of running docker containers.
+pub fn running_containers() -> Result<Vec<String>> {
+    let out = Command::new("docker")
+        .args(["ps", "--format", "{{.Names}}"])
+        .output()
+        .context("run docker")?;
+    if !out.status.success() {
+        return Err(anyhow!(
+            "docker ps failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect())
+}
+
+// owned_by reports whether a docker container/volume name belongs to the supabase project
+// (`supabase_<service>_<project_id>`).
+pub fn owned_by(name: &str, project_id: &str) -> bool {
+    name.starts_with("supabase_") && name.ends_with(&format!("_{project_id}"))
+}
+
+// parse_human_size reads docker's decimal sizes ("0B", "41.2MB", "1.5GB").
+fn parse_human_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let split = s.find(|c: char| c.is_ascii_alphabetic())?;
+    let (num, unit) = s.split_at(split);
+    let n: f64 = num.trim().parse().ok()?;
+    let mult = match unit.to_ascii_lowercase().as_str() {
+        "b" => 1.0,
+        "kb" => 1e3,
+        "mb" => 1e6,
+        "gb" => 1e9,
+        "tb" => 1e12,
+        _ => return None,
+    };
+    Some((n * mult) as u64)
+}
+
+fn committed_config(worktree_root: &Path) -> Result<String> {
+    let out = Command::new("git")
+        .args(["show", "HEAD:supabase/config.toml"])
+        .current_dir(worktree_root)
+        .output()
+        .context("git show HEAD:supabase/config.toml")?;
+    if !out.status.success() {
+        return Err(anyhow!("supabase/config.toml is not committed at HEAD"));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
+
+fn is_patched_for(config: &str, worktree_name: &str) -> bool {
+    let suffix = sanitize_suffix(worktree_name);
+    if suffix.is_empty() {
+        return false;
+    }
+    config.lines().any(|line| {
+        re_project_id()
+            .captures(line)
+            .and_then(|c| c.get(2))
+            .is_some_and(|m| m.as_str().ends_with(&format!("-{suffix}")))
+    })
+}
+
+// edited_beyond_patch compares the configs line by line, ignoring what patch_config rewrites.
+fn edited_beyond_patch(original: &str, current: &str) -> bool {
+    let orig: Vec<&str> = original.trim_end_matches('\n').split('\n').collect();
+    let cur: Vec<&str> = current.trim_end_matches('\n').split('\n').collect();
+    orig.len() != cur.len() || orig.iter().zip(&cur).any(|(o, c)| !same_but_patch(o, c))
+}
+
+fn same_but_patch(original: &str, current: &str) -> bool {
+    if original == current {
+        return true;
+    }
+    if let (Some(o), Some(c)) = (
+        re_project_id().captures(original),
+        re_project_id().captures(current),
+    ) {
+        return o[1] == c[1] && o[3] == c[3] && c[2].starts_with(&format!("{}-", &o[2]));
+    }
+    // Ports and localhost URLs may be shifted or re-hosted; anything else must be unchanged.
+    let shape = |line: &str| match re_port_assign().captures(line) {
+        Some(c) => format!("{}N{}", &c[1], &c[3]),
+        None => re_loopback_url_port()
+            .replace_all(line, "${1}H:N")
+            .to_string(),
+    };
+    shape(original) == shape(current)
+}
+
+// offset_between returns the offset of the first port assignment that differs between the two
+// configs (patch_config shifts every port by the same amount).
+fn offset_between(original: &str, patched: &str) -> Option<i32> {
+    let ports = |s: &str| -> Vec<i32> {
+        s.lines()
+            .filter_map(|line| re_port_assign().captures(line))
+            .filter_map(|c| c.get(2)?.as_str().parse().ok())
+            .collect()
+    };
+    ports(original)
+        .into_iter()
+        .zip(ports(patched))
+        .map(|(a, b)| b - a)
+        .find(|d| *d != 0)
+}
+
 fn sanitize_suffix(s: &str) -> String {
     let mut s = s.trim().to_lowercase();
 
@@ -137,6 +346,23 @@ mod tests {
     use super::*;
     use tempfile::TempDir;
 
+    #[test]
+    fn volumes_are_attributed_by_project_id() {
+        assert_eq!(parse_human_size("41.2MB"), Some(41_200_000));
+        assert_eq!(parse_human_size("0B"), Some(0));
+        assert_eq!(parse_human_size("1.5GB"), Some(1_500_000_000));
+        assert_eq!(parse_human_size("N/A"), None);
+
+        let vols = vec![
+            ("supabase_db_myproj-a1".to_string(), 100),
+            ("supabase_storage_myproj-a1".to_string(), 20),
+            ("supabase_db_myproj".to_string(), 7),
+            ("other_myproj-a1".to_string(), 1),
+        ];
+        assert_eq!(volumes_size(&vols, "myproj-a1"), 120);
+        assert_eq!(volumes_size(&vols, "myproj"), 7);
+    }
+
     #[test]
     fn sanitize_suffix_limits_and_dashes() {
         assert_eq!(sanitize_suffix("A B C"), "a-b-c");
@@ -154,6 +380,28 @@ mod tests {
         assert_eq!(sanitize_suffix("---"), "");
     }
 
+    #[test]
+    fn edits_beyond_the_patch_are_told_apart() {
+        let original =
+            "project_id = \"p\"\nport = 5432\nsite_url = \"http://localhost:3000\"\n# x\n";
+        let patched =
+            "project_id = \"p-wt\"\nport = 5532\nsite_url = \"http://127.0.1.2:3100\"\n# x\n";
+        assert!(!edited_beyond_patch(original, original));
+        assert!(!edited_beyond_patch(original, patched));
+        assert!(edited_beyond_patch(
+            original,
+            &patched.replace("# x", "# y")
+        ));
+        assert!(edited_beyond_patch(
+            original,
+            &format!("{patched}extra = 1\n")
+        ));
+        assert!(edited_beyond_patch(
+            original,
+            &patched.replace("p-wt", "q-wt")
+        ));
+    }
+
     #[test]
     fn patch_config_updates_ports_and_project_and_urls() {
         let td = TempDir::new().unwrap();
@@ -166,13 +414,33 @@ mod tests {
         )
         .unwrap();
 
-        patch_config(td.path(), "a-gpt-fix", 200).unwrap();
+        patch_config(td.path(), "a-gpt-fix", 200, None).unwrap();
         let out = fs::read_to_string(&p).unwrap();
         assert!(out.contains("project_id = \"myproj-a-gpt-fix\""));
         assert!(out.contains("port = 5632"));
         assert!(out.contains("http://localhost:3200"));
     }
 
+    #[test]
+    fn patch_config_loopback_host_keeps_ports() {
+        let td = TempDir::new().unwrap();
+        let sbdir = td.path().join("supabase");
+        fs::create_dir_all(&sbdir).unwrap();
+        let p = sbdir.join("config.toml");
+        fs::write(
+            &p,
+            "project_id = \"myproj\"\nport = 5432\nsite_url = \"http://127.0.0.1:3000\"\nredirects = [\"https://localhost:3000/cb\"]\n",
+        )
+        .unwrap();
+
+        patch_config(td.path(), "wt", 0, Some("127.0.2.1")).unwrap();
+        let out = fs::read_to_string(&p).unwrap();
+        assert!(out.contains("project_id = \"myproj-wt\""));
+        assert!(out.contains("port = 5432"));
+        assert!(out.contains("\"http://127.0.2.1:3000\""));
+        assert!(out.contains("\"https://127.0.2.1:3000/cb\""));
+    }
+
     #[test]
     fn patch_config_rejects_port_overflow() {
         let td = TempDir::new().unwrap();
@@ -181,7 +449,7 @@ mod tests {
         let p = sbdir.join("config.toml");
         fs::write(&p, "port = 65500\n").unwrap();
 
-        let result = patch_config(td.path(), "test", 100);
+        let result = patch_config(td.path(), "test", 100, None);
         assert!(result.is_err());
         let err = result.unwrap_err().to_string();
         assert!(
@@ -199,7 +467,7 @@ mod tests {
         let p = sbdir.join("config.toml");
         fs::write(&p, "port = 65435\n").unwrap();
 
-        patch_config(td.path(), "test", 100).unwrap();
+        patch_config(td.path(), "test", 100, None).unwrap();
         let out = fs::read_to_string(&p).unwrap();
         assert!(
             out.contains("port = 65535"),
@@ -215,10 +483,10 @@ mod tests {
         let p = sbdir.join("config.toml");
         fs::write(&p, "project_id = \"myproj\"\n").unwrap();
 
-        patch_config(td.path(), "wt1", 0).unwrap();
+        patch_config(td.path(), "wt1", 0, None).unwrap();
         let after_first = fs::read_to_string(&p).unwrap();
 
-        patch_config(td.path(), "wt1", 0).unwrap();
+        patch_config(td.path(), "wt1", 0, None).unwrap();
         let after_second = fs::read_to_string(&p).unwrap();
 
         assert_eq!(
@@ -236,7 +504,7 @@ mod tests {
         let p = sbdir.join("config.toml");
         fs::write(&p, "project_id = \"myproj-wt1\"\n").unwrap();
 
-        patch_config(td.path(), "wt1", 0).unwrap();
+        patch_config(td.path(), "wt1", 0, None).unwrap();
         let out = fs::read_to_string(&p).unwrap();
         assert!(
             out.contains("project_id = \"myproj-wt1\""),
@@ -261,7 +529,7 @@ pop3_port = 1100
         )
         .unwrap();
 
-        patch_config(td.path(), "test", 100).unwrap();
+        patch_config(td.path(), "test", 100, None).unwrap();
         let out = fs::read_to_string(&p).unwrap();
         assert!(out.contains("port = 5532"), "port not offset: {out}");
         assert!(
@@ -286,7 +554,7 @@ pop3_port = 1100
         let p = sbdir.join("config.toml");
         fs::write(&p, "port = 5432 # database port\n").unwrap();
 
-        patch_config(td.path(), "test", 100).unwrap();
+        patch_config(td.path(), "test", 100, None).unwrap();
         let out = fs::read_to_string(&p).unwrap();
         assert!(
             out.contains("port = 5532 # database port"),
@@ -294,10 +562,27 @@ pop3_port = 1100
         );
     }
 
+    #[test]
+    fn offset_between_recovers_patch_offset() {
+        let original = "project_id = \"p\"\n[api]\nport = 54321\n[db]\nport = 54322\n";
+        let td = TempDir::new().unwrap();
+        let sbdir = td.path().join("supabase");
+        fs::create_dir_all(&sbdir).unwrap();
+        fs::write(sbdir.join("config.toml"), original).unwrap();
+        patch_config(td.path(), "wt-3", 300, None).unwrap();
+        let patched = fs::read_to_string(sbdir.join("config.toml")).unwrap();
+
+        assert!(is_patched_for(&patched, "wt-3"));
+        assert!(!is_patched_for(&patched, "other"));
+        assert!(!is_patched_for(original, "wt-3"));
+        assert_eq!(offset_between(original, &patched), Some(300));
+        assert_eq!(offset_between(original, original), None);
+    }
+
     #[test]
     fn patch_config_errors_on_missing_file() {
         let td = TempDir::new().unwrap();
-        let result = patch_config(td.path(), "test", 100);
+        let result = patch_config(td.path(), "test", 100, None);
         assert!(result.is_err());
         let err = result.unwrap_err().to_string();
         assert!(
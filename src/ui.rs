@@ -1,14 +1,103 @@
+use serde::Serialize;
+use std::fmt;
 use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
 
-#[derive(Clone, Copy, Debug, Default)]
-pub struct Logger;
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+    /// Indented continuation of the message before it (e.g. what `rm` would lose).
+    Detail,
+}
+
+// Event is one message of an operation's progress.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Event {
+    pub level: Level,
+    pub message: String,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.level {
+            Level::Info => write!(f, "{}", self.message),
+            Level::Warn => write!(f, "WARN: {}", self.message),
+            Level::Error => write!(f, "ERROR: {}", self.message),
+            Level::Detail => write!(f, "  {}", self.message),
+        }
+    }
+}
+
+/// Receives the events of library operations (see `Wrt::with_sink`).
+pub type Sink = Arc<dyn Fn(&Event) + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct Logger {
+    /// Where events go instead of stderr.
+    sink: Option<Sink>,
+    /// Events are also kept, to be returned with a result (`wrt mcp`, the library's errors).
+    capture: Option<Arc<Mutex<Vec<Event>>>>,
+}
 
 impl Logger {
+    pub fn to_sink(sink: Sink) -> Logger {
+        Logger {
+            sink: Some(sink),
+            capture: None,
+        }
+    }
+
+    // capturing returns a logger that also keeps what it logs (see captured).
+    pub fn capturing(&self) -> Logger {
+        Logger {
+            sink: self.sink.clone(),
+            capture: Some(Arc::default()),
+        }
+    }
+
+    // captured returns the events logged so far (empty unless capturing).
+    pub fn captured(&self) -> Vec<Event> {
+        match &self.capture {
+            Some(c) => c.lock().unwrap_or_else(|e| e.into_inner()).clone(),
+            None => Vec::new(),
+        }
+    }
+
     pub fn infof(&self, msg: &str) {
-        let _ = writeln!(io::stderr(), "[wrt] {msg}");
+        self.emit(Level::Info, msg);
+    }
+
+    pub fn warnf(&self, msg: &str) {
+        self.emit(Level::Warn, msg);
     }
 
     pub fn errorf(&self, msg: &str) {
-        let _ = writeln!(io::stderr(), "[wrt] ERROR: {msg}");
+        self.emit(Level::Error, msg);
+    }
+
+    pub fn detailf(&self, msg: &str) {
+        self.emit(Level::Detail, msg);
+    }
+
+    fn emit(&self, level: Level, msg: &str) {
+        let ev = Event {
+            level,
+            message: msg.to_string(),
+        };
+        match &self.sink {
+            Some(sink) => sink(&ev),
+            None if level == Level::Detail => {
+                let _ = writeln!(io::stderr(), "{ev}");
+            }
+            None => {
+                let _ = writeln!(io::stderr(), "[wrt] {ev}");
+            }
+        }
+        if let Some(c) = &self.capture {
+            c.lock().unwrap_or_else(|e| e.into_inner()).push(ev);
+        }
     }
 }
@@ -1,17 +1,54 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use std::env;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::state::{Allocation, State};
+use crate::worktree;
+
+static CHILD_STDOUT_TO_STDERR: AtomicBool = AtomicBool::new(false);
+static CHILD_STDIN_NULL: AtomicBool = AtomicBool::new(false);
+
+// child_stdout_to_stderr makes the commands wrt runs (installs, git, run_argv_with_wrt_env) send
+// their stdout to our stderr, for wrt commands whose own stdout is machine-readable (summaries, JSON).
+pub fn child_stdout_to_stderr(on: bool) {
+    CHILD_STDOUT_TO_STDERR.store(on, Ordering::Relaxed);
+}
+
+pub fn child_stdout() -> Stdio {
+    if CHILD_STDOUT_TO_STDERR.load(Ordering::Relaxed) {
+        Stdio::from(std::io::stderr())
+    } else {
+        Stdio::inherit()
+    }
+}
+
+// child_stdin_null keeps the commands wrt runs off our stdin, for `wrt mcp` where stdin carries
+// the protocol.
+pub fn child_stdin_null(on: bool) {
+    CHILD_STDIN_NULL.store(on, Ordering::Relaxed);
+}
+
+pub fn child_stdin() -> Stdio {
+    if CHILD_STDIN_NULL.load(Ordering::Relaxed) {
+        Stdio::null()
+    } else {
+        Stdio::inherit()
+    }
+}
 
 pub fn run_cmd(dir: &Path, cmd: &str, args: &[&str]) -> Result<()> {
     let status = Command::new(cmd)
         .args(args)
         .current_dir(dir)
-        .stdout(Stdio::inherit())
+        .stdout(child_stdout())
         .stderr(Stdio::inherit())
-        .stdin(Stdio::inherit())
+        .stdin(child_stdin())
         .status()
         .with_context(|| format!("run {cmd}"))?;
     if !status.success() {
@@ -21,34 +58,100 @@ pub fn run_cmd(dir: &Path, cmd: &str, args: &[&str]) -> Result<()> {
 }
 
 pub fn run_argv_with_wrt_env(dir: &Path, a: &Allocation, argv: &[String]) -> Result<()> {
-    let cmd = &argv[0];
-    let cmd_args = &argv[1..];
-
-    let mut envs: Vec<(String, String)> = env::vars().collect();
-    envs.push(("WRT_NAME".into(), a.name.clone()));
-    envs.push(("WRT_BRANCH".into(), a.branch.clone()));
-    envs.push(("WRT_PORT_BLOCK".into(), a.block.to_string()));
-    envs.push(("WRT_PORT_OFFSET".into(), a.offset.to_string()));
-
-    let mut c = Command::new(cmd);
-    c.args(cmd_args)
-        .current_dir(dir)
-        .stdout(Stdio::inherit())
+    let status = wrt_command(dir, a, argv)
+        .stdout(child_stdout())
         .stderr(Stdio::inherit())
-        .stdin(Stdio::inherit());
-
-    c.env_clear();
-    for (k, v) in envs {
-        c.env(k, v);
-    }
-
-    let status = c.status().with_context(|| format!("run {cmd}"))?;
+        .stdin(child_stdin())
+        .status()
+        .with_context(|| format!("run {}", argv[0]))?;
     if !status.success() {
         return Err(anyhow::anyhow!("command failed"));
     }
     Ok(())
 }
 
+// Captured is the outcome of output_argv_with_wrt_env.
+pub struct Captured {
+    /// None when the command was killed (timeout or signal).
+    pub code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub timed_out: bool,
+}
+
+// output_argv_with_wrt_env runs argv like run_argv_with_wrt_env but collects its output, killing
+// it after `timeout`.
+pub fn output_argv_with_wrt_env(
+    dir: &Path,
+    a: &Allocation,
+    argv: &[String],
+    timeout: Duration,
+) -> Result<Captured> {
+    let mut child = wrt_command(dir, a, argv)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .stdin(Stdio::null())
+        .spawn()
+        .with_context(|| format!("run {}", argv[0]))?;
+
+    let out = drain(child.stdout.take());
+    let err = drain(child.stderr.take());
+
+    let started = Instant::now();
+    let mut timed_out = false;
+    let status = loop {
+        if let Some(s) = child.try_wait()? {
+            break s;
+        }
+        if started.elapsed() >= timeout {
+            timed_out = true;
+            let _ = child.kill();
+            break child.wait()?;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    // Background processes the command started may hold the pipes open; don't wait for them.
+    let grace = Instant::now() + Duration::from_secs(2);
+    while !(out.1.is_finished() && err.1.is_finished()) && Instant::now() < grace {
+        thread::sleep(Duration::from_millis(20));
+    }
+    let take =
+        |b: &Arc<Mutex<Vec<u8>>>| std::mem::take(&mut *b.lock().unwrap_or_else(|e| e.into_inner()));
+    Ok(Captured {
+        code: status.code(),
+        stdout: take(&out.0),
+        stderr: take(&err.0),
+        timed_out,
+    })
+}
+
+// drain collects a child's pipe into a shared buffer until EOF.
+fn drain<R: Read + Send + 'static>(r: Option<R>) -> (Arc<Mutex<Vec<u8>>>, thread::JoinHandle<()>) {
+    let buf: Arc<Mutex<Vec<u8>>> = Arc::default();
+    let sink = Arc::clone(&buf);
+    let handle = thread::spawn(move || {
+        let Some(mut r) = r else { return };
+        let mut chunk = [0u8; 8192];
+        while let Ok(n) = r.read(&mut chunk) {
+            if n == 0 {
+                break;
+            }
+            sink.lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .extend_from_slice(&chunk[..n]);
+        }
+    });
+    (buf, handle)
+}
+
+// wrt_command prepares argv to run in dir with the worktree's WRT_* variables set.
+fn wrt_command(dir: &Path, a: &Allocation, argv: &[String]) -> Command {
+    let mut c = Command::new(&argv[0]);
+    c.args(&argv[1..]).current_dir(dir);
+    c.envs(worktree::env_file_vars(a));
+    c
+}
+
 pub fn which(bin: &str) -> Option<PathBuf> {
     let path = env::var_os("PATH")?;
     for p in env::split_paths(&path) {
@@ -95,3 +198,52 @@ pub fn infer_worktree_from_cwd(st: &State) -> Option<String> {
     }
     None
 }
+
+// parse_duration reads durations like `90m`, `12h`, `7d` or `2w` (a bare number means days).
+pub fn parse_duration(s: &str) -> Result<chrono::Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let n: i64 = num
+        .parse()
+        .map_err(|_| anyhow!("invalid duration \"{s}\" (expected e.g. 12h, 7d, 2w)"))?;
+    let d = match unit {
+        "s" => chrono::Duration::try_seconds(n),
+        "m" => chrono::Duration::try_minutes(n),
+        "h" => chrono::Duration::try_hours(n),
+        "" | "d" => chrono::Duration::try_days(n),
+        "w" => chrono::Duration::try_weeks(n),
+        _ => {
+            return Err(anyhow!(
+                "invalid duration unit in \"{s}\" (use s, m, h, d or w)"
+            ))
+        }
+    };
+    d.ok_or_else(|| anyhow!("duration \"{s}\" is too large"))
+}
+
+// fmt_age renders an age in seconds as whole minutes, hours or days ("45m", "3h", "9d").
+pub fn fmt_age(secs: i64) -> String {
+    match secs.max(0) {
+        s if s < 3600 => format!("{}m", s / 60),
+        s if s < 86400 => format!("{}h", s / 3600),
+        s => format!("{}d", s / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("7d").unwrap(), chrono::Duration::days(7));
+        assert_eq!(parse_duration("36h").unwrap(), chrono::Duration::hours(36));
+        assert_eq!(parse_duration("2w").unwrap(), chrono::Duration::weeks(2));
+        assert_eq!(parse_duration("3").unwrap(), chrono::Duration::days(3));
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("5y").is_err());
+        assert!(parse_duration("-1d").is_err());
+        assert!(parse_duration("99999999999999999d").is_err());
+    }
+}
@@ -1,8 +1,23 @@
 use anyhow::{anyhow, Context, Result};
 use regex::Regex;
+use std::collections::BTreeSet;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::lock::FileLock;
+use crate::util::child_stdout;
+
+// Namespace for refs wrt keeps work in (`wrt rm --backup`), named after the branch.
+pub const ARCHIVE_REF_PREFIX: &str = "refs/wrt-archive";
+// Namespace for the uncommitted changes of archived worktrees, named after the worktree. It sits
+// beside ARCHIVE_REF_PREFIX: a branch name can't collide with it there.
+pub const WIP_REF_PREFIX: &str = "refs/wrt-wip";
+
+// A fetch this recent is reused by concurrent `wrt new` calls instead of hitting the remote again.
+const FETCH_REUSE: Duration = Duration::from_secs(30);
 
 fn re_space() -> &'static Regex {
     static RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
@@ -40,14 +55,136 @@ pub fn ensure_dir(dir: &Path) -> Result<()> {
     Ok(())
 }
 
-pub fn add(repo_root: &Path, wt_path: &Path, branch: &str, from_ref: &str) -> Result<()> {
+// resolve_remote returns the remote to use: the requested one (which must exist), else `origin`,
+// else the first configured remote. None means the repo has no remotes.
+pub fn resolve_remote(repo_root: &Path, requested: Option<&str>) -> Result<Option<String>> {
     let remotes = list_remotes(repo_root)?;
-    let remote = pick_remote(&remotes);
+    if let Some(r) = requested.map(str::trim).filter(|r| !r.is_empty()) {
+        if !remotes.iter().any(|x| x == r) {
+            return Err(anyhow!("unknown git remote: \"{r}\""));
+        }
+        return Ok(Some(r.to_string()));
+    }
+    Ok(pick_remote(&remotes).map(|r| r.to_string()))
+}
 
-    if let Some(remote) = remote {
-        run_git(repo_root, ["fetch", "--prune", remote])
-            .with_context(|| format!("git fetch --prune {remote}"))?;
+pub enum Fetch {
+    Done,
+    /// Another wrt process fetched this remote moments ago.
+    Reused,
+}
+
+// fetch_shared runs `git fetch --prune <remote>`, serialized across wrt processes through a lock in
+// <git-common-dir>/.wrt. A process that waited on the lock skips the fetch if the previous holder
+// finished one within FETCH_REUSE, so N parallel `wrt new` calls fetch once. A fetch that runs
+// longer than `timeout` is killed and reported as an error.
+pub fn fetch_shared(
+    repo_root: &Path,
+    git_common_dir: &Path,
+    remote: &str,
+    timeout: Duration,
+) -> Result<Fetch> {
+    let _lock = fetch_lock(git_common_dir, remote, timeout)?;
+    let stamp = git_common_dir
+        .join(".wrt")
+        .join(format!("fetch-{}.stamp", slug(remote)));
+    if is_fresh(&stamp) {
+        return Ok(Fetch::Reused);
+    }
+    if !run_fetch(repo_root, &["fetch", "--prune", remote], timeout, false)? {
+        return Err(anyhow!("git fetch --prune {remote} failed"));
     }
+    fs::write(&stamp, b"").with_context(|| format!("write {}", stamp.display()))?;
+    Ok(Fetch::Done)
+}
+
+// fetch_pr fetches the head of pull/merge request `pr` into `pr_ref(remote, pr)`, under the same
+// lock and timeout as fetch_shared (but never reusing an earlier fetch: the head moves with every
+// push). It returns the remote ref the head came from, or None when the remote has neither kind.
+pub fn fetch_pr(
+    repo_root: &Path,
+    git_common_dir: &Path,
+    remote: &str,
+    pr: u64,
+    timeout: Duration,
+) -> Result<Option<String>> {
+    let _lock = fetch_lock(git_common_dir, remote, timeout)?;
+    let local_ref = pr_ref(remote, pr);
+    for src in pr_head_refs(pr) {
+        let refspec = format!("+{src}:{local_ref}");
+        // Each host only has one of the ref kinds; the miss on the other is expected.
+        if run_fetch(
+            repo_root,
+            &["fetch", "--no-tags", remote, refspec.as_str()],
+            timeout,
+            true,
+        )? {
+            return Ok(Some(src));
+        }
+    }
+    Ok(None)
+}
+
+fn fetch_lock(git_common_dir: &Path, remote: &str, timeout: Duration) -> Result<FileLock> {
+    FileLock::acquire(
+        &git_common_dir
+            .join(".wrt")
+            .join(format!("fetch-{}.lock", slug(remote))),
+        timeout * 2 + Duration::from_secs(5),
+    )
+}
+
+fn is_fresh(stamp: &Path) -> bool {
+    fs::metadata(stamp)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| SystemTime::now().duration_since(t).ok())
+        .is_some_and(|age| age < FETCH_REUSE)
+}
+
+// run_fetch runs git with `args`, killing it after `timeout` (an error). Ok(false) means git failed.
+fn run_fetch(repo_root: &Path, args: &[&str], timeout: Duration, quiet: bool) -> Result<bool> {
+    let mut child = Command::new("git")
+        .args(args)
+        .current_dir(repo_root)
+        .stdout(if quiet { Stdio::null() } else { child_stdout() })
+        .stderr(if quiet {
+            Stdio::null()
+        } else {
+            Stdio::inherit()
+        })
+        .spawn()
+        .context("run git")?;
+    let start = SystemTime::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().context("wait git")? {
+            break status;
+        }
+        if start.elapsed().unwrap_or_default() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!("timed out after {}s", timeout.as_secs()));
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    Ok(status.success())
+}
+
+pub struct AddOpts<'a> {
+    pub branch: &'a str,
+    pub from_ref: &'a str,
+    /// Remote used to look up upstream branches / PR refs (see `resolve_remote`).
+    pub remote: Option<&'a str>,
+    /// Cone-mode sparse-checkout paths; empty means a full checkout.
+    pub sparse: &'a [String],
+}
+
+// add creates the worktree at wt_path. It does not fetch; callers run `fetch_shared` first so the
+// fetch can be skipped (offline) or shared between concurrent invocations.
+// When `sparse` is non-empty the worktree is created without a checkout, cone-mode
+// sparse-checkout is configured, and only then are files materialized.
+pub fn add(repo_root: &Path, wt_path: &Path, opts: &AddOpts<'_>) -> Result<()> {
+    let branch = opts.branch;
 
     // Prefer existing local branch. If it doesn't exist, check for upstream.
     if git_ok(
@@ -59,18 +196,10 @@ pub fn add(repo_root: &Path, wt_path: &Path, branch: &str, from_ref: &str) -> Re
             &format!("refs/heads/{branch}"),
         ],
     )? {
-        return run_git(
-            repo_root,
-            [
-                "worktree",
-                "add",
-                wt_path.to_string_lossy().as_ref(),
-                branch,
-            ],
-        );
+        return worktree_add(repo_root, wt_path, &[], &[branch], opts.sparse);
     }
 
-    if let Some(remote) = remote {
+    if let Some(remote) = opts.remote {
         let remote_ref = format!("refs/remotes/{remote}/{branch}");
         if git_ok(
             repo_root,
@@ -81,29 +210,194 @@ pub fn add(repo_root: &Path, wt_path: &Path, branch: &str, from_ref: &str) -> Re
                 repo_root,
                 ["branch", "--track", branch, start_point.as_str()],
             )?;
-            return run_git(
+            return worktree_add(repo_root, wt_path, &[], &[branch], opts.sparse);
+        }
+    }
+
+    worktree_add(
+        repo_root,
+        wt_path,
+        &["-b", branch],
+        &[opts.from_ref],
+        opts.sparse,
+    )
+}
+
+pub enum PrHead {
+    /// Freshly fetched from the remote ref with this name.
+    Fetched(String),
+    /// Not fetched (offline or fetch failed); using the copy from an earlier fetch.
+    Cached,
+}
+
+// add_pr creates the worktree for the head of pull/merge request `pr` on a local branch that
+// tracks it, so `git pull` inside the worktree picks up new pushes. It does not fetch: `fetched` is
+// the remote ref `fetch_pr` got the head from, and without one (`fetch` unset, or the fetch failed)
+// it falls back to the head fetched by an earlier run, if any. An existing local branch is
+// fast-forwarded to the head; one that has diverged from it is refused.
+pub fn add_pr(
+    repo_root: &Path,
+    wt_path: &Path,
+    opts: &AddOpts<'_>,
+    pr: u64,
+    fetch: bool,
+    fetched: Option<&str>,
+) -> Result<PrHead> {
+    let Some(remote) = opts.remote else {
+        return Err(anyhow!("no git remote configured (needed to fetch #{pr})"));
+    };
+    let branch = opts.branch;
+
+    let local_ref = pr_ref(remote, pr);
+    if fetched.is_none() && !ref_exists(repo_root, &local_ref) {
+        let how = if fetch {
+            "not found"
+        } else {
+            "not fetched yet (--no-fetch)"
+        };
+        return Err(anyhow!(
+            "#{pr} {how} on remote {remote} (tried {})",
+            pr_head_refs(pr).join(", ")
+        ));
+    }
+
+    let local_branch = format!("refs/heads/{branch}");
+    if ref_exists(repo_root, &local_branch) {
+        let behind = git_ok(
+            repo_root,
+            [
+                "merge-base",
+                "--is-ancestor",
+                local_branch.as_str(),
+                local_ref.as_str(),
+            ],
+        )?;
+        if !behind
+            && !git_ok(
                 repo_root,
                 [
-                    "worktree",
-                    "add",
-                    wt_path.to_string_lossy().as_ref(),
-                    branch,
+                    "merge-base",
+                    "--is-ancestor",
+                    local_ref.as_str(),
+                    local_branch.as_str(),
                 ],
-            );
+            )?
+        {
+            return Err(anyhow!(
+                "branch {branch} has diverged from #{pr}; delete it or pick another name with --branch"
+            ));
         }
+        // Check the branch out before moving it: git refuses if another worktree has it, and
+        // the fast-forward then happens in the new checkout only.
+        worktree_add(repo_root, wt_path, &[], &[branch], opts.sparse)?;
+        if behind {
+            if let Err(e) = run_git(
+                wt_path,
+                ["merge", "--ff-only", "--quiet", local_ref.as_str()],
+            ) {
+                let _ = remove(repo_root, wt_path, true);
+                return Err(e);
+            }
+        }
+    } else {
+        worktree_add(
+            repo_root,
+            wt_path,
+            &["-b", branch],
+            &[&local_ref],
+            opts.sparse,
+        )?;
     }
 
+    let Some(src) = fetched else {
+        return Ok(PrHead::Cached);
+    };
     run_git(
         repo_root,
-        [
-            "worktree",
-            "add",
-            "-b",
-            branch,
-            wt_path.to_string_lossy().as_ref(),
-            from_ref,
-        ],
-    )
+        ["config", &format!("branch.{branch}.remote"), remote],
+    )?;
+    run_git(
+        repo_root,
+        ["config", &format!("branch.{branch}.merge"), src],
+    )?;
+    Ok(PrHead::Fetched(src.to_string()))
+}
+
+// pr_ref is where wrt keeps the fetched head of pull/merge request `pr`.
+fn pr_ref(remote: &str, pr: u64) -> String {
+    format!("refs/wrt/pr/{remote}/{pr}")
+}
+
+fn pr_head_refs(pr: u64) -> Vec<String> {
+    vec![
+        format!("refs/pull/{pr}/head"),
+        format!("refs/merge-requests/{pr}/head"),
+    ]
+}
+
+// worktree_add runs `git worktree add [<opts>...] <path> [<rest>...]`.
+fn worktree_add(
+    repo_root: &Path,
+    wt_path: &Path,
+    opts: &[&str],
+    rest: &[&str],
+    sparse: &[String],
+) -> Result<()> {
+    let mut argv: Vec<String> = vec!["worktree".into(), "add".into()];
+    if !sparse.is_empty() {
+        argv.push("--no-checkout".into());
+    }
+    argv.extend(opts.iter().map(|s| s.to_string()));
+    argv.push(wt_path.to_string_lossy().to_string());
+    argv.extend(rest.iter().map(|s| s.to_string()));
+    run_git_vec(repo_root, &argv)?;
+
+    if sparse.is_empty() {
+        return Ok(());
+    }
+    let checkout = sparse_set(wt_path, sparse)
+        .and_then(|()| run_git(wt_path, ["checkout"]).context("git checkout (sparse)"));
+    if let Err(e) = checkout {
+        // Callers only undo a worktree that was added successfully; take this one back here.
+        let _ = remove(repo_root, wt_path, true);
+        return Err(e);
+    }
+    Ok(())
+}
+
+// sparse_set replaces the worktree's cone-mode sparse-checkout paths. git stores the sparse
+// config per worktree (extensions.worktreeConfig), so other worktrees are unaffected.
+pub fn sparse_set(wt_path: &Path, paths: &[String]) -> Result<()> {
+    let mut argv: Vec<String> = vec!["sparse-checkout".into(), "set".into(), "--cone".into()];
+    argv.extend(paths.iter().cloned());
+    run_git_vec(wt_path, &argv).context("git sparse-checkout set")
+}
+
+pub fn sparse_add(wt_path: &Path, paths: &[String]) -> Result<()> {
+    let mut argv: Vec<String> = vec!["sparse-checkout".into(), "add".into()];
+    argv.extend(paths.iter().cloned());
+    run_git_vec(wt_path, &argv).context("git sparse-checkout add")
+}
+
+pub fn sparse_disable(wt_path: &Path) -> Result<()> {
+    run_git(wt_path, ["sparse-checkout", "disable"]).context("git sparse-checkout disable")
+}
+
+// sparse_list returns the configured paths, or None when sparse-checkout is not enabled.
+pub fn sparse_list(wt_path: &Path) -> Result<Option<Vec<String>>> {
+    let enabled = git_out(wt_path, ["config", "--bool", "core.sparseCheckout"])
+        .map(|s| s.trim() == "true")
+        .unwrap_or(false);
+    if !enabled {
+        return Ok(None);
+    }
+    let out = git_out(wt_path, ["sparse-checkout", "list"])?;
+    Ok(Some(
+        out.lines()
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty())
+            .collect(),
+    ))
 }
 
 pub fn remove(repo_root: &Path, wt_path: &Path, force: bool) -> Result<()> {
@@ -115,6 +409,85 @@ pub fn remove(repo_root: &Path, wt_path: &Path, force: bool) -> Result<()> {
     run_git_vec(repo_root, &args)
 }
 
+// GitWorktree is one entry of `git worktree list --porcelain`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GitWorktree {
+    pub path: PathBuf,
+    pub head: Option<String>,
+    /// Short branch name; None when detached (or bare).
+    pub branch: Option<String>,
+    pub bare: bool,
+    pub detached: bool,
+    pub locked: bool,
+    pub prunable: bool,
+}
+
+// list returns git's view of the worktrees; the first entry is always the main worktree.
+pub fn list(repo_root: &Path) -> Result<Vec<GitWorktree>> {
+    let out = git_out(repo_root, ["worktree", "list", "--porcelain"])
+        .context("git worktree list --porcelain")?;
+    Ok(parse_porcelain(&out))
+}
+
+fn parse_porcelain(s: &str) -> Vec<GitWorktree> {
+    let mut out = Vec::new();
+    let mut cur: Option<GitWorktree> = None;
+    for line in s.lines() {
+        let (key, val) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "worktree" => {
+                out.extend(cur.take());
+                cur = Some(GitWorktree {
+                    path: PathBuf::from(val),
+                    ..Default::default()
+                });
+            }
+            _ => {
+                let Some(wt) = cur.as_mut() else { continue };
+                match key {
+                    "HEAD" => wt.head = Some(val.to_string()),
+                    "branch" => {
+                        wt.branch = Some(val.strip_prefix("refs/heads/").unwrap_or(val).to_string())
+                    }
+                    "bare" => wt.bare = true,
+                    "detached" => wt.detached = true,
+                    "locked" => wt.locked = true,
+                    "prunable" => wt.prunable = true,
+                    _ => {}
+                }
+            }
+        }
+    }
+    out.extend(cur);
+    out
+}
+
+// same_path compares two paths after resolving symlinks (git reports real paths, state stores
+// whatever path wrt was run with), falling back to a plain comparison for missing paths.
+pub fn same_path(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+// move_to relocates a worktree with `git worktree move` (keeps git's admin files in sync).
+pub fn move_to(repo_root: &Path, from: &Path, to: &Path) -> Result<()> {
+    run_git(
+        repo_root,
+        [
+            "worktree".as_ref(),
+            "move".as_ref(),
+            from.as_os_str(),
+            to.as_os_str(),
+        ],
+    )
+}
+
+pub fn rename_branch(repo_root: &Path, from: &str, to: &str) -> Result<()> {
+    run_git(repo_root, ["branch", "-m", from, to])
+}
+
 pub fn is_dirty(wt_path: &Path) -> Result<bool> {
     let out = Command::new("git")
         .args(["status", "--porcelain"])
@@ -127,18 +500,412 @@ pub fn is_dirty(wt_path: &Path) -> Result<bool> {
     Ok(!String::from_utf8_lossy(&out.stdout).trim().is_empty())
 }
 
+// env_file_vars lists the variables wrt writes to .wrt.env for an allocation.
+pub fn env_file_vars(a: &crate::state::Allocation) -> Vec<(String, String)> {
+    let mut vars = vec![
+        ("WRT_NAME".to_string(), a.name.clone()),
+        ("WRT_BRANCH".to_string(), a.branch.clone()),
+        ("WRT_PORT_BLOCK".to_string(), a.block.to_string()),
+        ("WRT_PORT_OFFSET".to_string(), a.offset.to_string()),
+    ];
+    if let Some(host) = &a.host {
+        vars.push(("WRT_HOST".to_string(), host.clone()));
+    }
+    for (svc, url) in &a.urls {
+        let svc = svc
+            .to_uppercase()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+        vars.push((format!("WRT_URL_{svc}"), url.clone()));
+    }
+    vars
+}
+
+// dirty_files returns `git status --porcelain` entries (status code + path) for the worktree.
+pub fn dirty_files(wt_path: &Path) -> Result<Vec<String>> {
+    let out = git_out(wt_path, ["status", "--porcelain"]).context("git status")?;
+    Ok(out
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.to_string())
+        .collect())
+}
+
+// default_branch guesses the repo's integration branch: the remote's HEAD if known, else a local
+// main/master, else whatever the main worktree has checked out.
+pub fn default_branch(repo_root: &Path, remote: Option<&str>) -> Option<String> {
+    if let Some(remote) = remote {
+        let head = format!("refs/remotes/{remote}/HEAD");
+        if let Ok(out) = git_out(repo_root, ["symbolic-ref", "--short", head.as_str()]) {
+            let out = out.trim();
+            if !out.is_empty() {
+                return Some(out.to_string());
+            }
+        }
+    }
+    for b in ["main", "master"] {
+        let r = format!("refs/heads/{b}");
+        if git_ok(repo_root, ["show-ref", "--verify", "--quiet", r.as_str()]).unwrap_or(false) {
+            return Some(b.to_string());
+        }
+    }
+    list(repo_root).ok()?.first()?.branch.clone()
+}
+
+// unique_commits lists commits on `branch` that are on no remote-tracking branch and not in
+// `base`; these are lost if the branch is deleted.
+pub fn unique_commits(repo_root: &Path, branch: &str, base: Option<&str>) -> Result<Vec<String>> {
+    let mut args: Vec<String> = vec![
+        "log".into(),
+        "--format=%h %s".into(),
+        format!("refs/heads/{branch}"),
+        "--not".into(),
+        "--remotes".into(),
+    ];
+    if let Some(base) = base.filter(|b| *b != branch) {
+        args.push(base.to_string());
+    }
+    args.push("--".into());
+    let out = git_out(repo_root, &args).context("git log")?;
+    Ok(out.lines().map(|l| l.to_string()).collect())
+}
+
+// last_commit_time returns the committer time (unix seconds) of the worktree's HEAD.
+pub fn last_commit_time(wt_path: &Path) -> Result<i64> {
+    Ok(last_commit(wt_path)?.0)
+}
+
+// last_commit returns the committer time (unix seconds) and subject of the worktree's HEAD.
+pub fn last_commit(wt_path: &Path) -> Result<(i64, String)> {
+    let out = git_out(wt_path, ["log", "-1", "--format=%ct %s", "HEAD"]).context("git log")?;
+    let (ts, subject) = out.trim_end().split_once(' ').unwrap_or((out.trim(), ""));
+    let ts = ts
+        .parse()
+        .map_err(|_| anyhow!("unexpected git log output: {out:?}"))?;
+    Ok((ts, subject.to_string()))
+}
+
+// head_branch returns the branch checked out in dir (None when detached).
+pub fn head_branch(dir: &Path) -> Option<String> {
+    let out = git_out(dir, ["symbolic-ref", "--quiet", "--short", "HEAD"]).ok()?;
+    Some(out.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+// upstream returns the upstream of the branch checked out in dir, if it has one.
+pub fn upstream(dir: &Path) -> Option<String> {
+    let out = git_out(
+        dir,
+        [
+            "rev-parse",
+            "--abbrev-ref",
+            "--symbolic-full-name",
+            "@{upstream}",
+        ],
+    )
+    .ok()?;
+    Some(out.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+// ahead_behind counts the commits only in `ours` and only in `theirs`.
+pub fn ahead_behind(dir: &Path, ours: &str, theirs: &str) -> Result<(u32, u32)> {
+    let range = format!("{ours}...{theirs}");
+    let out = git_out(dir, ["rev-list", "--left-right", "--count", range.as_str()])
+        .with_context(|| format!("git rev-list {range}"))?;
+    let mut it = out.split_whitespace().map(|n| n.parse::<u32>());
+    match (it.next(), it.next()) {
+        (Some(Ok(a)), Some(Ok(b))) => Ok((a, b)),
+        _ => Err(anyhow!("unexpected git rev-list output: {out:?}")),
+    }
+}
+
+// merge_conflicts does an in-memory merge of `theirs` into `ours` (`git merge-tree
+// --write-tree`, git >= 2.38) and returns the conflicting paths. Nothing is written to any
+// worktree; the resulting objects are unreferenced.
+pub fn merge_conflicts(repo_root: &Path, ours: &str, theirs: &str) -> Result<Vec<String>> {
+    let out = Command::new("git")
+        .args([
+            "merge-tree",
+            "--write-tree",
+            "--name-only",
+            "--no-messages",
+            ours,
+            theirs,
+        ])
+        .current_dir(repo_root)
+        .output()
+        .context("run git merge-tree")?;
+    match out.status.code() {
+        Some(0) => Ok(Vec::new()),
+        // Exit 1 means conflicts: the tree id, then one conflicted path per line.
+        Some(1) => Ok(String::from_utf8_lossy(&out.stdout)
+            .lines()
+            .skip(1)
+            .filter(|l| !l.is_empty())
+            .map(|l| l.to_string())
+            .collect()),
+        _ => Err(anyhow!(
+            "git merge-tree failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        )),
+    }
+}
+
+// DiffStat summarizes `git diff --numstat` (binary files count as changed, with no lines).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct DiffStat {
+    pub files: u32,
+    pub insertions: u32,
+    pub deletions: u32,
+}
+
+// diff_stat compares HEAD of the worktree with its merge-base with `base` (committed work only).
+pub fn diff_stat(wt_path: &Path, base: &str) -> Result<DiffStat> {
+    let range = format!("{base}...HEAD");
+    let out = git_out(wt_path, ["diff", "--numstat", range.as_str(), "--"])
+        .with_context(|| format!("git diff {range}"))?;
+    let mut st = DiffStat::default();
+    for line in out.lines().filter(|l| !l.is_empty()) {
+        let mut it = line.split('\t');
+        st.files += 1;
+        st.insertions += it.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+        st.deletions += it.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+    }
+    Ok(st)
+}
+
+// changed_lines returns the lines added and removed since the merge-base with `base`, as
+// "<path>\t<+|-><text>", for comparing what different worktrees changed.
+pub fn changed_lines(wt_path: &Path, base: &str) -> Result<BTreeSet<String>> {
+    let range = format!("{base}...HEAD");
+    let out = git_out(
+        wt_path,
+        [
+            "diff",
+            "-U0",
+            "--no-color",
+            "--no-ext-diff",
+            range.as_str(),
+            "--",
+        ],
+    )
+    .with_context(|| format!("git diff {range}"))?;
+    Ok(parse_changed_lines(&out))
+}
+
+fn parse_changed_lines(diff: &str) -> BTreeSet<String> {
+    let mut out = BTreeSet::new();
+    let mut path = "";
+    let mut in_hunk = false;
+    for line in diff.lines() {
+        if line.starts_with("diff ") {
+            in_hunk = false;
+        } else if line.starts_with("@@") {
+            in_hunk = true;
+        } else if in_hunk {
+            if line.starts_with('+') || line.starts_with('-') {
+                out.insert(format!("{path}\t{line}"));
+            }
+        } else if let Some(p) = line.strip_prefix("--- a/") {
+            path = p;
+        } else if let Some(p) = line.strip_prefix("+++ b/") {
+            // Deleted files have "+++ /dev/null" and keep the "--- a/..." path.
+            path = p;
+        }
+    }
+    out
+}
+
+// Integrate is how `wrt pick` brings a branch into the target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Integrate {
+    Merge,
+    Squash,
+    CherryPick,
+}
+
+// integrate applies `branch` onto the branch checked out in dir (which must be clean). On
+// conflicts the operation is aborted, leaving dir as it was.
+pub fn integrate(dir: &Path, branch: &str, how: Integrate) -> Result<()> {
+    let res = match how {
+        Integrate::Merge => {
+            let msg = format!("Merge branch '{branch}'");
+            run_git(dir, ["merge", "--no-ff", "-m", msg.as_str(), branch])
+        }
+        Integrate::Squash => run_git(dir, ["merge", "--squash", branch]).and_then(|_| {
+            let msg = format!("{branch} (squashed)");
+            run_git(dir, ["commit", "-m", msg.as_str()])
+        }),
+        Integrate::CherryPick => {
+            let range = format!("HEAD..{branch}");
+            run_git(dir, ["cherry-pick", range.as_str()])
+        }
+    };
+    if let Err(e) = res {
+        let _ = match how {
+            Integrate::Merge => git_ok(dir, ["merge", "--abort"]),
+            Integrate::Squash => git_ok(dir, ["reset", "--merge"]),
+            Integrate::CherryPick => git_ok(dir, ["cherry-pick", "--abort"]),
+        };
+        return Err(e);
+    }
+    Ok(())
+}
+
+// branch_exists reports whether refs/heads/<branch> exists.
+pub fn branch_exists(repo_root: &Path, branch: &str) -> bool {
+    ref_exists(repo_root, &format!("refs/heads/{branch}"))
+}
+
+// is_merged reports whether the branch tip is already contained in `base`. A local branch that
+// never moved since it was created (one reflog entry, no upstream) doesn't count: it merely
+// starts from a commit of `base`, nobody has worked on it yet.
+pub fn is_merged(repo_root: &Path, branch: &str, base: &str) -> Result<bool> {
+    let head = format!("refs/heads/{branch}");
+    if !git_ok(
+        repo_root,
+        ["merge-base", "--is-ancestor", head.as_str(), base],
+    )? {
+        return Ok(false);
+    }
+
+    let upstream = format!("{branch}@{{upstream}}");
+    if git_ok(
+        repo_root,
+        ["rev-parse", "--verify", "--quiet", upstream.as_str()],
+    )? {
+        return Ok(true);
+    }
+    match git_out(
+        repo_root,
+        ["reflog", "show", "--format=%H", head.as_str(), "--"],
+    ) {
+        Ok(out) if !out.trim().is_empty() => Ok(out.lines().count() > 1),
+        // Without a reflog, fall back to "not sitting on the tip of base".
+        _ => {
+            let tip = git_out(repo_root, ["rev-parse", head.as_str()]).context("rev-parse")?;
+            let base_tip = git_out(repo_root, ["rev-parse", base]).context("rev-parse")?;
+            Ok(tip.trim() != base_tip.trim())
+        }
+    }
+}
+
+// branch_stashes lists stash entries that were created while `branch` was checked out.
+pub fn branch_stashes(repo_root: &Path, branch: &str) -> Result<Vec<String>> {
+    let out =
+        git_out(repo_root, ["stash", "list", "--format=%gd %gs"]).context("git stash list")?;
+    let wip = format!(" WIP on {branch}:");
+    let on = format!(" On {branch}:");
+    Ok(out
+        .lines()
+        .filter(|l| l.contains(&wip) || l.contains(&on))
+        .map(|l| l.to_string())
+        .collect())
+}
+
+// snapshot records the worktree's uncommitted state (tracked changes and untracked, non-ignored
+// files) as a commit on top of HEAD without touching the worktree, index or stash. Returns None if
+// there is nothing to record.
+pub fn snapshot(wt_path: &Path, message: &str) -> Result<Option<String>> {
+    if !is_dirty(wt_path)? {
+        return Ok(None);
+    }
+    let tmp = tempfile::NamedTempFile::new().context("temp index")?;
+    // A snapshot must not fail just because no identity is configured (e.g. CI, fresh machines).
+    let has_ident = git_ok(wt_path, ["config", "user.email"])?;
+    let git_index = |args: &[&str]| -> Result<String> {
+        let mut c = Command::new("git");
+        c.args(args)
+            .current_dir(wt_path)
+            .env("GIT_INDEX_FILE", tmp.path());
+        if !has_ident {
+            for k in ["GIT_AUTHOR", "GIT_COMMITTER"] {
+                c.env(format!("{k}_NAME"), "wrt");
+                c.env(format!("{k}_EMAIL"), "wrt@localhost");
+            }
+        }
+        let out = c.output().context("run git")?;
+        if !out.status.success() {
+            return Err(anyhow!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&out.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+    };
+    // Start from a copy of the real index so staged changes and skip-worktree bits (sparse
+    // checkouts) carry over.
+    let index = git_out(
+        wt_path,
+        ["rev-parse", "--path-format=absolute", "--git-path", "index"],
+    )?;
+    fs::copy(index.trim(), tmp.path()).context("copy index")?;
+    git_index(&["add", "-A", "."])?;
+    let tree = git_index(&["write-tree"])?;
+    let commit = git_index(&["commit-tree", &tree, "-p", "HEAD", "-m", message])?;
+    Ok(Some(commit))
+}
+
+// apply_snapshot writes a `snapshot` commit's tree into the worktree (index untouched), bringing
+// back modified, deleted and untracked files as uncommitted changes.
+pub fn apply_snapshot(wt_path: &Path, commit: &str) -> Result<()> {
+    let source = format!("--source={commit}");
+    run_git(
+        wt_path,
+        ["restore", source.as_str(), "--worktree", "--", "."],
+    )
+    .context("git restore")
+}
+
+// update_ref points `refname` at `target` (a commit or ref).
+pub fn update_ref(repo_root: &Path, refname: &str, target: &str) -> Result<()> {
+    run_git(repo_root, ["update-ref", refname, target])
+}
+
+pub fn ref_exists(repo_root: &Path, refname: &str) -> bool {
+    git_ok(repo_root, ["show-ref", "--verify", "--quiet", refname]).unwrap_or(false)
+}
+
+pub fn delete_ref(repo_root: &Path, refname: &str) -> Result<()> {
+    run_git(repo_root, ["update-ref", "-d", refname])
+}
+
+// push_ref pushes a local ref to the same name on `remote`.
+pub fn push_ref(repo_root: &Path, remote: &str, refname: &str) -> Result<()> {
+    let spec = format!("+{refname}:{refname}");
+    run_git(repo_root, ["push", "--quiet", remote, spec.as_str()])
+}
+
 pub fn write_env_file(wt_path: &Path, a: &crate::state::Allocation) -> Result<()> {
     // A tiny env file that can be sourced, and can be read by tooling/scripts.
     // Intentionally avoids setting PORT/VITE_PORT etc by default (too repo-specific).
     let p = wt_path.join(".wrt.env");
-    let content = format!(
-        "# Generated by wrt. Safe to edit; re-running wrt may overwrite.\nWRT_NAME={}\nWRT_BRANCH={}\nWRT_PORT_BLOCK={}\nWRT_PORT_OFFSET={}\n",
-        a.name, a.branch, a.block, a.offset
-    );
+    let mut content =
+        String::from("# Generated by wrt. Safe to edit; re-running wrt may overwrite.\n");
+    for (k, v) in env_file_vars(a) {
+        content.push_str(&format!("{k}={v}\n"));
+    }
     fs::write(&p, content.as_bytes()).with_context(|| format!("write {}", p.display()))?;
     Ok(())
 }
 
+// read_env_file parses KEY=VALUE lines from a worktree's .wrt.env (comments/blank lines skipped).
+pub fn read_env_file(wt_path: &Path) -> Result<Vec<(String, String)>> {
+    let p = wt_path.join(".wrt.env");
+    let s = fs::read_to_string(&p).with_context(|| format!("read {}", p.display()))?;
+    Ok(s.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect())
+}
+
+// is_skip_worktree reports whether `rel` is flagged skip-worktree in the worktree's index.
+pub fn is_skip_worktree(wt_path: &Path, rel: &str) -> Result<bool> {
+    let out = git_out(wt_path, ["ls-files", "-v", "--", rel])?;
+    Ok(out.starts_with('S'))
+}
+
 pub fn copy_repo_env(repo_root: &Path, wt_path: &Path) -> Result<bool> {
     let src = repo_root.join(".env");
     if !src.is_file() {
@@ -152,6 +919,46 @@ pub fn copy_repo_env(repo_root: &Path, wt_path: &Path) -> Result<bool> {
     Ok(true)
 }
 
+// copy_from_root copies a file or directory of the main workdir (`rel`, relative to it) to the same
+// place in the worktree. Files that already exist in the worktree are kept. Returns Ok(false) when
+// there is nothing to copy.
+pub fn copy_from_root(repo_root: &Path, wt_path: &Path, rel: &str) -> Result<bool> {
+    let rel = Path::new(rel);
+    if !rel.components().all(|c| {
+        matches!(
+            c,
+            std::path::Component::Normal(_) | std::path::Component::CurDir
+        )
+    }) {
+        return Err(anyhow!("{} is not a path inside the repo", rel.display()));
+    }
+    let src = repo_root.join(rel);
+    if !src.exists() {
+        return Ok(false);
+    }
+    copy_missing(&src, &wt_path.join(rel))?;
+    Ok(true)
+}
+
+fn copy_missing(src: &Path, dst: &Path) -> Result<()> {
+    if src.is_dir() {
+        fs::create_dir_all(dst).with_context(|| format!("create {}", dst.display()))?;
+        for entry in fs::read_dir(src).with_context(|| format!("read {}", src.display()))? {
+            let entry = entry.with_context(|| format!("read {}", src.display()))?;
+            copy_missing(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+        return Ok(());
+    }
+    if dst.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+    }
+    fs::copy(src, dst).with_context(|| format!("copy {} -> {}", src.display(), dst.display()))?;
+    Ok(())
+}
+
 fn run_git<I, S>(dir: &Path, args: I) -> Result<()>
 where
     I: IntoIterator<Item = S>,
@@ -161,7 +968,7 @@ where
         .args(args)
         .current_dir(dir)
         // Keep output visible for troubleshooting.
-        .stdout(Stdio::inherit())
+        .stdout(child_stdout())
         .stderr(Stdio::inherit())
         .status()
         .context("run git")?;
@@ -227,7 +1034,7 @@ fn run_git_vec(dir: &Path, args: &[String]) -> Result<()> {
     let status = Command::new("git")
         .args(args)
         .current_dir(dir)
-        .stdout(Stdio::inherit())
+        .stdout(child_stdout())
         .stderr(Stdio::inherit())
         .status()
         .context("run git")?;
@@ -248,6 +1055,31 @@ mod tests {
         assert_eq!(slug("***"), "wrt");
     }
 
+    #[test]
+    fn parse_porcelain_entries() {
+        let s = "worktree /r\nHEAD aaa\nbranch refs/heads/main\n\nworktree /r/.worktrees/x\nHEAD bbb\ndetached\n\nworktree /gone\nHEAD ccc\nbranch refs/heads/a/b\nlocked reason\nprunable gitdir file points to non-existent location\n\n";
+        let wts = parse_porcelain(s);
+        assert_eq!(wts.len(), 3);
+        assert_eq!(wts[0].path, PathBuf::from("/r"));
+        assert_eq!(wts[0].branch.as_deref(), Some("main"));
+        assert!(wts[1].detached && wts[1].branch.is_none());
+        assert_eq!(wts[1].head.as_deref(), Some("bbb"));
+        assert_eq!(wts[2].branch.as_deref(), Some("a/b"));
+        assert!(wts[2].locked && wts[2].prunable);
+    }
+
+    #[test]
+    fn changed_lines_are_keyed_by_path() {
+        let diff = "diff --git a/x b/x\n--- a/x\n+++ b/x\n@@ -1 +1 @@\n-old\n+new\n\
+                    diff --git a/gone b/gone\ndeleted file mode 100644\n--- a/gone\n+++ /dev/null\n\
+                    @@ -1,2 +0,0 @@\n--- not a header\n-bye\n";
+        let got: Vec<String> = parse_changed_lines(diff).into_iter().collect();
+        assert_eq!(
+            got,
+            vec!["gone\t--- not a header", "gone\t-bye", "x\t+new", "x\t-old"]
+        );
+    }
+
     #[test]
     fn normalize_branch_basic() {
         assert_eq!(normalize_branch("refs/heads/a/b"), "a/b");
@@ -191,6 +191,63 @@ fn new_patches_supabase_and_sets_skip_worktree_when_auto() {
     assert!(v.starts_with('S'));
 }
 
+#[test]
+fn loopback_isolation_rewrites_host_instead_of_ports() {
+    let td = init_repo();
+
+    let sbdir = td.path().join("supabase");
+    fs::create_dir_all(&sbdir).unwrap();
+    fs::write(
+        sbdir.join("config.toml"),
+        "project_id = \"myproj\"\nport = 5432\nauth_site_url = \"http://localhost:3000\"\n",
+    )
+    .unwrap();
+    git(td.path(), &["add", "supabase/config.toml"]);
+    git(
+        td.path(),
+        &[
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "commit",
+            "-m",
+            "add supabase",
+        ],
+    );
+    let cfg_dir = td.path().join(".git").join(".wrt");
+    fs::create_dir_all(&cfg_dir).unwrap();
+    fs::write(cfg_dir.join("config.json"), r#"{"isolation": "loopback"}"#).unwrap();
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["new", "x", "--install", "false", "--supabase", "auto"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    let wt_dir = td.path().join(".worktrees").join("x");
+    let patched = fs::read_to_string(wt_dir.join("supabase").join("config.toml")).unwrap();
+    assert!(patched.contains("project_id = \"myproj-x\""));
+    assert!(patched.contains("port = 5432"));
+    assert!(patched.contains("http://127.0.1.1:3000"));
+
+    let env = fs::read_to_string(wt_dir.join(".wrt.env")).unwrap();
+    assert!(env.contains("WRT_HOST=127.0.1.1"));
+    assert!(env.contains("WRT_PORT_OFFSET=0"));
+
+    let out = wrt_cmd()
+        .current_dir(&wt_dir)
+        .args(["env"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert!(String::from_utf8(out)
+        .unwrap()
+        .contains("export WRT_HOST=127.0.1.1"));
+}
+
 #[test]
 fn new_and_rm_roundtrip() {
     let td = init_repo();
@@ -444,6 +501,239 @@ fn new_db_true_does_not_fallback_to_seed_or_migrate() {
     assert!(!wt_dir.join(".db_seed_ran").exists());
 }
 
+#[test]
+fn new_rolls_back_a_failed_setup_unless_keep_on_failure() {
+    let td = init_repo();
+    fs::write(
+        td.path().join(".wrt.json"),
+        r#"{
+  "version": 1,
+  "port_block_size": 100,
+  "package_manager": { "name": "unknown", "install_command": ["npm","install"], "notes": null },
+  "services": [],
+  "database": { "detected": true, "kind": "unknown", "reset_command": ["sh","-c","exit 3"] },
+  "supabase": { "detected": false }
+}
+"#,
+    )
+    .unwrap();
+    // An existing branch survives the rollback; only branches wrt created are deleted.
+    git(td.path(), &["branch", "old"]);
+
+    let new = |name: &str, extra: &[&str]| {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path())
+            .args(["new", name, "--install", "false", "--supabase", "false"])
+            .args(["--db", "true"])
+            .args(extra);
+        set_minimal_path(&mut cmd);
+        cmd.assert()
+    };
+    let branches = || git_out(td.path(), &["branch", "--format=%(refname:short)"]);
+
+    for name in ["x", "old"] {
+        // Twice: the rollback leaves nothing that makes the retry fail differently.
+        for _ in 0..2 {
+            new(name, &[])
+                .code(1)
+                .stderr(predicate::str::contains("db setup failed"))
+                .stderr(predicate::str::contains(format!("rolling back {name}")));
+        }
+        assert!(!td.path().join(".worktrees").join(name).exists());
+    }
+    assert!(!branches().lines().any(|b| b == "x"));
+    assert!(branches().lines().any(|b| b == "old"));
+    let state = fs::read_to_string(td.path().join(".git").join(".wrt").join("state.json")).unwrap();
+    assert!(!state.contains("\"x\""));
+
+    // A failing sparse checkout happens inside `git worktree add`'s step; it is undone too.
+    new("sx", &["--sparse", "/abs/path"])
+        .code(1)
+        .stderr(predicate::str::contains("could not delete branch").not());
+    assert!(!td.path().join(".worktrees").join("sx").exists());
+    assert!(!branches().lines().any(|b| b == "sx"));
+
+    new("x", &["--keep-on-failure"])
+        .code(1)
+        .stderr(predicate::str::contains("kept x (--keep-on-failure)"));
+    assert!(td.path().join(".worktrees/x/.wrt.env").exists());
+    assert!(branches().lines().any(|b| b == "x"));
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).arg("ls");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match(r"x\s+block=1\s+offset=100\s+failed").unwrap());
+    new("x", &[])
+        .code(2)
+        .stderr(predicate::str::contains("its setup failed"));
+}
+
+#[test]
+fn setup_reruns_steps_idempotently_and_records_them() {
+    let td = init_repo();
+    fs::create_dir_all(td.path().join("supabase")).unwrap();
+    fs::write(
+        td.path().join("supabase").join("config.toml"),
+        "project_id = \"myproj\"\nport = 5432\n",
+    )
+    .unwrap();
+    git(td.path(), &["add", "supabase/config.toml"]);
+    git(
+        td.path(),
+        &[
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "commit",
+            "-m",
+            "add supabase",
+        ],
+    );
+    let write_db = |cmd: &str| {
+        fs::write(
+            td.path().join(".wrt.json"),
+            format!(
+                r#"{{"version":1,"port_block_size":100,"package_manager":{{"name":"unknown","install_command":["npm","install"]}},"services":[],"database":{{"detected":true,"kind":"unknown","reset_command":["sh","-c","{cmd}"]}},"supabase":{{"detected":false}}}}"#
+            ),
+        )
+        .unwrap();
+    };
+    write_db("echo ran >> .db_runs");
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["new", "x", "--install", "false", "--db", "false"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    let wt = td.path().join(".worktrees").join("x");
+    let steps = || {
+        let p = td.path().join(".git").join(".wrt").join("state.json");
+        let v: serde_json::Value = serde_json::from_str(&fs::read_to_string(p).unwrap()).unwrap();
+        v["allocations"]["x"]["steps"].clone()
+    };
+    let setup = |args: &[&str]| {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path())
+            .args(["setup", "x", "--install", "false"])
+            .args(args);
+        set_minimal_path(&mut cmd);
+        cmd.assert()
+    };
+    assert_eq!(steps(), serde_json::json!(["env", "files", "supabase"]));
+
+    fs::remove_file(wt.join(".wrt.env")).unwrap();
+    setup(&["--steps", "env"]).success();
+    assert!(wt.join(".wrt.env").exists());
+
+    // Patching again starts from the committed config: the offset is applied once.
+    for runs in 1..=2 {
+        setup(&["--from-step", "supabase", "--db", "true"]).success();
+        let config = fs::read_to_string(wt.join("supabase").join("config.toml")).unwrap();
+        assert!(config.contains("project_id = \"myproj-x\""));
+        assert!(config.contains("port = 5532"));
+        let db_runs = fs::read_to_string(wt.join(".db_runs")).unwrap();
+        assert_eq!(db_runs.lines().count(), runs);
+    }
+    assert_eq!(
+        steps(),
+        serde_json::json!(["env", "files", "supabase", "db"])
+    );
+
+    // Edits of the user's survive: the config is already patched for this block.
+    let cfg = wt.join("supabase").join("config.toml");
+    fs::write(&cfg, fs::read_to_string(&cfg).unwrap() + "[mine]\n").unwrap();
+    setup(&["--steps", "supabase"]).success();
+    let config = fs::read_to_string(&cfg).unwrap();
+    assert!(config.contains("[mine]") && config.contains("port = 5532"));
+
+    write_db("exit 3");
+    setup(&["--steps", "db", "--db", "true"])
+        .code(1)
+        .stderr(predicate::str::contains("db setup failed"));
+    assert_eq!(steps(), serde_json::json!(["env", "files", "supabase"]));
+    let ls = || {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path()).arg("ls");
+        String::from_utf8(cmd.output().unwrap().stdout).unwrap()
+    };
+    assert!(ls().contains(" failed "));
+
+    write_db("true");
+    setup(&["--steps", "db", "--db", "true"]).success();
+    assert!(!ls().contains(" failed "));
+
+    setup(&["--steps", "nope"]).code(2);
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args(["setup", "missing"]);
+    cmd.assert()
+        .code(2)
+        .stderr(predicate::str::contains("unknown worktree"));
+}
+
+#[test]
+fn new_applies_profiles_with_flags_winning() {
+    let td = init_repo();
+    let home = TempDir::new().unwrap();
+    fs::write(td.path().join(".env.local"), "SECRET=1\n").unwrap();
+    fs::write(
+        td.path().join(".wrt.json"),
+        r#"{"version":1,"port_block_size":100,"package_manager":{"name":"unknown","install_command":["npm","install"]},"services":[],"database":{"detected":true,"kind":"unknown","reset_command":["sh","-c","echo ran > .db_ran"]},"supabase":{"detected":false}}"#,
+    )
+    .unwrap();
+    let user_dir = home.path().join("wrt");
+    fs::create_dir_all(&user_dir).unwrap();
+    fs::write(
+        user_dir.join("config.json"),
+        r#"{"default_profile":"review","profiles":{"review":{"install":"false","db":"false","labels":{"kind":"review"}}}}"#,
+    )
+    .unwrap();
+    let new = |args: &[&str]| {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path())
+            .env("XDG_CONFIG_HOME", home.path())
+            .arg("new")
+            .args(args);
+        set_minimal_path(&mut cmd);
+        cmd.assert()
+    };
+    let alloc = |name: &str| {
+        let p = td.path().join(".git").join(".wrt").join("state.json");
+        let v: serde_json::Value = serde_json::from_str(&fs::read_to_string(p).unwrap()).unwrap();
+        v["allocations"][name].clone()
+    };
+
+    // The user's default profile applies when the repo sets none.
+    new(&["r"]).success();
+    let a = alloc("r");
+    assert_eq!(a["profile"], "review");
+    assert_eq!(a["labels"]["kind"], "review");
+    assert!(!td.path().join(".worktrees/r/.db_ran").exists());
+
+    let repo_dir = td.path().join(".git").join(".wrt");
+    fs::write(
+        repo_dir.join("config.json"),
+        r#"{"profiles":{"sandbox":{"install":"false","db":"true","files":[".env.local"],"labels":{"kind":"sandbox"}}}}"#,
+    )
+    .unwrap();
+    new(&["s", "--profile", "sandbox", "--label", "kind=mine"]).success();
+    let wt = td.path().join(".worktrees").join("s");
+    assert_eq!(
+        fs::read_to_string(wt.join(".env.local")).unwrap(),
+        "SECRET=1\n"
+    );
+    assert!(wt.join(".db_ran").exists());
+    assert_eq!(alloc("s")["labels"]["kind"], "mine");
+
+    new(&["t", "--profile", "sandbox", "--db", "false"]).success();
+    assert!(!td.path().join(".worktrees/t/.db_ran").exists());
+
+    new(&["u", "--profile", "nope"])
+        .code(2)
+        .stderr(predicate::str::contains("unknown profile"));
+}
+
 #[test]
 fn db_reset_requires_yes_non_interactive_and_runs_with_yes() {
     let td = init_repo();
@@ -529,6 +819,131 @@ fn rm_delete_branch_removes_branch_ref() {
     assert!(!status.success());
 }
 
+#[test]
+fn completions_list_worktrees_profiles_and_db_actions() {
+    let td = init_repo();
+    for name in ["api", "web"] {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path())
+            .args(["new", name, "--install", "false", "--db", "false"]);
+        set_minimal_path(&mut cmd);
+        cmd.assert().success();
+    }
+    fs::write(
+        td.path().join(".git").join(".wrt").join("config.json"),
+        r#"{"profiles":{"review":{"db":"false"}}}"#,
+    )
+    .unwrap();
+    let complete = |words: &[&str]| {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path())
+            .arg("__complete")
+            .arg("--")
+            .args(words);
+        let out = cmd.output().unwrap();
+        assert!(out.status.success());
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    assert_eq!(complete(&["rm", ""]), "api\tapi\nweb\tweb\n");
+    assert_eq!(complete(&["path", "w"]), "web\tweb\n");
+    assert!(complete(&["db", "api", ""]).starts_with("reset\t"));
+    assert_eq!(complete(&["new", "x", "--profile", ""]), "review\n");
+    assert!(complete(&[""]).lines().any(|l| l.starts_with("setup\t")));
+
+    // Outside a repo there are no dynamic values, but no errors either.
+    let outside = TempDir::new().unwrap();
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(outside.path())
+        .args(["__complete", "--", "rm", ""]);
+    cmd.assert().success().stdout("").stderr("");
+
+    for shell in ["bash", "zsh", "fish", "nushell", "powershell"] {
+        let mut cmd = wrt_cmd();
+        cmd.args(["completions", shell]);
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("wrt __complete"));
+    }
+    let mut cmd = wrt_cmd();
+    cmd.args(["completions", "tcsh"]);
+    cmd.assert().code(2);
+}
+
+#[test]
+fn cd_prompt_and_shell_init_bash() {
+    let td = init_repo();
+    for name in ["api", "a/gpt/login-timeout"] {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path())
+            .args(["new", name, "--install", "false", "--db", "false"]);
+        set_minimal_path(&mut cmd);
+        cmd.assert().success();
+    }
+    let wrt = |dir: &Path, args: &[&str]| {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(dir).args(args);
+        cmd.assert()
+    };
+
+    wrt(td.path(), &["cd", "login"])
+        .success()
+        .stdout(predicate::str::ends_with(
+            ".worktrees/a-gpt-login-timeout\n",
+        ));
+    wrt(td.path(), &["cd", "a"])
+        .code(2)
+        .stderr(predicate::str::contains("ambiguous"));
+    wrt(td.path(), &["cd", "zzz"])
+        .code(2)
+        .stderr(predicate::str::contains("no worktree matches"));
+
+    let api = td.path().join(".worktrees").join("api");
+    fs::create_dir_all(api.join("src")).unwrap();
+    wrt(&api.join("src"), &["prompt"])
+        .success()
+        .stdout("api+100\n");
+    wrt(&api, &["prompt", "--format", "{name}:{block}"])
+        .success()
+        .stdout("api:1\n");
+    wrt(td.path(), &["prompt"]).success().stdout("");
+
+    let bin_dir = Path::new(env!("CARGO_BIN_EXE_wrt")).parent().unwrap();
+    let script = r#"
+set -e
+eval "$(wrt shell-init bash)"
+wrt cd login; _wrt_env_hook
+echo "1 ${PWD##*/} $WRT_NAME"
+wrt cd; _wrt_env_hook
+echo "2 ${WRT_NAME-unset}"
+wrt new web --install false --db false 2>/dev/null; _wrt_env_hook
+echo "3 ${PWD##*/} $WRT_PORT_OFFSET"
+"#;
+    let out = StdCommand::new("bash")
+        .arg("-c")
+        .arg(script)
+        .current_dir(td.path())
+        .env("PATH", format!("{}:/usr/bin:/bin", bin_dir.display()))
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "1 a-gpt-login-timeout a-gpt-login-timeout\n2 unset\n3 web 300\n"
+    );
+
+    for shell in ["zsh", "fish"] {
+        wrt(td.path(), &["shell-init", shell])
+            .success()
+            .stdout(predicate::str::contains("command wrt cd"));
+    }
+    wrt(td.path(), &["shell-init", "nushell"]).code(2);
+}
+
 #[test]
 fn env_infers_from_cwd() {
     let td = init_repo();
@@ -600,3 +1015,1766 @@ fn run_propagates_exit_code_and_requires_separator() {
     set_minimal_path(&mut cmd);
     cmd.assert().code(42);
 }
+
+#[test]
+fn new_sparse_only_materializes_requested_paths() {
+    let td = init_repo();
+    for d in ["apps/web", "apps/api", "docs"] {
+        fs::create_dir_all(td.path().join(d)).unwrap();
+        fs::write(td.path().join(d).join("f.txt"), "x\n").unwrap();
+    }
+    git(td.path(), &["add", "."]);
+    git(
+        td.path(),
+        &[
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "commit",
+            "-m",
+            "dirs",
+        ],
+    );
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args([
+        "new",
+        "x",
+        "--install",
+        "false",
+        "--supabase",
+        "false",
+        "--db",
+        "false",
+        "--sparse",
+        "apps/web",
+    ]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    let wt_dir = td.path().join(".worktrees").join("x");
+    assert!(wt_dir.join("README.md").exists());
+    assert!(wt_dir.join("apps/web/f.txt").exists());
+    assert!(!wt_dir.join("apps/api").exists());
+    assert!(!wt_dir.join("docs").exists());
+
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["sparse", "x", "add", "docs"])
+        .assert()
+        .success();
+    assert!(wt_dir.join("docs/f.txt").exists());
+
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["sparse", "x", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("apps/web").and(predicate::str::contains("docs")));
+
+    let st_path = td.path().join(".git").join(".wrt").join("state.json");
+    let v: serde_json::Value = serde_json::from_str(&fs::read_to_string(st_path).unwrap()).unwrap();
+    let sparse = v["allocations"]["x"]["sparse"].as_array().unwrap();
+    assert_eq!(sparse.len(), 2);
+
+    // The main workdir must stay a full checkout.
+    assert!(td.path().join("apps/api/f.txt").exists());
+}
+
+#[test]
+fn new_sparse_profile_from_repo_config() {
+    let td = init_repo();
+    fs::create_dir_all(td.path().join("web")).unwrap();
+    fs::write(td.path().join("web").join("f.txt"), "x\n").unwrap();
+    fs::create_dir_all(td.path().join("api")).unwrap();
+    fs::write(td.path().join("api").join("f.txt"), "x\n").unwrap();
+    git(td.path(), &["add", "."]);
+    git(
+        td.path(),
+        &[
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "commit",
+            "-m",
+            "dirs",
+        ],
+    );
+
+    let cfg_dir = td.path().join(".git").join(".wrt");
+    fs::create_dir_all(&cfg_dir).unwrap();
+    fs::write(
+        cfg_dir.join("config.json"),
+        r#"{"sparse_profiles":{"front":["web"]}}"#,
+    )
+    .unwrap();
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args([
+        "new",
+        "x",
+        "--install",
+        "false",
+        "--supabase",
+        "false",
+        "--db",
+        "false",
+        "--sparse-profile",
+        "front",
+    ]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    let wt_dir = td.path().join(".worktrees").join("x");
+    assert!(wt_dir.join("web/f.txt").exists());
+    assert!(!wt_dir.join("api").exists());
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["new", "y", "--sparse-profile", "nope"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert()
+        .code(2)
+        .stderr(predicate::str::contains("unknown sparse profile"));
+}
+
+#[test]
+fn new_pr_fetches_github_and_gitlab_refs() {
+    let td = init_repo();
+    let origin = TempDir::new().unwrap();
+
+    git(origin.path(), &["init", "--bare"]);
+    git(
+        td.path(),
+        &["remote", "add", "origin", origin.path().to_str().unwrap()],
+    );
+
+    // Publish two commits only under PR/MR refs, the way the hosting providers do.
+    git(td.path(), &["checkout", "-b", "contrib"]);
+    fs::write(td.path().join("PR.txt"), "pr\n").unwrap();
+    git(td.path(), &["add", "PR.txt"]);
+    git(
+        td.path(),
+        &[
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "commit",
+            "-m",
+            "pr",
+        ],
+    );
+    git(td.path(), &["push", "origin", "HEAD:refs/pull/7/head"]);
+    fs::write(td.path().join("MR.txt"), "mr\n").unwrap();
+    git(td.path(), &["add", "MR.txt"]);
+    git(
+        td.path(),
+        &[
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "commit",
+            "-m",
+            "mr",
+        ],
+    );
+    git(
+        td.path(),
+        &["push", "origin", "HEAD:refs/merge-requests/9/head"],
+    );
+    git(td.path(), &["checkout", "-"]);
+    git(td.path(), &["branch", "-D", "contrib"]);
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args([
+        "new",
+        "--pr",
+        "7",
+        "--install",
+        "false",
+        "--supabase",
+        "false",
+        "--db",
+        "false",
+    ]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    let wt_dir = td.path().join(".worktrees").join("pr-7");
+    assert!(wt_dir.join("PR.txt").exists());
+    assert!(!wt_dir.join("MR.txt").exists());
+    let merge = git_out(td.path(), &["config", "branch.pr/7.merge"]);
+    assert_eq!(merge.trim(), "refs/pull/7/head");
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args([
+        "new",
+        "review-mr",
+        "--pr",
+        "9",
+        "--install",
+        "false",
+        "--supabase",
+        "false",
+        "--db",
+        "false",
+    ]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+    assert!(td.path().join(".worktrees/review-mr/MR.txt").exists());
+
+    let st_path = td.path().join(".git").join(".wrt").join("state.json");
+    let v: serde_json::Value = serde_json::from_str(&fs::read_to_string(st_path).unwrap()).unwrap();
+    assert_eq!(v["allocations"]["pr-7"]["pr"], 7);
+    assert_eq!(v["allocations"]["review-mr"]["pr"], 9);
+    assert_eq!(v["allocations"]["review-mr"]["branch"], "pr/9");
+
+    let new_pr7 = |args: &[&str]| {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path())
+            .args(["new", "--pr", "7", "--install", "false", "--db", "false"])
+            .args(args);
+        set_minimal_path(&mut cmd);
+        cmd.assert()
+    };
+    new_pr7(&["x", "--from", "HEAD"]).code(2);
+
+    // A local branch left at an older head is fast-forwarded to the new push.
+    git(td.path(), &["branch", "old7", "refs/wrt/pr/origin/7"]);
+    fs::write(wt_dir.join("PR2.txt"), "pr2\n").unwrap();
+    git(&wt_dir, &["add", "PR2.txt"]);
+    git(
+        &wt_dir,
+        &[
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "commit",
+            "-m",
+            "pr2",
+        ],
+    );
+    git(&wt_dir, &["push", "origin", "HEAD:refs/pull/7/head"]);
+    new_pr7(&["upd", "--branch", "old7"]).success();
+    assert!(td.path().join(".worktrees/upd/PR2.txt").exists());
+
+    // One checked out in another worktree is left alone: git refuses the second checkout.
+    let busy = td.path().join("busy");
+    git(
+        td.path(),
+        &[
+            "worktree",
+            "add",
+            "-b",
+            "busy7",
+            busy.to_str().unwrap(),
+            "old7^",
+        ],
+    );
+    let before = git_out(td.path(), &["rev-parse", "busy7"]);
+    new_pr7(&["busy", "--branch", "busy7"]).code(1);
+    assert_eq!(git_out(td.path(), &["rev-parse", "busy7"]), before);
+    assert_eq!(git_out(&busy, &["status", "--porcelain"]), "");
+
+    // One that went its own way is refused.
+    let div = git_out(
+        td.path(),
+        &[
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "commit-tree",
+            "HEAD^{tree}",
+            "-p",
+            "HEAD",
+            "-m",
+            "div",
+        ],
+    );
+    git(td.path(), &["branch", "div7", div.trim()]);
+    new_pr7(&["div", "--branch", "div7"])
+        .code(1)
+        .stderr(predicate::str::contains("branch div7 has diverged from #7"));
+    assert!(!td.path().join(".worktrees/div").exists());
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args(["new", "--pr", "404"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert()
+        .code(1)
+        .stderr(predicate::str::contains("#404 not found on remote origin"));
+}
+
+#[test]
+fn new_continues_when_fetch_fails() {
+    let td = init_repo();
+    let gone = td.path().join("no-such-remote.git");
+    git(
+        td.path(),
+        &["remote", "add", "origin", gone.to_str().unwrap()],
+    );
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args([
+        "new",
+        "x",
+        "--install",
+        "false",
+        "--supabase",
+        "false",
+        "--db",
+        "false",
+    ]);
+    set_minimal_path(&mut cmd);
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("continuing with existing refs"));
+    assert!(td.path().join(".worktrees").join("x").exists());
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args([
+        "new",
+        "y",
+        "--no-fetch",
+        "--install",
+        "false",
+        "--supabase",
+        "false",
+        "--db",
+        "false",
+    ]);
+    set_minimal_path(&mut cmd);
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("fetch").not());
+}
+
+#[test]
+fn new_remote_option_selects_tracking_remote() {
+    let td = init_repo();
+    let upstream = TempDir::new().unwrap();
+    git(upstream.path(), &["init", "--bare"]);
+
+    let gone = td.path().join("no-such-remote.git");
+    git(
+        td.path(),
+        &["remote", "add", "origin", gone.to_str().unwrap()],
+    );
+    git(
+        td.path(),
+        &[
+            "remote",
+            "add",
+            "upstream",
+            upstream.path().to_str().unwrap(),
+        ],
+    );
+
+    git(td.path(), &["checkout", "-b", "feature/up"]);
+    fs::write(td.path().join("UP.txt"), "up\n").unwrap();
+    git(td.path(), &["add", "UP.txt"]);
+    git(
+        td.path(),
+        &[
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "commit",
+            "-m",
+            "up",
+        ],
+    );
+    git(td.path(), &["push", "upstream", "feature/up"]);
+    git(td.path(), &["checkout", "-"]);
+    git(td.path(), &["branch", "-D", "feature/up"]);
+    git(
+        td.path(),
+        &["update-ref", "-d", "refs/remotes/upstream/feature/up"],
+    );
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args([
+        "new",
+        "feature/up",
+        "--remote",
+        "upstream",
+        "--install",
+        "false",
+        "--supabase",
+        "false",
+        "--db",
+        "false",
+    ]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    assert!(td.path().join(".worktrees/feature-up/UP.txt").exists());
+    let up = git_out(
+        td.path(),
+        &["rev-parse", "--abbrev-ref", "feature/up@{upstream}"],
+    );
+    assert_eq!(up.trim(), "upstream/feature/up");
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["new", "z", "--remote", "nope"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert()
+        .code(2)
+        .stderr(predicate::str::contains("unknown git remote"));
+}
+
+#[test]
+fn adopt_tracks_plain_git_worktree() {
+    let td = init_repo();
+    git(
+        td.path(),
+        &["worktree", "add", "-b", "manual", "../manual-wt"],
+    );
+    let wt_dir = td.path().parent().unwrap().join("manual-wt");
+
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["adopt", wt_dir.to_str().unwrap()])
+        .assert()
+        .success();
+
+    assert!(wt_dir.join(".wrt.env").exists());
+    let env = fs::read_to_string(wt_dir.join(".wrt.env")).unwrap();
+    assert!(env.contains("WRT_NAME=manual-wt"));
+    assert!(env.contains("WRT_PORT_BLOCK=1"));
+
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["ls"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("manual-wt").and(predicate::str::contains("manual")));
+
+    // Adopting again is a no-op.
+    wrt_cmd()
+        .current_dir(&wt_dir)
+        .args(["adopt"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("already tracked"));
+
+    fs::remove_dir_all(&wt_dir).unwrap();
+}
+
+#[test]
+fn adopt_all_recovers_blocks_from_patched_supabase_config() {
+    let td = init_repo();
+    let sbdir = td.path().join("supabase");
+    fs::create_dir_all(&sbdir).unwrap();
+    fs::write(
+        sbdir.join("config.toml"),
+        "project_id = \"myproj\"\nport = 5432\n",
+    )
+    .unwrap();
+    git(td.path(), &["add", "supabase/config.toml"]);
+    git(
+        td.path(),
+        &[
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "commit",
+            "-m",
+            "add supabase",
+        ],
+    );
+
+    for name in ["a", "b"] {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path())
+            .args(["new", name, "--install", "false", "--db", "false"]);
+        set_minimal_path(&mut cmd);
+        cmd.assert().success();
+    }
+
+    // Lose a's state entry and state.json entirely; b (block 2) must come back as block 2.
+    let st_path = td.path().join(".git").join(".wrt").join("state.json");
+    fs::remove_file(&st_path).unwrap();
+    git(td.path(), &["worktree", "add", "-b", "c", ".worktrees/c"]);
+    // d is patched for a's block and hand-edited besides: it gets a new block, its config stays.
+    git(td.path(), &["worktree", "add", "-b", "d", ".worktrees/d"]);
+    let d_cfg_path = td.path().join(".worktrees/d/supabase/config.toml");
+    let d_cfg = fs::read_to_string(td.path().join(".worktrees/a/supabase/config.toml"))
+        .unwrap()
+        .replace("myproj-a", "myproj-d")
+        + "# mine\n";
+    fs::write(&d_cfg_path, &d_cfg).unwrap();
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args(["adopt", "--all"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    let v: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&st_path).unwrap()).unwrap();
+    assert_eq!(v["allocations"]["a"]["block"], 1);
+    assert_eq!(v["allocations"]["b"]["block"], 2);
+    assert_eq!(v["allocations"]["c"]["block"], 3);
+
+    let b_cfg = fs::read_to_string(td.path().join(".worktrees/b/supabase/config.toml")).unwrap();
+    assert!(b_cfg.contains("project_id = \"myproj-b\""));
+    assert!(b_cfg.contains("port = 5632"));
+    let c_cfg = fs::read_to_string(td.path().join(".worktrees/c/supabase/config.toml")).unwrap();
+    assert!(c_cfg.contains("project_id = \"myproj-c\""));
+    assert!(c_cfg.contains("port = 5732"));
+    assert_eq!(v["allocations"]["d"]["block"], 4);
+    assert_eq!(fs::read_to_string(&d_cfg_path).unwrap(), d_cfg);
+}
+
+#[test]
+fn doctor_reports_and_fixes_drift() {
+    let td = init_repo();
+
+    for name in ["x", "y", "z"] {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path())
+            .args(["new", name, "--install", "false", "--supabase", "false"]);
+        set_minimal_path(&mut cmd);
+        cmd.assert().success();
+    }
+
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["doctor"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("no problems found"));
+
+    // Drift: y shares x's block, z's directory is gone, x lost its .wrt.env, excludes are gone.
+    let st_path = td.path().join(".git").join(".wrt").join("state.json");
+    let mut v: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&st_path).unwrap()).unwrap();
+    v["allocations"]["y"]["block"] = 1.into();
+    v["allocations"]["y"]["offset"] = 100.into();
+    fs::write(&st_path, serde_json::to_string_pretty(&v).unwrap()).unwrap();
+    fs::remove_dir_all(td.path().join(".worktrees").join("z")).unwrap();
+    fs::remove_file(td.path().join(".worktrees/x/.wrt.env")).unwrap();
+    fs::write(td.path().join(".git/info/exclude"), "").unwrap();
+
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["doctor"])
+        .assert()
+        .code(1)
+        .stdout(
+            predicate::str::contains("port block 1 is also used by \"x\"")
+                .and(predicate::str::contains("path missing"))
+                .and(predicate::str::contains(".wrt.env missing"))
+                .and(predicate::str::contains("missing patterns"))
+                .and(predicate::str::contains("stale worktree entry")),
+        );
+
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["doctor", "--fix"])
+        .assert()
+        .success();
+
+    let v: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&st_path).unwrap()).unwrap();
+    assert_eq!(v["allocations"]["x"]["block"], 1);
+    assert_eq!(v["allocations"]["y"]["block"], 2);
+    assert!(v["allocations"].get("z").is_none());
+    let y_env = fs::read_to_string(td.path().join(".worktrees/y/.wrt.env")).unwrap();
+    assert!(y_env.contains("WRT_PORT_OFFSET=200"));
+    assert!(td.path().join(".worktrees/x/.wrt.env").exists());
+
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["doctor"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("no problems found"));
+}
+
+#[test]
+fn doctor_fix_keeps_local_edits_of_the_supabase_config() {
+    let td = init_repo();
+    fs::create_dir_all(td.path().join("supabase")).unwrap();
+    fs::write(
+        td.path().join("supabase/config.toml"),
+        "project_id = \"myproj\"\nport = 5432\n",
+    )
+    .unwrap();
+    git(td.path(), &["add", "supabase/config.toml"]);
+    git(
+        td.path(),
+        &[
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "commit",
+            "-m",
+            "add supabase",
+        ],
+    );
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["new", "s", "--install", "false", "--db", "false"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    // The patch is gone, and the user added a setting of their own.
+    let cfg = td.path().join(".worktrees/s/supabase/config.toml");
+    fs::write(
+        &cfg,
+        "project_id = \"myproj\"\nport = 5432\n[mine]\nx = 1\n",
+    )
+    .unwrap();
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["doctor"])
+        .assert()
+        .code(1)
+        .stdout(
+            predicate::str::contains("fix it by hand")
+                .and(predicate::str::contains("[fixable").not()),
+        );
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["doctor", "--fix"])
+        .assert()
+        .code(1);
+    assert!(fs::read_to_string(&cfg).unwrap().contains("[mine]"));
+
+    // Without the edit, --fix re-patches it.
+    fs::write(&cfg, "project_id = \"myproj\"\nport = 5432\n").unwrap();
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["doctor", "--fix"])
+        .assert()
+        .success();
+    assert!(fs::read_to_string(&cfg)
+        .unwrap()
+        .contains("project_id = \"myproj-s\""));
+}
+
+#[test]
+fn rm_refuses_to_lose_work_and_backup_preserves_it() {
+    let td = init_repo();
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["new", "x", "--install", "false", "--supabase", "false"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+    let wt_dir = td.path().join(".worktrees").join("x");
+
+    fs::write(wt_dir.join("notes.txt"), "draft\n").unwrap();
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["rm", "x"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("uncommitted: ?? notes.txt"));
+    assert!(wt_dir.exists());
+
+    git(&wt_dir, &["add", "notes.txt"]);
+    git(
+        &wt_dir,
+        &[
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "commit",
+            "-m",
+            "agent output",
+        ],
+    );
+    fs::write(wt_dir.join("wip.txt"), "half done\n").unwrap();
+
+    // Without --delete-branch the commit survives, but the uncommitted file doesn't.
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["rm", "x", "--delete-branch"])
+        .assert()
+        .code(1)
+        .stderr(
+            predicate::str::contains("unpushed commit:")
+                .and(predicate::str::contains("agent output"))
+                .and(predicate::str::contains("uncommitted: ?? wip.txt")),
+        );
+
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["rm", "x", "--delete-branch", "--backup"])
+        .assert()
+        .success();
+    assert!(!wt_dir.exists());
+
+    let log = git_out(td.path(), &["log", "--format=%s", "refs/wrt-archive/x"]);
+    assert!(log.contains("agent output"));
+    let files = git_out(
+        td.path(),
+        &["ls-tree", "-r", "--name-only", "refs/wrt-archive/x-wip"],
+    );
+    assert!(files.contains("wip.txt") && files.contains("notes.txt"));
+}
+
+#[test]
+fn rm_clean_worktree_needs_no_force() {
+    let td = init_repo();
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["new", "x", "--install", "false", "--supabase", "false"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["rm", "x", "--delete-branch"])
+        .assert()
+        .success();
+    assert!(!td.path().join(".worktrees").join("x").exists());
+}
+
+#[test]
+fn archive_and_restore_roundtrip() {
+    let td = init_repo();
+    let sbdir = td.path().join("supabase");
+    fs::create_dir_all(&sbdir).unwrap();
+    fs::write(
+        sbdir.join("config.toml"),
+        "project_id = \"myproj\"\nport = 5432\n",
+    )
+    .unwrap();
+    git(td.path(), &["add", "supabase/config.toml"]);
+    git(
+        td.path(),
+        &[
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "commit",
+            "-m",
+            "add supabase",
+        ],
+    );
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["new", "x", "--install", "false", "--db", "false"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    let wt_dir = td.path().join(".worktrees").join("x");
+    fs::write(wt_dir.join("README.md"), "changed\n").unwrap();
+    fs::write(wt_dir.join("scratch.txt"), "untracked\n").unwrap();
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args(["archive", "x"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+    assert!(!wt_dir.exists());
+
+    let st_path = td.path().join(".git").join(".wrt").join("state.json");
+    let v: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&st_path).unwrap()).unwrap();
+    assert!(v["allocations"].get("x").is_none());
+    assert_eq!(v["archived"]["x"]["block"], 1);
+    assert_eq!(v["archived"]["x"]["wip_ref"], "refs/wrt-wip/x");
+
+    wrt_cmd()
+        .current_dir(td.path())
+        .args(["ls"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("arch").and(predicate::str::contains("x")));
+
+    // The block was released, so a new worktree takes it.
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["new", "y", "--install", "false", "--db", "false"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    // A backup of the same branch elsewhere must not overwrite the archived changes.
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args([
+        "new",
+        "z",
+        "--branch",
+        "x",
+        "--install",
+        "false",
+        "--supabase",
+        "false",
+        "--db",
+        "false",
+    ]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+    fs::write(td.path().join(".worktrees/z/other.txt"), "z\n").unwrap();
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args(["rm", "z", "--backup"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args(["restore", "x"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+    assert!(!wt_dir.join("other.txt").exists());
+
+    assert_eq!(
+        fs::read_to_string(wt_dir.join("README.md")).unwrap(),
+        "changed\n"
+    );
+    assert_eq!(
+        fs::read_to_string(wt_dir.join("scratch.txt")).unwrap(),
+        "untracked\n"
+    );
+    let cfg = fs::read_to_string(wt_dir.join("supabase/config.toml")).unwrap();
+    assert!(cfg.contains("project_id = \"myproj-x\""));
+    assert!(cfg.contains("port = 5632"));
+
+    let v: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&st_path).unwrap()).unwrap();
+    assert_eq!(v["allocations"]["x"]["block"], 2);
+    assert!(v.get("archived").is_none());
+
+    // With its block free, an archived worktree gets the same block back.
+    for args in [["archive", "y"], ["restore", "y"]] {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path()).args(args);
+        set_minimal_path(&mut cmd);
+        cmd.assert().success();
+    }
+    let v: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&st_path).unwrap()).unwrap();
+    assert_eq!(v["allocations"]["y"]["block"], 1);
+}
+
+#[test]
+fn mv_renames_worktree_state_env_and_supabase_suffix() {
+    let td = init_repo();
+    let sbdir = td.path().join("supabase");
+    fs::create_dir_all(&sbdir).unwrap();
+    fs::write(
+        sbdir.join("config.toml"),
+        "project_id = \"myproj\"\nport = 5432\n",
+    )
+    .unwrap();
+    git(td.path(), &["add", "supabase/config.toml"]);
+    git(
+        td.path(),
+        &[
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "commit",
+            "-m",
+            "add supabase",
+        ],
+    );
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["new", "agent-3", "--install", "false", "--db", "false"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["mv", "agent-3", "login-fix", "--branch"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    let old_dir = td.path().join(".worktrees").join("agent-3");
+    let wt_dir = td.path().join(".worktrees").join("login-fix");
+    assert!(!old_dir.exists());
+    assert!(wt_dir.exists());
+
+    let env = fs::read_to_string(wt_dir.join(".wrt.env")).unwrap();
+    assert!(env.contains("WRT_NAME=login-fix"));
+    assert!(env.contains("WRT_BRANCH=login-fix"));
+
+    let cfg = fs::read_to_string(wt_dir.join("supabase/config.toml")).unwrap();
+    assert!(cfg.contains("project_id = \"myproj-login-fix\""));
+    assert!(cfg.contains("port = 5532"));
+    let v = git_out(&wt_dir, &["ls-files", "-v", "supabase/config.toml"]);
+    assert!(v.starts_with('S'));
+
+    let head = git_out(&wt_dir, &["rev-parse", "--abbrev-ref", "HEAD"]);
+    assert_eq!(head.trim(), "login-fix");
+
+    let st_path = td.path().join(".git").join(".wrt").join("state.json");
+    let v: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&st_path).unwrap()).unwrap();
+    assert!(v["allocations"].get("agent-3").is_none());
+    assert_eq!(v["allocations"]["login-fix"]["block"], 1);
+    assert_eq!(v["allocations"]["login-fix"]["branch"], "login-fix");
+
+    // Without --branch the branch name stays.
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args(["mv", "login-fix", "lf"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+    let v: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&st_path).unwrap()).unwrap();
+    assert_eq!(v["allocations"]["lf"]["branch"], "login-fix");
+
+    // A branch rename git refuses puts the worktree back where state has it.
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["mv", "lf", "other", "--branch", "bad..name"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().code(1);
+    assert!(td.path().join(".worktrees").join("lf").exists());
+    assert!(!td.path().join(".worktrees").join("other").exists());
+    let v: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&st_path).unwrap()).unwrap();
+    assert!(v["allocations"].get("other").is_none());
+    assert!(v["allocations"]["lf"]["path"]
+        .as_str()
+        .unwrap()
+        .ends_with("/lf"));
+
+    // A config with edits of the user's keeps them (and its project_id).
+    let cfg = td.path().join(".worktrees/lf/supabase/config.toml");
+    fs::write(&cfg, fs::read_to_string(&cfg).unwrap() + "[mine]\n").unwrap();
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args(["mv", "lf", "lf2"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("has local edits"));
+    let cfg = fs::read_to_string(td.path().join(".worktrees/lf2/supabase/config.toml")).unwrap();
+    assert!(cfg.contains("[mine]") && cfg.contains("project_id = \"myproj-lf\""));
+}
+
+#[test]
+fn metadata_tag_filter_and_foreach() {
+    let td = init_repo();
+
+    for (name, agent) in [("a1", "gpt"), ("a2", "claude")] {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path()).args([
+            "new",
+            name,
+            "--install",
+            "false",
+            "--supabase",
+            "false",
+            "--db",
+            "false",
+            "--label",
+            &format!("agent={agent}"),
+            "--owner",
+            "alice",
+            "--task-url",
+            "https://example.com/t/1",
+        ]);
+        set_minimal_path(&mut cmd);
+        cmd.assert().success();
+    }
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args([
+        "tag",
+        "a2",
+        "--note",
+        "needs review",
+        "--owner",
+        "",
+        "--label",
+        "team=web",
+    ]);
+    cmd.assert().success();
+
+    let st_path = td.path().join(".git").join(".wrt").join("state.json");
+    let v: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&st_path).unwrap()).unwrap();
+    let a2 = &v["allocations"]["a2"];
+    assert_eq!(a2["labels"]["agent"], "claude");
+    assert_eq!(a2["labels"]["team"], "web");
+    assert_eq!(a2["note"], "needs review");
+    assert!(a2.get("owner").is_none());
+    assert_eq!(v["allocations"]["a1"]["owner"], "alice");
+    assert_eq!(
+        v["allocations"]["a1"]["task_url"],
+        "https://example.com/t/1"
+    );
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args(["tag", "a2"]);
+    let out = cmd.assert().success().get_output().stdout.clone();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("needs review"));
+    assert!(out.contains("agent=claude team=web"));
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["ls", "--filter", "label=agent=gpt"]);
+    let out = cmd.assert().success().get_output().stdout.clone();
+    let out = String::from_utf8(out).unwrap();
+    // Paths include a random tempdir name; match on the leading name column.
+    assert!(out.lines().any(|l| l.starts_with("a1 ")));
+    assert!(!out.lines().any(|l| l.starts_with("a2 ")));
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["ls", "--filter", "bogus=1"]);
+    cmd.assert().code(2);
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args([
+        "foreach",
+        "--filter",
+        "label=team",
+        "--",
+        "sh",
+        "-c",
+        "echo $WRT_NAME > marker",
+    ]);
+    cmd.assert().success();
+    let wt = td.path().join(".worktrees");
+    assert_eq!(
+        fs::read_to_string(wt.join("a2").join("marker"))
+            .unwrap()
+            .trim(),
+        "a2"
+    );
+    assert!(!wt.join("a1").join("marker").exists());
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args(["foreach", "--", "false"]);
+    cmd.assert().code(1);
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["tag", "a1", "--label", "novalue"]);
+    cmd.assert().code(2);
+}
+
+#[test]
+fn gc_collects_idle_expired_and_merged_worktrees() {
+    let td = init_repo();
+    let new = |name: &str, extra: &[&str]| {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path()).args([
+            "new",
+            name,
+            "--install",
+            "false",
+            "--supabase",
+            "false",
+            "--db",
+            "false",
+        ]);
+        cmd.args(extra);
+        set_minimal_path(&mut cmd);
+        cmd.assert().success();
+    };
+    new("idle", &[]);
+    new("dirty", &[]);
+    new("merged", &[]);
+    new("ttl", &["--ttl", "2d"]);
+    new("fresh", &[]);
+
+    // A TTL past chrono's range is rejected before the worktree or branch exist.
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["new", "huge", "--ttl", "100000000000d"]);
+    cmd.assert()
+        .code(2)
+        .stderr(predicate::str::contains("--ttl"));
+    assert!(!td.path().join(".worktrees").join("huge").exists());
+    assert!(git_out(td.path(), &["branch", "--list", "huge"]).is_empty());
+
+    let wt = td.path().join(".worktrees");
+    let commit = |dir: &Path, file: &str, date: Option<&str>| {
+        fs::write(dir.join(file), "y\n").unwrap();
+        git(dir, &["add", file]);
+        let mut c = StdCommand::new("git");
+        c.args([
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "commit",
+            "-m",
+            file,
+        ])
+        .current_dir(dir);
+        if let Some(d) = date {
+            c.env("GIT_AUTHOR_DATE", d).env("GIT_COMMITTER_DATE", d);
+        }
+        assert!(c.status().unwrap().success());
+    };
+    commit(&wt.join("idle"), "idle.txt", Some("2020-01-01T00:00:00Z"));
+    commit(&wt.join("dirty"), "dirty.txt", Some("2020-01-01T00:00:00Z"));
+    fs::write(wt.join("dirty").join("wip.txt"), "wip\n").unwrap();
+    filetime_to_2020(&wt.join("dirty").join("wip.txt"));
+    commit(&wt.join("merged"), "merged.txt", None);
+    git(
+        td.path(),
+        &[
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "user.name=test",
+            "merge",
+            "--no-ff",
+            "-m",
+            "merge",
+            "merged",
+        ],
+    );
+
+    let st_path = td.path().join(".git").join(".wrt").join("state.json");
+    let mut v: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&st_path).unwrap()).unwrap();
+    assert!(v["allocations"]["ttl"]["expires_at"].is_string());
+    for name in ["idle", "dirty"] {
+        v["allocations"][name]["createdAt"] = "2020-01-01T00:00:00Z".into();
+    }
+    v["allocations"]["ttl"]["expires_at"] = "2020-01-01T00:00:00Z".into();
+    fs::write(&st_path, serde_json::to_vec_pretty(&v).unwrap()).unwrap();
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["gc", "--older-than", "7d", "--merged", "--dry-run"]);
+    set_minimal_path(&mut cmd);
+    let out = cmd.assert().success().get_output().stdout.clone();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("would remove"));
+    assert!(out.contains("skipped: uncommitted changes"));
+    assert!(!out.contains("fresh"));
+    assert!(wt.join("idle").exists());
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["gc", "--older-than", "7d", "--merged"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    assert!(!wt.join("idle").exists());
+    assert!(!wt.join("merged").exists());
+    assert!(!wt.join("ttl").exists());
+    assert!(wt.join("dirty").exists());
+    assert!(wt.join("fresh").exists());
+
+    // Only the merged branch is deleted; idle work stays reachable.
+    let branches = git_out(td.path(), &["branch", "--format=%(refname:short)"]);
+    assert!(branches.lines().any(|b| b == "idle"));
+    assert!(!branches.lines().any(|b| b == "merged"));
+
+    let v: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&st_path).unwrap()).unwrap();
+    let names: Vec<&String> = v["allocations"].as_object().unwrap().keys().collect();
+    assert_eq!(names, vec!["dirty", "fresh"]);
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["gc", "--older-than", "7y"]);
+    cmd.assert().code(2);
+}
+
+fn filetime_to_2020(p: &Path) {
+    let status = StdCommand::new("touch")
+        .args(["-d", "2020-01-01T00:00:00"])
+        .arg(p)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn du_breaks_out_node_modules_build_and_hardlinks() {
+    let td = init_repo();
+    for name in ["a1", "a2"] {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path()).args([
+            "new",
+            name,
+            "--install",
+            "false",
+            "--supabase",
+            "false",
+            "--db",
+            "false",
+        ]);
+        set_minimal_path(&mut cmd);
+        cmd.assert().success();
+    }
+    let wt = td.path().join(".worktrees");
+    let nm = wt.join("a1").join("node_modules").join("pkg");
+    fs::create_dir_all(&nm).unwrap();
+    fs::write(nm.join("index.js"), vec![b'x'; 64 * 1024]).unwrap();
+    let dist = wt.join("a1").join("web").join("dist");
+    fs::create_dir_all(&dist).unwrap();
+    fs::write(dist.join("bundle.js"), vec![b'y'; 32 * 1024]).unwrap();
+    let nm2 = wt.join("a2").join("node_modules").join("pkg");
+    fs::create_dir_all(&nm2).unwrap();
+    fs::hard_link(nm.join("index.js"), nm2.join("index.js")).unwrap();
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["du", "--json", "--sort", "build"]);
+    let out = cmd.assert().success().get_output().stdout.clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    let rows = v.as_array().unwrap();
+    assert_eq!(rows[0]["name"], "a1");
+    assert_eq!(rows[1]["name"], "a2");
+
+    let a1 = &rows[0];
+    assert!(a1["node_modules"].as_u64().unwrap() >= 64 * 1024);
+    assert!(a1["build"].as_u64().unwrap() >= 32 * 1024);
+    assert!(a1["checkout"].as_u64().unwrap() > 0);
+    assert!(a1["volumes"].is_null());
+
+    // The hardlinked file is charged to a1 only, but a2 reports it as shared.
+    let a2 = &rows[1];
+    assert_eq!(a2["node_modules"], 0);
+    assert!(a2["shared"].as_u64().unwrap() >= 64 * 1024);
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args(["du"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("NODE_MODULES").and(predicate::str::contains("(total)")));
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args(["du", "--sort", "bogus"]);
+    cmd.assert().code(2);
+}
+
+#[test]
+fn status_reports_ahead_behind_conflicts_and_last_commit() {
+    let td = init_repo();
+    let main = git_out(td.path(), &["symbolic-ref", "--short", "HEAD"])
+        .trim()
+        .to_string();
+    for name in ["feat", "idle"] {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path()).args([
+            "new",
+            name,
+            "--install",
+            "false",
+            "--supabase",
+            "false",
+            "--db",
+            "false",
+        ]);
+        set_minimal_path(&mut cmd);
+        cmd.assert().success();
+    }
+
+    let st_path = td.path().join(".git").join(".wrt").join("state.json");
+    let v: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&st_path).unwrap()).unwrap();
+    assert_eq!(v["allocations"]["feat"]["from_ref"], main.as_str());
+
+    let commit = |dir: &Path, content: &str, msg: &str| {
+        fs::write(dir.join("README.md"), content).unwrap();
+        git(
+            dir,
+            &[
+                "-c",
+                "user.email=test@example.com",
+                "-c",
+                "user.name=test",
+                "commit",
+                "-am",
+                msg,
+            ],
+        );
+    };
+    let feat = td.path().join(".worktrees").join("feat");
+    commit(&feat, "feat\n", "feat: rewrite readme");
+    commit(td.path(), "main\n", "main: rewrite readme");
+    fs::write(feat.join("scratch.txt"), "wip\n").unwrap();
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args(["status", "--json"]);
+    let out = cmd.assert().success().get_output().stdout.clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    let rows = v.as_array().unwrap();
+    let feat = rows.iter().find(|r| r["name"] == "feat").unwrap();
+    assert_eq!(feat["base"], main.as_str());
+    assert_eq!(feat["base_ab"], serde_json::json!([1, 1]));
+    assert_eq!(feat["dirty"], 1);
+    assert_eq!(feat["last_commit_subject"], "feat: rewrite readme");
+    assert_eq!(feat["conflicts"], serde_json::json!(["README.md"]));
+    assert!(feat["upstream"].is_null());
+    assert!(feat["services"].is_null());
+
+    let idle = rows.iter().find(|r| r["name"] == "idle").unwrap();
+    assert_eq!(idle["base_ab"], serde_json::json!([0, 1]));
+    assert_eq!(idle["conflicts"], serde_json::json!([]));
+    assert_eq!(idle["dirty"], 0);
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["status", "--filter", "name=feat"]);
+    cmd.assert().success().stdout(
+        predicate::str::contains("dirty:1")
+            .and(predicate::str::contains("+1/-1"))
+            .and(predicate::str::contains("feat: rewrite readme"))
+            .and(predicate::str::contains("idle").not()),
+    );
+}
+
+#[test]
+fn ui_requires_a_terminal() {
+    let td = init_repo();
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args(["ui"]);
+    cmd.assert()
+        .code(2)
+        .stderr(predicate::str::contains("interactive terminal"));
+}
+
+#[test]
+fn new_count_spawns_grouped_worktrees() {
+    let td = init_repo();
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args([
+        "spawn",
+        "task/login-fix",
+        "--count",
+        "3",
+        "--jobs",
+        "2",
+        "--json",
+        "--install",
+        "false",
+        "--supabase",
+        "false",
+        "--db",
+        "false",
+    ]);
+    set_minimal_path(&mut cmd);
+    let out = cmd.assert().success().get_output().stdout.clone();
+    let rows: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    let rows = rows.as_array().unwrap();
+    assert_eq!(rows.len(), 3);
+
+    let mut blocks = Vec::new();
+    for (i, r) in rows.iter().enumerate() {
+        let name = format!("task-login-fix-{}", i + 1);
+        assert_eq!(r["name"], name.as_str());
+        assert_eq!(r["branch"], format!("task/login-fix-{}", i + 1).as_str());
+        assert_eq!(r["group"], "task-login-fix");
+        assert_eq!(r["ok"], true);
+        assert!(td.path().join(".worktrees").join(&name).exists());
+        blocks.push(r["block"].as_i64().unwrap());
+    }
+    blocks.sort();
+    blocks.dedup();
+    assert_eq!(blocks.len(), 3);
+
+    let st_path = td.path().join(".git").join(".wrt").join("state.json");
+    let v: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&st_path).unwrap()).unwrap();
+    assert_eq!(
+        v["allocations"]["task-login-fix-2"]["group"],
+        "task-login-fix"
+    );
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["ls", "--filter", "group=task-login-fix"]);
+    let out = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).unwrap();
+    let listed = out
+        .lines()
+        .filter(|l| l.starts_with("task-login-fix-"))
+        .count();
+    assert_eq!(listed, 3);
+
+    // A name clash anywhere in the batch aborts before anything is created.
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args([
+        "new",
+        "task/login-fix",
+        "--count",
+        "4",
+        "--install",
+        "false",
+        "--supabase",
+        "false",
+        "--db",
+        "false",
+    ]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().code(2);
+    assert!(!td
+        .path()
+        .join(".worktrees")
+        .join("task-login-fix-4")
+        .exists());
+}
+
+#[test]
+fn compare_and_pick_a_group_winner() {
+    let td = init_repo();
+    let main = git_out(td.path(), &["rev-parse", "--abbrev-ref", "HEAD"]);
+    let main = main.trim().to_string();
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).args([
+        "new",
+        "try",
+        "--count",
+        "2",
+        "--install",
+        "false",
+        "--supabase",
+        "false",
+        "--db",
+        "false",
+    ]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    for (i, body) in [(1, "a\nb\n"), (2, "a\nc\n")] {
+        let wt = td.path().join(".worktrees").join(format!("try-{i}"));
+        fs::write(wt.join("answer.txt"), body).unwrap();
+        if i == 2 {
+            fs::write(wt.join("ok"), "").unwrap();
+        }
+        git(&wt, &["add", "."]);
+        git(
+            &wt,
+            &[
+                "-c",
+                "user.email=test@example.com",
+                "-c",
+                "user.name=test",
+                "commit",
+                "-m",
+                "attempt",
+            ],
+        );
+    }
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["compare", "try", "--check", "test -f ok", "--json"]);
+    set_minimal_path(&mut cmd);
+    let out = cmd.assert().success().get_output().stdout.clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    let wts = v["worktrees"].as_array().unwrap();
+    assert_eq!(wts.len(), 2);
+    assert_eq!(wts[0]["name"], "try-1");
+    assert_eq!(wts[0]["base"], main.as_str());
+    assert_eq!(wts[0]["commits"], 1);
+    assert_eq!(wts[0]["diff"]["files"], 1);
+    assert_eq!(wts[0]["diff"]["insertions"], 2);
+    assert_eq!(wts[0]["check"]["ok"], false);
+    assert_eq!(wts[1]["diff"]["files"], 2);
+    assert_eq!(wts[1]["check"]["ok"], true);
+    // {+a, +b} vs {+a, +c}: one shared line out of three.
+    let score = v["similarity"][0]["score"].as_f64().unwrap();
+    assert!((score - 1.0 / 3.0).abs() < 1e-9);
+
+    // Backups of the losers stay local even with a remote.
+    let origin = TempDir::new().unwrap();
+    git(origin.path(), &["init", "--bare"]);
+    git(
+        td.path(),
+        &["remote", "add", "origin", origin.path().to_str().unwrap()],
+    );
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .env("GIT_AUTHOR_NAME", "test")
+        .env("GIT_AUTHOR_EMAIL", "test@example.com")
+        .env("GIT_COMMITTER_NAME", "test")
+        .env("GIT_COMMITTER_EMAIL", "test@example.com")
+        .args(["pick", "try-2", "--others", "rm"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    assert_eq!(
+        fs::read_to_string(td.path().join("answer.txt")).unwrap(),
+        "a\nc\n"
+    );
+    let subject = git_out(td.path(), &["log", "-1", "--format=%s"]);
+    assert_eq!(subject.trim(), "Merge branch 'try-2'");
+    assert!(!td.path().join(".worktrees").join("try-1").exists());
+    assert!(td.path().join(".worktrees").join("try-2").exists());
+    git_out(
+        td.path(),
+        &["rev-parse", "--verify", "refs/wrt-archive/try-1"],
+    );
+    assert!(!git_out(td.path(), &["ls-remote", "origin"]).contains("wrt-archive"));
+}
+
+#[test]
+fn registry_keeps_blocks_unique_across_repos() {
+    let home = TempDir::new().unwrap();
+    let cfg_dir = home.path().join("config").join("wrt");
+    fs::create_dir_all(&cfg_dir).unwrap();
+    fs::write(cfg_dir.join("config.json"), r#"{"registry": true}"#).unwrap();
+    let with_registry = |cmd: &mut assert_cmd::Command| {
+        cmd.env("XDG_CONFIG_HOME", home.path().join("config"))
+            .env("XDG_STATE_HOME", home.path().join("state"));
+    };
+
+    let front = init_repo();
+    let back = init_repo();
+    for (td, name) in [(&front, "ui"), (&back, "api")] {
+        let mut cmd = wrt_cmd();
+        cmd.current_dir(td.path()).args([
+            "new",
+            name,
+            "--install",
+            "false",
+            "--supabase",
+            "false",
+            "--db",
+            "false",
+        ]);
+        set_minimal_path(&mut cmd);
+        with_registry(&mut cmd);
+        cmd.assert().success();
+    }
+
+    let env_of = |td: &TempDir, name: &str| {
+        fs::read_to_string(td.path().join(".worktrees").join(name).join(".wrt.env")).unwrap()
+    };
+    assert!(env_of(&front, "ui").contains("WRT_PORT_BLOCK=1"));
+    assert!(env_of(&back, "api").contains("WRT_PORT_BLOCK=2"));
+
+    let reg_path = home.path().join("state").join("wrt").join("registry.json");
+    let reg: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&reg_path).unwrap()).unwrap();
+    assert_eq!(reg["repos"].as_object().unwrap().len(), 2);
+
+    // Works outside any repo.
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(home.path()).args(["ls", "--all-repos"]);
+    with_registry(&mut cmd);
+    let out = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).unwrap();
+    assert!(out.contains(&front.path().display().to_string()));
+    assert!(out.lines().any(|l| l.starts_with("  ui ")));
+    assert!(out.lines().any(|l| l.starts_with("  api ")));
+
+    // Removing a worktree releases its block machine-wide.
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(front.path()).args(["rm", "ui", "--force"]);
+    with_registry(&mut cmd);
+    cmd.assert().success();
+    let reg: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&reg_path).unwrap()).unwrap();
+    assert_eq!(reg["repos"].as_object().unwrap().len(), 1);
+}
+
+#[test]
+fn proxy_routes_worktree_hostnames_to_offset_ports() {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    struct Kill(std::process::Child);
+    impl Drop for Kill {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+    let free_port = || {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    };
+
+    let td = init_repo();
+    // The first worktree gets offset 100: its web service listens on base_port + 100.
+    let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_port = upstream.local_addr().unwrap().port() - 100;
+    fs::write(
+        td.path().join(".wrt.json"),
+        format!(
+            r#"{{"version":1,"port_block_size":100,"package_manager":{{"name":"npm","install_command":["npm","install"]}},"services":[{{"name":"web","dev_command":["npm","run","dev"],"base_port":{base_port}}}],"supabase":{{"detected":false}}}}"#
+        ),
+    )
+    .unwrap();
+
+    let port = free_port();
+    let cfg_dir = td.path().join(".git").join(".wrt");
+    fs::create_dir_all(&cfg_dir).unwrap();
+    fs::write(
+        cfg_dir.join("config.json"),
+        format!(r#"{{"proxy_port": {port}}}"#),
+    )
+    .unwrap();
+
+    // Started before the worktree exists: the route must show up without a restart.
+    let _proxy = Kill(
+        StdCommand::new(assert_cmd::cargo::cargo_bin!("wrt"))
+            .current_dir(td.path())
+            .args(["proxy"])
+            .spawn()
+            .unwrap(),
+    );
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path())
+        .args(["new", "x", "--install", "false", "--supabase", "false"]);
+    set_minimal_path(&mut cmd);
+    cmd.assert().success();
+
+    let env = fs::read_to_string(td.path().join(".worktrees/x/.wrt.env")).unwrap();
+    assert!(env.contains(&format!("WRT_URL_WEB=http://x.web.localhost:{port}")));
+
+    let server = std::thread::spawn(move || {
+        let (mut conn, _) = upstream.accept().unwrap();
+        let mut buf = [0u8; 4096];
+        let n = conn.read(&mut buf).unwrap();
+        let req = String::from_utf8_lossy(&buf[..n]).to_string();
+        conn.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello")
+            .unwrap();
+        req
+    });
+
+    let get = |host: &str| -> String {
+        let mut conn = (0..50)
+            .find_map(|_| {
+                TcpStream::connect(("127.0.0.1", port)).ok().or_else(|| {
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                    None
+                })
+            })
+            .expect("proxy is listening");
+        write!(
+            conn,
+            "GET /ping HTTP/1.1\r\nHost: {host}:{port}\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut resp = String::new();
+        conn.read_to_string(&mut resp).unwrap();
+        resp
+    };
+
+    let resp = get("x.web.localhost");
+    assert!(resp.starts_with("HTTP/1.1 200"), "{resp}");
+    assert!(resp.ends_with("hello"));
+    assert!(server.join().unwrap().starts_with("GET /ping HTTP/1.1"));
+
+    let resp = get("nope.web.localhost");
+    assert!(resp.starts_with("HTTP/1.1 404"), "{resp}");
+    assert!(resp.contains("x.web.localhost"));
+}
+
+#[test]
+fn mcp_tools_create_run_and_remove_worktrees() {
+    let td = init_repo();
+    let cfg_dir = td.path().join(".git").join(".wrt");
+    fs::create_dir_all(&cfg_dir).unwrap();
+    fs::write(cfg_dir.join("config.json"), r#"{"mcp_max_worktrees": 1}"#).unwrap();
+
+    let call = |id: u32, tool: &str, args: serde_json::Value| {
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "tools/call",
+            "params": { "name": tool, "arguments": args },
+        })
+        .to_string()
+    };
+    let no_setup = serde_json::json!({ "install": "false", "supabase": "false", "db": "false" });
+    let mut create_x = no_setup.clone();
+    create_x["name"] = "x".into();
+    let mut create_y = no_setup;
+    create_y["name"] = "y".into();
+    let input = [
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-06-18","clientInfo":{"name":"agent-7"}}}"#.to_string(),
+        r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#.to_string(),
+        r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#.to_string(),
+        call(3, "create", create_x),
+        call(4, "create", create_y),
+        call(5, "list", serde_json::json!({ "filter": ["agent=agent-7"] })),
+        call(6, "run", serde_json::json!({ "name": "x", "command": ["sh", "-c", "echo $WRT_NAME; touch wip; exit 3"] })),
+        call(7, "env", serde_json::json!({ "name": "x" })),
+        call(8, "remove", serde_json::json!({ "name": "x" })),
+        call(9, "remove", serde_json::json!({ "name": "x", "backup": true })),
+    ]
+    .join("\n")
+        + "\n";
+
+    let mut cmd = wrt_cmd();
+    cmd.current_dir(td.path()).arg("mcp").write_stdin(input);
+    set_minimal_path(&mut cmd);
+    let out = cmd.assert().success().get_output().stdout.clone();
+    let replies: Vec<serde_json::Value> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(replies.len(), 9);
+    let result = |id: u64| {
+        let r = replies.iter().find(|r| r["id"] == id).unwrap();
+        r["result"].clone()
+    };
+
+    let tools: Vec<String> = result(2)["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["name"].as_str().unwrap().to_string())
+        .collect();
+    for t in [
+        "create", "list", "env", "run", "db_reset", "remove", "status",
+    ] {
+        assert!(tools.contains(&t.to_string()), "{t}");
+    }
+
+    let created = result(3);
+    assert_eq!(created["isError"], false, "{created}");
+    assert_eq!(created["structuredContent"]["worktree"]["agent"], "agent-7");
+    assert_eq!(
+        created["structuredContent"]["env"]["WRT_PORT_OFFSET"],
+        "100"
+    );
+
+    let refused = result(4);
+    assert_eq!(refused["isError"], true);
+    assert_eq!(refused["structuredContent"]["exit_code"], 2);
+    assert!(refused["structuredContent"]["log"]
+        .to_string()
+        .contains("limit reached: 1 worktrees exist"));
+
+    let listed = result(5);
+    assert_eq!(listed["structuredContent"]["worktrees"][0]["name"], "x");
+
+    let ran = result(6)["structuredContent"].clone();
+    assert_eq!(ran["exit_code"], 3);
+    assert_eq!(ran["stdout"], "x\n");
+
+    assert_eq!(result(7)["structuredContent"]["env"]["WRT_NAME"], "x");
+
+    let kept = result(8);
+    assert_eq!(kept["isError"], true);
+    assert_eq!(
+        kept["structuredContent"]["at_risk"],
+        serde_json::json!(["uncommitted: ?? wip"])
+    );
+
+    assert_eq!(result(9)["isError"], false, "{}", result(9));
+    assert!(!td.path().join(".worktrees").join("x").exists());
+}
@@ -0,0 +1,114 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+
+use wrt::{CreateOpts, Error, Level, Mode, RemoveOpts, Wrt};
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(["-c", "user.email=test@example.com", "-c", "user.name=test"])
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .expect("run git");
+    assert!(status.success());
+}
+
+fn init_repo() -> TempDir {
+    let td = TempDir::new().unwrap();
+    git(td.path(), &["init"]);
+    fs::write(td.path().join("README.md"), "x\n").unwrap();
+    git(td.path(), &["add", "."]);
+    git(td.path(), &["commit", "-m", "init"]);
+    td
+}
+
+fn opts(name: &str) -> CreateOpts {
+    CreateOpts {
+        name: name.into(),
+        install: Some(Mode::Never),
+        supabase: Some(Mode::Never),
+        db: Some(Mode::Never),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn create_list_env_and_remove() {
+    let td = init_repo();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&events);
+    let mut wrt = Wrt::open(td.path())
+        .unwrap()
+        .with_sink(move |ev| seen.lock().unwrap().push(ev.clone()));
+
+    let mut o = opts("Agent 4");
+    o.labels.insert("team".into(), "web".into());
+    let a = wrt.create(&o).unwrap();
+    assert_eq!(a.name, "agent-4");
+    assert_eq!(a.offset, 100);
+    assert_eq!(a.labels["team"], "web");
+    assert!(Path::new(&a.path).join(".wrt.env").exists());
+    assert!(events
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|e| e.level == Level::Info && e.message.starts_with("creating worktree")));
+
+    assert!(matches!(wrt.create(&o), Err(Error::AlreadyExists(n)) if n == "agent-4"));
+    let mut bad = opts("b");
+    bad.labels.insert("".into(), "x".into());
+    assert!(matches!(wrt.create(&bad), Err(Error::Invalid(_))));
+
+    let names: Vec<String> = wrt.list().unwrap().into_iter().map(|a| a.name).collect();
+    assert_eq!(names, ["agent-4"]);
+    let env = wrt.env_for("agent-4").unwrap();
+    assert!(env.contains(&("WRT_PORT_BLOCK".to_string(), "1".to_string())));
+    assert!(matches!(
+        wrt.env_for("nope"),
+        Err(Error::UnknownWorktree(_))
+    ));
+
+    fs::write(Path::new(&a.path).join("wip.txt"), "x\n").unwrap();
+    match wrt.remove("agent-4", &RemoveOpts::default()) {
+        Err(Error::WouldLoseWork { at_risk, .. }) => assert!(!at_risk.is_empty()),
+        other => panic!("expected WouldLoseWork, got {other:?}"),
+    }
+    let force = RemoveOpts {
+        force: true,
+        delete_branch: true,
+        ..Default::default()
+    };
+    wrt.remove("agent-4", &force).unwrap();
+    assert!(wrt.list().unwrap().is_empty());
+    assert!(!Path::new(&a.path).exists());
+}
+
+#[test]
+fn open_outside_a_repo_is_not_a_repo() {
+    let td = TempDir::new().unwrap();
+    assert!(matches!(Wrt::open(td.path()), Err(Error::NotARepo(_))));
+}
+
+#[test]
+fn create_reports_typed_errors() {
+    let td = init_repo();
+    let mut wrt = Wrt::open(td.path()).unwrap();
+
+    let mut o = opts("p");
+    o.profile = Some("nope".into());
+    assert!(matches!(wrt.create(&o), Err(Error::UnknownProfile(p)) if p == "nope"));
+
+    // The main workdir has this branch checked out, so `git worktree add` refuses it.
+    let head = Command::new("git")
+        .args(["rev-parse", "--abbrev-ref", "HEAD"])
+        .current_dir(td.path())
+        .output()
+        .unwrap();
+    let mut o = opts("g");
+    o.branch = Some(String::from_utf8(head.stdout).unwrap().trim().into());
+    assert!(matches!(wrt.create(&o), Err(Error::Git(_))));
+    assert!(wrt.list().unwrap().is_empty());
+}